@@ -0,0 +1,869 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+use crate::api;
+use crate::auth::{self, Scopes};
+use crate::config::{Config, ConfigError, RuleConfig};
+use crate::history;
+use crate::monitor::collector;
+use crate::monitor::snapshot::Snapshot;
+use crate::utils::{serialize_optional_timestamp, serialize_timestamp};
+
+/// How long resolved alerts stay visible before they are forgotten.
+const RESOLVED_RETENTION: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operator {
+	Greater,
+	GreaterOrEqual,
+	Less,
+	LessOrEqual,
+	Equal,
+	NotEqual,
+	Contains,
+}
+
+/// Operators in the order they are looked for, so `>=` wins over `>`.
+const OPERATORS: [(&str, Operator); 7] = [
+	(">=", Operator::GreaterOrEqual),
+	("<=", Operator::LessOrEqual),
+	("==", Operator::Equal),
+	("!=", Operator::NotEqual),
+	(">", Operator::Greater),
+	("<", Operator::Less),
+	(" contains ", Operator::Contains),
+];
+
+#[derive(Clone, Debug)]
+enum Operand {
+	Number(f64),
+	Text(String),
+	/// Another field of the same entry, e.g. `critical` for components
+	Field(Vec<String>),
+}
+
+/// A parsed alert rule. The expression compares a field of a subsystem, as returned by
+/// `/api/v1/{subsystem}`, with a number, a quoted string or another field of the same entry:
+///
+/// - `storage.percent{mount="/"} > 90`
+/// - `ups.status contains "OB"`
+/// - `components.temperature > critical`
+///
+/// Subsystems with entries are evaluated for every entry the optional `{...}` filter selects.
+#[derive(Clone, Debug)]
+pub struct Rule {
+	name: String,
+	subsystem: String,
+	path: Vec<String>,
+	filters: HashMap<String, String>,
+	operator: Operator,
+	operand: Operand,
+	clear: Option<f64>,
+	hold: Duration,
+	severity: Option<String>,
+	description: Option<String>,
+}
+
+impl Rule {
+	/// Parses a rule, reporting problems under `key`.
+	pub fn parse(key: &str, config: &RuleConfig) -> Result<Self, ConfigError> {
+		let expr = parse_expr(&config.expr).map_err(|message| ConfigError::Invalid {
+			key: format!("{}.expr", key),
+			message,
+		})?;
+
+		let hold = match &config.hold {
+			Some(value) => history::parse_duration(value).ok_or_else(|| ConfigError::Invalid {
+				key: format!("{}.for", key),
+				message: String::from("expected a duration such as 90s, 5m or 1h"),
+			})?,
+			None => Duration::ZERO,
+		};
+
+		if let Some(clear) = config.clear {
+			let threshold = match expr.operand {
+				Operand::Number(threshold) => threshold,
+				_ => {
+					return Err(ConfigError::Invalid {
+						key: format!("{}.clear", key),
+						message: String::from("requires a numeric threshold"),
+					})
+				}
+			};
+
+			let valid = match expr.operator {
+				Operator::Greater | Operator::GreaterOrEqual => clear <= threshold,
+				Operator::Less | Operator::LessOrEqual => clear >= threshold,
+				_ => false,
+			};
+			if !valid {
+				return Err(ConfigError::Invalid {
+					key: format!("{}.clear", key),
+					message: format!(
+						"must be on the safe side of the threshold {} of a <, <=, > or >= comparison",
+						threshold
+					),
+				});
+			}
+		}
+
+		Ok(Rule {
+			name: config.name.clone(),
+			subsystem: expr.subsystem,
+			path: expr.path,
+			filters: expr.filters,
+			operator: expr.operator,
+			operand: expr.operand,
+			clear: config.clear,
+			hold,
+			severity: config.severity.clone(),
+			description: config.description.clone(),
+		})
+	}
+
+	/// Whether `entry` matches the rule. A firing alert is compared against `clear`
+	/// instead of the threshold, so it does not flap around the threshold.
+	fn matches(&self, entry: &Value, firing: bool) -> bool {
+		let value = lookup(entry, &self.path);
+		let operand = match (&self.operand, self.clear) {
+			(Operand::Number(_), Some(clear)) if firing => Value::from(clear),
+			(Operand::Number(threshold), _) => Value::from(*threshold),
+			(Operand::Text(text), _) => Value::from(text.as_str()),
+			(Operand::Field(path), _) => lookup(entry, path).clone(),
+		};
+
+		compare(value, self.operator, &operand)
+	}
+}
+
+/// The parts of a rule expression.
+struct Expr {
+	subsystem: String,
+	path: Vec<String>,
+	filters: HashMap<String, String>,
+	operator: Operator,
+	operand: Operand,
+}
+
+fn parse_expr(expr: &str) -> Result<Expr, String> {
+	let (left, operator, right) = split_operator(expr)?;
+
+	// Left side: subsystem.field[.field...]{key="value",...}
+	let left = left.trim();
+	let (path, filters) = match left.find('{') {
+		Some(start) => {
+			let Some(selector) = left[start + 1..].strip_suffix('}') else {
+				return Err(String::from("unterminated {...} filter"));
+			};
+			(&left[..start], parse_filters(selector)?)
+		}
+		None => (left, HashMap::new()),
+	};
+
+	let mut path: Vec<String> = path.split('.').map(|s| s.trim().to_string()).collect();
+	let subsystem = path.remove(0);
+	if !api::subsystems().contains(&subsystem.as_str()) {
+		return Err(format!(
+			"unknown subsystem `{}`, expected one of {}",
+			subsystem,
+			api::subsystems().join(", ")
+		));
+	}
+	if path.is_empty() || path.iter().any(|segment| segment.is_empty()) {
+		return Err(format!(
+			"expected a field after the subsystem, e.g. `{}.percent`",
+			subsystem
+		));
+	}
+	for key in filters.keys() {
+		if !api::filters(&subsystem).contains(&key.as_str()) {
+			return Err(format!("{} can not be filtered by `{}`", subsystem, key));
+		}
+	}
+
+	// Right side: a number, a quoted string or a field of the same entry
+	let right = right.trim();
+	let operand = if let Ok(number) = right.parse::<f64>() {
+		Operand::Number(number)
+	} else if right.len() >= 2 && right.starts_with('"') && right.ends_with('"') {
+		Operand::Text(right[1..right.len() - 1].to_string())
+	} else if !right.is_empty()
+		&& right
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+	{
+		Operand::Field(right.split('.').map(String::from).collect())
+	} else {
+		return Err(format!(
+			"expected a number, a quoted string or a field after the operator, got `{}`",
+			right
+		));
+	};
+
+	match (operator, &operand) {
+		(Operator::Contains, Operand::Number(_) | Operand::Field(_)) => {
+			return Err(String::from("contains expects a quoted string"))
+		}
+		(
+			Operator::Greater | Operator::GreaterOrEqual | Operator::Less | Operator::LessOrEqual,
+			Operand::Text(_),
+		) => return Err(String::from("<, <=, > and >= expect a number or a field")),
+		_ => {}
+	}
+
+	Ok(Expr {
+		subsystem,
+		path,
+		filters,
+		operator,
+		operand,
+	})
+}
+
+/// Splits an expression at the first operator outside of quotes and braces.
+fn split_operator(expr: &str) -> Result<(&str, Operator, &str), String> {
+	let mut quoted = false;
+	let mut depth = 0;
+
+	for (i, c) in expr.char_indices() {
+		match c {
+			'"' => quoted = !quoted,
+			'{' if !quoted => depth += 1,
+			'}' if !quoted => depth -= 1,
+			_ if !quoted && depth == 0 => {
+				for (token, operator) in OPERATORS {
+					if expr[i..].starts_with(token) {
+						return Ok((&expr[..i], operator, &expr[i + token.len()..]));
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+
+	Err(String::from(
+		"expected a comparison with >, >=, <, <=, ==, != or contains",
+	))
+}
+
+fn parse_filters(selector: &str) -> Result<HashMap<String, String>, String> {
+	let mut filters = HashMap::new();
+
+	for filter in selector.split(',').filter(|f| !f.trim().is_empty()) {
+		let Some((key, value)) = filter.split_once('=') else {
+			return Err(format!(
+				"expected key=\"value\" in the filter, got `{}`",
+				filter
+			));
+		};
+		let value = value.trim();
+		let value = value
+			.strip_prefix('"')
+			.and_then(|v| v.strip_suffix('"'))
+			.unwrap_or(value);
+		filters.insert(key.trim().to_string(), value.to_string());
+	}
+
+	Ok(filters)
+}
+
+fn lookup<'a>(entry: &'a Value, path: &[String]) -> &'a Value {
+	let mut value = entry;
+	for segment in path {
+		value = match value.get(segment) {
+			Some(value) => value,
+			None => return &Value::Null,
+		};
+	}
+	value
+}
+
+/// Compares two JSON values. Numbers (and booleans as 0 / 1) are compared numerically,
+/// strings only for equality and containment; anything else, like a missing value, never matches.
+fn compare(value: &Value, operator: Operator, operand: &Value) -> bool {
+	let number = |value: &Value| match value {
+		Value::Number(n) => n.as_f64(),
+		Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+		_ => None,
+	};
+
+	if let (Some(a), Some(b)) = (number(value), number(operand)) {
+		return match operator {
+			Operator::Greater => a > b,
+			Operator::GreaterOrEqual => a >= b,
+			Operator::Less => a < b,
+			Operator::LessOrEqual => a <= b,
+			Operator::Equal => a == b,
+			Operator::NotEqual => a != b,
+			Operator::Contains => false,
+		};
+	}
+
+	if let (Value::String(a), Value::String(b)) = (value, operand) {
+		return match operator {
+			Operator::Equal => a == b,
+			Operator::NotEqual => a != b,
+			Operator::Contains => a.contains(b.as_str()),
+			_ => false,
+		};
+	}
+
+	false
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+	Pending,
+	Firing,
+	Resolved,
+}
+
+impl fmt::Display for AlertState {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AlertState::Pending => write!(f, "pending"),
+			AlertState::Firing => write!(f, "firing"),
+			AlertState::Resolved => write!(f, "resolved"),
+		}
+	}
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Alert {
+	pub rule: String,
+	/// Entry of the subsystem, e.g. the device of a storage rule
+	pub entry: Option<String>,
+	pub state: AlertState,
+	pub severity: Option<String>,
+	pub description: Option<String>,
+	/// Value of the field when the rule was last evaluated
+	pub value: Value,
+	/// When the condition started to hold
+	#[serde(serialize_with = "serialize_timestamp")]
+	pub active_since: Duration,
+	#[serde(serialize_with = "serialize_optional_timestamp")]
+	pub fired_at: Option<Duration>,
+	#[serde(serialize_with = "serialize_optional_timestamp")]
+	pub resolved_at: Option<Duration>,
+	#[serde(skip)]
+	pub subsystem: String,
+	/// State of the entry (or the whole subsystem) when the rule was last evaluated
+	#[serde(skip)]
+	pub details: Value,
+	/// Names of the entry used to apply token scopes
+	#[serde(skip)]
+	identifiers: Vec<String>,
+}
+
+#[cfg(test)]
+impl Alert {
+	/// An alert of `rule` without details, for the tests of the notifiers.
+	pub fn for_test(rule: &str, entry: Option<&str>, state: AlertState) -> Self {
+		Alert {
+			rule: rule.to_string(),
+			entry: entry.map(str::to_string),
+			state,
+			severity: None,
+			description: None,
+			value: Value::Null,
+			active_since: Duration::from_secs(1_700_000_000),
+			fired_at: None,
+			resolved_at: None,
+			subsystem: String::new(),
+			details: Value::Null,
+			identifiers: Vec::new(),
+		}
+	}
+}
+
+/// Evaluates the alert rules after every refresh and keeps the state of their alerts.
+pub struct Alerts {
+	rules: Vec<Rule>,
+	alerts: BTreeMap<(String, String), Alert>,
+}
+
+impl Alerts {
+	pub fn new(rules: Vec<Rule>) -> Self {
+		Alerts {
+			rules,
+			alerts: BTreeMap::new(),
+		}
+	}
+
+	/// Parses the rules of the configuration and its `rules_file`.
+	pub fn load_rules(config: &Config) -> Result<Vec<Rule>, ConfigError> {
+		config
+			.load_rules()?
+			.iter()
+			.map(|(key, rule)| Rule::parse(key, rule))
+			.collect()
+	}
+
+	/// Replaces the rules. Alerts of rules that still exist keep their state.
+	pub fn set_rules(&mut self, rules: Vec<Rule>) {
+		self
+			.alerts
+			.retain(|(name, _), _| rules.iter().any(|rule| &rule.name == name));
+		self.rules = rules;
+	}
+
+	pub fn rule_count(&self) -> usize {
+		self.rules.len()
+	}
+
+	/// Evaluates every rule against the latest snapshot of the monitor and returns
+	/// the alerts that started firing or resolved.
+	pub fn evaluate(&mut self, snapshot: &Snapshot, now: Duration) -> Vec<Alert> {
+		let scopes = Scopes::all();
+		let values: Vec<Option<Value>> = self
+			.rules
+			.iter()
+			.map(
+				|rule| match api::subsystem(snapshot, &rule.subsystem, &rule.filters, &scopes) {
+					Ok(value) => Some(value),
+					Err(e) => {
+						eprintln!("Failed to evaluate alert rule {}: {}", rule.name, e);
+						None
+					}
+				},
+			)
+			.collect();
+
+		self.update(&values, now)
+	}
+
+	/// Evaluates every rule against the state of its subsystem, as returned by
+	/// `/api/v1/{subsystem}`, and returns the alerts that started firing or resolved.
+	/// Rules whose subsystem could not be read are skipped.
+	fn update(&mut self, values: &[Option<Value>], now: Duration) -> Vec<Alert> {
+		let mut changed = Vec::new();
+
+		for (rule, value) in self.rules.iter().zip(values) {
+			let Some(value) = value else {
+				continue;
+			};
+
+			// Subsystems with entries are evaluated per entry, the others as a whole
+			let entries: Vec<(Option<String>, &Value)> = if auth::entry_labels(&rule.subsystem).is_empty()
+			{
+				vec![(None, value)]
+			} else {
+				match value {
+					Value::Object(map) => map
+						.iter()
+						.map(|(name, entry)| (Some(name.clone()), entry))
+						.collect(),
+					_ => Vec::new(),
+				}
+			};
+
+			let mut seen = Vec::new();
+			for (entry, object) in entries {
+				let key = (rule.name.clone(), entry.clone().unwrap_or_default());
+				seen.push(key.1.clone());
+
+				let firing = self
+					.alerts
+					.get(&key)
+					.is_some_and(|alert| alert.state == AlertState::Firing);
+				let matches = rule.matches(object, firing);
+
+				if let Some(alert) = self.alerts.get_mut(&key) {
+					alert.value = lookup(object, &rule.path).clone();
+					alert.details = object.clone();
+				}
+
+				match (self.alerts.get(&key).map(|alert| alert.state), matches) {
+					(None | Some(AlertState::Resolved), true) => {
+						let alert = Alert {
+							rule: rule.name.clone(),
+							entry: entry.clone(),
+							state: AlertState::Pending,
+							severity: rule.severity.clone(),
+							description: rule.description.clone(),
+							value: lookup(object, &rule.path).clone(),
+							active_since: now,
+							fired_at: None,
+							resolved_at: None,
+							subsystem: rule.subsystem.clone(),
+							details: object.clone(),
+							identifiers: identifiers(&rule.subsystem, entry.as_deref(), object),
+						};
+						self.alerts.insert(key.clone(), alert);
+					}
+					(Some(AlertState::Pending), false) => {
+						self.alerts.remove(&key);
+					}
+					(Some(AlertState::Firing), false) => {
+						let alert = self.alerts.get_mut(&key).unwrap();
+						alert.state = AlertState::Resolved;
+						alert.resolved_at = Some(now);
+						changed.push(alert.clone());
+					}
+					_ => {}
+				}
+
+				if let Some(alert) = self.alerts.get_mut(&key) {
+					if alert.state == AlertState::Pending
+						&& now.saturating_sub(alert.active_since) >= rule.hold
+					{
+						alert.state = AlertState::Firing;
+						alert.fired_at = Some(now);
+						changed.push(alert.clone());
+					}
+				}
+			}
+
+			// Entries that disappeared, e.g. a stopped container, no longer match
+			for ((name, entry), alert) in self.alerts.iter_mut() {
+				if name != &rule.name || seen.contains(entry) {
+					continue;
+				}
+				if alert.state == AlertState::Firing {
+					alert.state = AlertState::Resolved;
+					alert.resolved_at = Some(now);
+					alert.value = Value::Null;
+					changed.push(alert.clone());
+				}
+			}
+			self.alerts.retain(|(name, entry), alert| {
+				name != &rule.name || seen.contains(entry) || alert.state != AlertState::Pending
+			});
+		}
+
+		self.alerts.retain(|_, alert| {
+			alert
+				.resolved_at
+				.is_none_or(|resolved| now.saturating_sub(resolved) < RESOLVED_RETENTION)
+		});
+
+		changed
+	}
+
+	/// Returns the alerts `scopes` grants access to, optionally only those in `state`.
+	pub fn list(&self, scopes: &Scopes, state: Option<AlertState>) -> Vec<Alert> {
+		self
+			.alerts
+			.values()
+			.filter(|alert| state.is_none_or(|state| alert.state == state))
+			.filter(|alert| {
+				let names: Vec<&str> = alert.identifiers.iter().map(|n| n.as_str()).collect();
+				match &alert.entry {
+					Some(_) => scopes.allows_entry(&alert.subsystem, &names),
+					None => scopes.allows(&alert.subsystem),
+				}
+			})
+			.cloned()
+			.collect()
+	}
+}
+
+/// Names an entry can be granted by in a scope, matching the filters of `/api/v1/{subsystem}`.
+fn identifiers(subsystem: &str, entry: Option<&str>, object: &Value) -> Vec<String> {
+	let Some(entry) = entry else {
+		return Vec::new();
+	};
+	let fields = match collector::subsystem(subsystem) {
+		Some(subsystem) => subsystem.entry_fields,
+		None => &[None],
+	};
+
+	fields
+		.iter()
+		.map(|field| match field.map(|name| object.get(name)) {
+			None => entry.to_string(),
+			Some(Some(Value::String(s))) => s.clone(),
+			Some(Some(value)) => value.to_string(),
+			Some(None) => String::new(),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn rule_config(expr: &str) -> RuleConfig {
+		RuleConfig {
+			name: String::from("test"),
+			expr: expr.to_string(),
+			hold: None,
+			clear: None,
+			severity: None,
+			description: None,
+		}
+	}
+
+	fn parse_error(config: &RuleConfig) -> String {
+		match Rule::parse("rules.test", config) {
+			Ok(_) => panic!("{} should not parse", config.expr),
+			Err(ConfigError::Invalid { message, .. }) => message,
+			Err(e) => panic!("unexpected error: {:?}", e),
+		}
+	}
+
+	fn seconds(secs: u64) -> Duration {
+		Duration::from_secs(secs)
+	}
+
+	#[test]
+	fn parses_expressions() {
+		let rule = Rule::parse(
+			"rules.test",
+			&rule_config(r#"storage.percent{mount="/"} >= 90"#),
+		)
+		.unwrap();
+		assert_eq!(rule.subsystem, "storage");
+		assert_eq!(rule.path, vec!["percent"]);
+		assert_eq!(rule.filters.get("mount").map(String::as_str), Some("/"));
+		assert_eq!(rule.operator, Operator::GreaterOrEqual);
+		assert!(matches!(rule.operand, Operand::Number(n) if n == 90.0));
+
+		let rule = Rule::parse("rules.test", &rule_config(r#"ups.status contains "OB""#)).unwrap();
+		assert_eq!(rule.operator, Operator::Contains);
+		assert!(matches!(&rule.operand, Operand::Text(text) if text == "OB"));
+
+		let rule = Rule::parse(
+			"rules.test",
+			&rule_config("components.temperature > critical"),
+		)
+		.unwrap();
+		assert!(matches!(&rule.operand, Operand::Field(path) if path == &["critical"]));
+	}
+
+	#[test]
+	fn operators_inside_quotes_and_filters_are_skipped() {
+		let rule = Rule::parse(
+			"rules.test",
+			&rule_config(r#"ups.status{name="a>b"} == "x<y""#),
+		)
+		.unwrap();
+		assert_eq!(rule.filters.get("name").map(String::as_str), Some("a>b"));
+		assert_eq!(rule.operator, Operator::Equal);
+		assert!(matches!(&rule.operand, Operand::Text(text) if text == "x<y"));
+	}
+
+	#[test]
+	fn rejects_invalid_expressions() {
+		assert!(
+			parse_error(&rule_config("disks.percent > 90")).starts_with("unknown subsystem `disks`")
+		);
+		assert!(parse_error(&rule_config("storage > 90")).starts_with("expected a field"));
+		assert!(parse_error(&rule_config("storage.percent 90")).starts_with("expected a comparison"));
+		assert_eq!(
+			parse_error(&rule_config(r#"storage.percent{mount="/"}.used > 90"#)),
+			"unterminated {...} filter"
+		);
+		assert_eq!(
+			parse_error(&rule_config(r#"storage.percent{pid="1"} > 90"#)),
+			"storage can not be filtered by `pid`"
+		);
+		assert!(parse_error(&rule_config("storage.percent > 9 0")).starts_with("expected a number"));
+	}
+
+	#[test]
+	fn rejects_operands_the_operator_can_not_compare() {
+		assert_eq!(
+			parse_error(&rule_config("ups.status contains 1")),
+			"contains expects a quoted string"
+		);
+		assert_eq!(
+			parse_error(&rule_config("ups.status contains OB")),
+			"contains expects a quoted string"
+		);
+		assert_eq!(
+			parse_error(&rule_config(r#"storage.percent > "90""#)),
+			"<, <=, > and >= expect a number or a field"
+		);
+	}
+
+	#[test]
+	fn validates_clear() {
+		let mut config = rule_config("storage.percent > 90");
+		config.clear = Some(85.0);
+		assert!(Rule::parse("rules.test", &config).is_ok());
+
+		config.clear = Some(95.0);
+		assert!(parse_error(&config).starts_with("must be on the safe side"));
+
+		let mut config = rule_config("storage.percent > critical");
+		config.clear = Some(85.0);
+		assert_eq!(parse_error(&config), "requires a numeric threshold");
+	}
+
+	#[test]
+	fn compares_values() {
+		assert!(compare(&json!(95), Operator::Greater, &json!(90.0)));
+		assert!(!compare(&json!(90), Operator::Greater, &json!(90.0)));
+		assert!(compare(&json!(true), Operator::Equal, &json!(1.0)));
+		assert!(compare(&json!("OB LB"), Operator::Contains, &json!("LB")));
+		assert!(compare(&json!("OL"), Operator::NotEqual, &json!("OB")));
+		assert!(!compare(&json!("OL"), Operator::Greater, &json!("OB")));
+		assert!(!compare(&Value::Null, Operator::Less, &json!(10.0)));
+		assert!(!compare(&json!(12), Operator::Contains, &json!(1.0)));
+	}
+
+	#[test]
+	fn fires_after_the_hold_time() {
+		let mut config = rule_config("storage.percent > 90");
+		config.hold = Some(String::from("1m"));
+		let mut alerts = Alerts::new(vec![Rule::parse("rules.test", &config).unwrap()]);
+		let full = [Some(json!({"sda1": {"percent": 95, "mount_point": "/"}}))];
+
+		assert!(alerts.update(&full, seconds(0)).is_empty());
+		assert_eq!(
+			alerts.list(&Scopes::all(), Some(AlertState::Pending)).len(),
+			1
+		);
+		assert!(alerts.update(&full, seconds(30)).is_empty());
+
+		let changed = alerts.update(&full, seconds(60));
+		assert_eq!(changed.len(), 1);
+		assert_eq!(changed[0].state, AlertState::Firing);
+		assert_eq!(changed[0].entry.as_deref(), Some("sda1"));
+		assert_eq!(changed[0].value, json!(95));
+		assert_eq!(changed[0].active_since, seconds(0));
+		assert_eq!(changed[0].fired_at, Some(seconds(60)));
+	}
+
+	#[test]
+	fn pending_alerts_are_dropped_when_the_condition_stops() {
+		let mut config = rule_config("storage.percent > 90");
+		config.hold = Some(String::from("1m"));
+		let mut alerts = Alerts::new(vec![Rule::parse("rules.test", &config).unwrap()]);
+
+		alerts.update(&[Some(json!({"sda1": {"percent": 95}}))], seconds(0));
+		assert!(alerts
+			.update(&[Some(json!({"sda1": {"percent": 50}}))], seconds(10))
+			.is_empty());
+		assert!(alerts.list(&Scopes::all(), None).is_empty());
+	}
+
+	#[test]
+	fn resolves_below_clear() {
+		let mut config = rule_config("storage.percent > 90");
+		config.clear = Some(80.0);
+		let mut alerts = Alerts::new(vec![Rule::parse("rules.test", &config).unwrap()]);
+		let percent = |percent: u64| [Some(json!({"sda1": {"percent": percent}}))];
+
+		assert_eq!(
+			alerts.update(&percent(95), seconds(0))[0].state,
+			AlertState::Firing
+		);
+		// Between the threshold and clear the alert keeps firing
+		assert!(alerts.update(&percent(85), seconds(3)).is_empty());
+
+		let changed = alerts.update(&percent(75), seconds(6));
+		assert_eq!(changed.len(), 1);
+		assert_eq!(changed[0].state, AlertState::Resolved);
+		assert_eq!(changed[0].resolved_at, Some(seconds(6)));
+
+		// Resolved alerts are forgotten after a while
+		alerts.update(&percent(75), seconds(6) + RESOLVED_RETENTION);
+		assert!(alerts.list(&Scopes::all(), None).is_empty());
+	}
+
+	#[test]
+	fn resolves_when_the_entry_disappears() {
+		let mut alerts = Alerts::new(vec![Rule::parse(
+			"rules.test",
+			&rule_config("containers.cpu > 90"),
+		)
+		.unwrap()]);
+
+		alerts.update(
+			&[Some(json!({"web": {"cpu": 99}, "db": {"cpu": 10}}))],
+			seconds(0),
+		);
+		let changed = alerts.update(&[Some(json!({"db": {"cpu": 10}}))], seconds(3));
+		assert_eq!(changed.len(), 1);
+		assert_eq!(changed[0].entry.as_deref(), Some("web"));
+		assert_eq!(changed[0].state, AlertState::Resolved);
+		assert_eq!(changed[0].value, Value::Null);
+	}
+
+	#[test]
+	fn evaluates_subsystems_without_entries_as_a_whole() {
+		let mut alerts = Alerts::new(vec![Rule::parse(
+			"rules.test",
+			&rule_config("memory.percent >= 90"),
+		)
+		.unwrap()]);
+
+		let changed = alerts.update(&[Some(json!({"percent": 92.5}))], seconds(0));
+		assert_eq!(changed.len(), 1);
+		assert_eq!(changed[0].entry, None);
+	}
+
+	#[test]
+	fn compares_fields_of_the_same_entry() {
+		let mut alerts = Alerts::new(vec![Rule::parse(
+			"rules.test",
+			&rule_config("components.temperature >= critical"),
+		)
+		.unwrap()]);
+		let components = json!({
+			"cpu": {"temperature": 101, "critical": 100},
+			"nvme": {"temperature": 40, "critical": 85},
+			"acpi": {"temperature": 40},
+		});
+
+		let changed = alerts.update(&[Some(components)], seconds(0));
+		assert_eq!(changed.len(), 1);
+		assert_eq!(changed[0].entry.as_deref(), Some("cpu"));
+	}
+
+	#[test]
+	fn skips_rules_whose_subsystem_could_not_be_read() {
+		let mut alerts = Alerts::new(vec![Rule::parse(
+			"rules.test",
+			&rule_config("memory.percent >= 90"),
+		)
+		.unwrap()]);
+
+		alerts.update(&[Some(json!({"percent": 95}))], seconds(0));
+		assert!(alerts.update(&[None], seconds(3)).is_empty());
+		assert_eq!(
+			alerts.list(&Scopes::all(), Some(AlertState::Firing)).len(),
+			1
+		);
+	}
+
+	#[test]
+	fn lists_alerts_of_the_entries_a_scope_grants() {
+		let mut disk = rule_config("storage.percent > 90");
+		disk.name = String::from("DiskFull");
+		let mut cpu = rule_config("processes.cpu > 90");
+		cpu.name = String::from("ProcessBusy");
+		let mut alerts = Alerts::new(vec![
+			Rule::parse("rules.disk", &disk).unwrap(),
+			Rule::parse("rules.cpu", &cpu).unwrap(),
+		]);
+		alerts.update(
+			&[
+				Some(json!({"sda1": {"percent": 95, "mount_point": "/"}})),
+				Some(json!({"42": {"cpu": 99, "name": "nginx", "pid": 42}})),
+			],
+			seconds(0),
+		);
+
+		let listed = |scopes: &[&str]| -> Vec<String> {
+			let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+			let alerts = alerts.list(&Scopes::parse(&scopes).unwrap(), None);
+			assert!(alerts.iter().all(|alert| alert.state == AlertState::Firing));
+			alerts.into_iter().map(|alert| alert.rule).collect()
+		};
+		assert_eq!(listed(&["storage:/"]), vec!["DiskFull"]);
+		assert_eq!(listed(&["storage:sda1"]), vec!["DiskFull"]);
+		assert!(listed(&["storage:sdb1"]).is_empty());
+		assert_eq!(listed(&["processes:nginx"]), vec!["ProcessBusy"]);
+		assert_eq!(listed(&["processes:42"]), vec!["ProcessBusy"]);
+		assert_eq!(listed(&["storage", "processes"]).len(), 2);
+		assert!(listed(&["memory"]).is_empty());
+	}
+}
@@ -0,0 +1,150 @@
+use crate::auth::Scopes;
+use crate::monitor::collector;
+use crate::monitor::snapshot::Snapshot;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Returns everything collected in the last refresh that `scopes` grants access to.
+pub fn snapshot(published: &Snapshot, scopes: &Scopes) -> Value {
+	let mut snapshot = Map::new();
+	snapshot.insert(String::from("version"), json!("v10.2.1"));
+
+	if scopes.allows("system") {
+		snapshot.insert(String::from("system_info"), json!(published.system_info));
+	}
+
+	for subsystem in collector::subsystems() {
+		if !scopes.allows(subsystem.name) {
+			continue;
+		}
+
+		if let Ok(value) = published.render(subsystem.name, &HashMap::new(), scopes) {
+			snapshot.insert(subsystem.key.to_string(), value);
+		}
+	}
+
+	snapshot.insert(
+		String::from("collectors"),
+		published.collector_status(scopes),
+	);
+
+	Value::Object(snapshot)
+}
+
+/// Subsystems that can be requested from `/api/v1/{subsystem}` and `/api/v1/stream`.
+pub fn subsystems() -> Vec<&'static str> {
+	collector::subsystems().iter().map(|s| s.name).collect()
+}
+
+/// Query parameters that select entries of a subsystem.
+pub fn filters(subsystem: &str) -> &'static [&'static str] {
+	match collector::subsystem(subsystem) {
+		Some(subsystem) => subsystem.filters,
+		None => &[],
+	}
+}
+
+pub enum ApiError {
+	UnknownSubsystem(String),
+	UnknownFilter(String),
+	Forbidden(String),
+	InvalidParameter(String, String),
+}
+
+impl fmt::Display for ApiError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ApiError::UnknownSubsystem(name) => write!(f, "Unknown subsystem: {}", name),
+			ApiError::UnknownFilter(key) => write!(f, "Unsupported filter: {}", key),
+			ApiError::Forbidden(name) => write!(f, "Access to {} is not granted to this token", name),
+			ApiError::InvalidParameter(key, message) => {
+				write!(f, "Invalid parameter {}: {}", key, message)
+			}
+		}
+	}
+}
+
+/// Returns the current state of a single subsystem. Query parameters select
+/// individual entries, e.g. `?name=eth0` for network or `?mount=/data` for storage.
+pub fn subsystem(
+	snapshot: &Snapshot,
+	name: &str,
+	query: &HashMap<String, String>,
+	scopes: &Scopes,
+) -> Result<Value, ApiError> {
+	if collector::subsystem(name).is_none() {
+		return Err(ApiError::UnknownSubsystem(name.to_string()));
+	}
+
+	if !scopes.allows(name) {
+		return Err(ApiError::Forbidden(name.to_string()));
+	}
+
+	snapshot.render(name, query, scopes)
+}
+
+/// Returns the subsystems whose state differs from `last`, and remembers the new state.
+pub fn delta(
+	snapshot: &Snapshot,
+	subsystems: &[String],
+	scopes: &Scopes,
+	last: &mut HashMap<String, Value>,
+) -> Map<String, Value> {
+	let mut changed = Map::new();
+
+	for name in subsystems {
+		let value = match subsystem(snapshot, name, &HashMap::new(), scopes) {
+			Ok(value) => value,
+			Err(_) => continue,
+		};
+
+		if last.get(name) != Some(&value) {
+			last.insert(name.clone(), value.clone());
+			changed.insert(name.clone(), value);
+		}
+	}
+
+	changed
+}
+
+/// Fails on query parameters that are not in `allowed`.
+pub fn check_filters(query: &HashMap<String, String>, allowed: &[&str]) -> Result<(), ApiError> {
+	match query.keys().find(|key| !allowed.contains(&key.as_str())) {
+		Some(key) => Err(ApiError::UnknownFilter(key.clone())),
+		None => Ok(()),
+	}
+}
+
+/// Serializes the entries of `entries` whose attributes match every query parameter
+/// and that the scopes grant. `attribute` returns the value of a filter key for an
+/// entry; the values of all `allowed` keys identify the entry for the scopes.
+pub fn select<T: Serialize>(
+	entries: &HashMap<String, T>,
+	query: &HashMap<String, String>,
+	allowed: &[&str],
+	(subsystem, scopes): (&str, &Scopes),
+	attribute: impl Fn(&str, &str, &T) -> String,
+) -> Result<Value, ApiError> {
+	check_filters(query, allowed)?;
+
+	let selected: HashMap<&String, &T> = entries
+		.iter()
+		.filter(|(name, entry)| {
+			let names: Vec<String> = allowed
+				.iter()
+				.map(|key| attribute(key, name, entry))
+				.collect();
+			let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+			scopes.allows_entry(subsystem, &names)
+		})
+		.filter(|(name, entry)| {
+			query
+				.iter()
+				.all(|(key, value)| &attribute(key, name, entry) == value)
+		})
+		.collect();
+
+	Ok(json!(selected))
+}
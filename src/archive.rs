@@ -0,0 +1,696 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::auth::Scopes;
+use crate::config::Config;
+use crate::metrics::{MetricFamily, MetricType};
+
+/// Resolution tiers of the archive. Raw samples are downsampled to 1-minute
+/// aggregates, which are downsampled to 1-hour aggregates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Tier {
+	Raw,
+	Minute,
+	Hour,
+}
+
+impl Tier {
+	const ALL: [Tier; 3] = [Tier::Raw, Tier::Minute, Tier::Hour];
+
+	fn prefix(&self) -> &'static str {
+		match self {
+			Tier::Raw => "raw",
+			Tier::Minute => "1m",
+			Tier::Hour => "1h",
+		}
+	}
+
+	/// Time span covered by one segment file in milliseconds.
+	fn window(&self) -> u64 {
+		match self {
+			Tier::Raw => 3_600_000,
+			Tier::Minute => 86_400_000,
+			Tier::Hour => 30 * 86_400_000,
+		}
+	}
+
+	/// Size of an aggregation bucket in milliseconds.
+	fn bucket(&self) -> u64 {
+		match self {
+			Tier::Raw => 1,
+			Tier::Minute => 60_000,
+			Tier::Hour => 3_600_000,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+struct SeriesKey {
+	name: String,
+	labels: Vec<(String, String)>,
+	subsystem: Option<String>,
+}
+
+/// A raw sample or an aggregate of samples. A raw sample has a count of one.
+#[derive(Clone, Copy, Debug)]
+struct Point {
+	timestamp: u64,
+	min: f64,
+	max: f64,
+	sum: f64,
+	count: u64,
+}
+
+impl Point {
+	fn merge(&mut self, other: &Point) {
+		self.min = self.min.min(other.min);
+		self.max = self.max.max(other.max);
+		self.sum += other.sum;
+		self.count += other.count;
+	}
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+	tier: Tier,
+	start: u64,
+	path: PathBuf,
+	size: u64,
+}
+
+struct SegmentWriter {
+	start: u64,
+	file: BufWriter<File>,
+	ids: HashMap<SeriesKey, u64>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ArchiveSettings {
+	/// Age after which raw samples are downsampled to 1-minute aggregates
+	pub minute_after: Duration,
+	/// Age after which 1-minute aggregates are downsampled to 1-hour aggregates
+	pub hour_after: Duration,
+	/// Size of all segment files together in bytes
+	pub max_size: u64,
+}
+
+impl ArchiveSettings {
+	pub fn from_config(config: &Config) -> Self {
+		ArchiveSettings {
+			minute_after: Duration::from_secs(config.downsample_1m_after),
+			hour_after: Duration::from_secs(config.downsample_1h_after),
+			max_size: config.data_max_size * 1024 * 1024,
+		}
+	}
+}
+
+/// Persists samples in append-only segment files, so a local record survives
+/// restarts of the agent and outages of the central Prometheus.
+///
+/// Segment files are line based. A `B` line starts a block, `B <segment>` one that
+/// holds the aggregates of a downsampled segment, `S <id> <series>` defines a
+/// series for the rest of the block, `P <id> <timestamp> <value>` is
+/// a raw sample and `A <id> <timestamp> <min> <max> <sum> <count>` an aggregate.
+/// Timestamps are milliseconds since the Unix epoch.
+pub struct Archive {
+	dir: PathBuf,
+	settings: ArchiveSettings,
+	writer: Option<SegmentWriter>,
+	last: HashMap<SeriesKey, u64>,
+}
+
+impl Archive {
+	pub fn open(dir: &Path, settings: ArchiveSettings) -> io::Result<Self> {
+		fs::create_dir_all(dir)?;
+
+		Ok(Archive {
+			dir: dir.to_path_buf(),
+			settings,
+			writer: None,
+			last: HashMap::new(),
+		})
+	}
+
+	pub fn set_settings(&mut self, settings: ArchiveSettings) {
+		self.settings = settings;
+	}
+
+	/// Appends the samples of a refresh to the current raw segment. Like the
+	/// in-memory history, info metrics are left out and samples are stored once.
+	pub fn record(&mut self, families: &[MetricFamily], now: Duration) -> io::Result<()> {
+		let now = now.as_millis() as u64;
+		let start = now - now % Tier::Raw.window();
+
+		if self.writer.as_ref().is_none_or(|w| w.start != start) {
+			self.writer = Some(self.open_writer(start)?);
+		}
+		let writer = self.writer.as_mut().unwrap();
+
+		for family in families {
+			if family.metric_type == MetricType::Info {
+				continue;
+			}
+
+			let name = family.full_name();
+			for sample in &family.samples {
+				let key = SeriesKey {
+					name: name.clone(),
+					labels: sample.labels.clone(),
+					subsystem: family.subsystem.clone(),
+				};
+
+				let timestamp = sample
+					.timestamp
+					.map(|t| t.as_millis() as u64)
+					.unwrap_or(now);
+				if self.last.get(&key).is_some_and(|last| *last >= timestamp) {
+					continue;
+				}
+
+				let id = match writer.ids.get(&key) {
+					Some(id) => *id,
+					None => {
+						let id = writer.ids.len() as u64;
+						let series = serde_json::to_string(&key).map_err(io::Error::other)?;
+						writeln!(writer.file, "S {} {}", id, series)?;
+						writer.ids.insert(key.clone(), id);
+						id
+					}
+				};
+
+				writeln!(writer.file, "P {} {} {}", id, timestamp, sample.value)?;
+				self.last.insert(key, timestamp);
+			}
+		}
+
+		// Series that disappeared (e.g. exited processes) are forgotten
+		self.last.retain(|_, last| *last + Tier::Raw.window() > now);
+
+		writer.file.flush()
+	}
+
+	fn open_writer(&self, start: u64) -> io::Result<SegmentWriter> {
+		let path = segment_path(&self.dir, Tier::Raw, start);
+		let mut file = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+		writeln!(file, "B")?;
+
+		Ok(SegmentWriter {
+			start,
+			file,
+			ids: HashMap::new(),
+		})
+	}
+
+	/// Takes what a compaction needs to know, so its segment files are read and
+	/// written without holding the archive.
+	pub fn compaction(&self) -> Compaction {
+		Compaction {
+			dir: self.dir.clone(),
+			settings: self.settings.clone(),
+			active: self.writer.as_ref().map(|w| w.start),
+		}
+	}
+
+	/// Returns the segment files that may hold samples between `from` and `to`, for
+	/// `query`.
+	pub fn segments_between(&self, from: Duration, to: Duration) -> io::Result<Vec<PathBuf>> {
+		let from = from.as_millis() as u64;
+		let to = to.as_millis() as u64;
+
+		let mut segments: Vec<Segment> = segments(&self.dir)?
+			.into_iter()
+			.filter(|segment| segment.start <= to && segment.start + segment.tier.window() >= from)
+			.collect();
+		// Coarser tiers first, so `query` knows which segments they already hold
+		segments.sort_by_key(|segment| std::cmp::Reverse(segment.tier.bucket()));
+
+		Ok(segments.into_iter().map(|segment| segment.path).collect())
+	}
+}
+
+/// Returns the samples and aggregates of a metric between `from` and `to` from the
+/// segment files returned by `Archive::segments_between`. Like the in-memory history,
+/// every entry of `labels` has to match and only series granted by `scopes` are
+/// returned. Segments removed by a compaction in the meantime are skipped, as are
+/// the ones whose aggregates have already been read from a coarser segment.
+pub fn query(
+	segments: &[PathBuf],
+	metric: &str,
+	from: Duration,
+	to: Duration,
+	labels: &HashMap<String, String>,
+	scopes: &Scopes,
+) -> io::Result<Value> {
+	let from = from.as_millis() as u64;
+	let to = to.as_millis() as u64;
+	let mut found: HashMap<SeriesKey, Vec<Point>> = HashMap::new();
+	let mut downsampled: HashSet<String> = HashSet::new();
+
+	for segment in segments {
+		if downsampled.contains(&file_name(segment)) {
+			continue;
+		}
+
+		let result = read_segment(segment, |key, point| {
+			if point.timestamp < from || point.timestamp > to {
+				return;
+			}
+			if key.name != metric && metric.strip_suffix("_total") != Some(key.name.as_str()) {
+				return;
+			}
+			let matches = labels
+				.iter()
+				.all(|(name, value)| key.labels.iter().any(|(k, v)| k == name && v == value));
+			if !matches || !scopes.allows_sample(key.subsystem.as_deref(), &key.labels) {
+				return;
+			}
+
+			found.entry(key.clone()).or_default().push(point);
+		});
+		match result {
+			Ok(sources) => downsampled.extend(sources),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(e),
+		}
+	}
+
+	let mut series: Vec<(SeriesKey, Vec<Point>)> = found.into_iter().collect();
+	series.sort_by(|(a, _), (b, _)| a.labels.cmp(&b.labels));
+
+	let series: Vec<Value> = series
+		.into_iter()
+		.map(|(key, mut points)| {
+			points.sort_by_key(|p| p.timestamp);
+			let labels: Map<String, Value> = key.labels.into_iter().map(|(k, v)| (k, json!(v))).collect();
+			let samples: Vec<Value> = points
+				.iter()
+				.map(|p| {
+					json!([
+						p.timestamp as f64 / 1000.0,
+						p.sum / p.count as f64,
+						p.min,
+						p.max,
+					])
+				})
+				.collect();
+
+			json!({
+				"labels": labels,
+				"samples": samples,
+			})
+		})
+		.collect();
+
+	Ok(json!({
+		"metric": metric,
+		"from": from as f64 / 1000.0,
+		"to": to as f64 / 1000.0,
+		"series": series,
+	}))
+}
+
+/// A compaction of the segment files, see `Archive::compaction`. Only the raw segment
+/// that is being written is changed meanwhile, and it is left alone.
+///
+/// A target segment is rewritten to a temporary file that replaces it, and its block
+/// names the segment it was downsampled from. So a compaction that is cut off before
+/// it removes that segment does not aggregate it twice when it runs again, and a query
+/// in the meantime skips it.
+pub struct Compaction {
+	dir: PathBuf,
+	settings: ArchiveSettings,
+	/// Start of the raw segment that is being written
+	active: Option<u64>,
+}
+
+impl Compaction {
+	/// Downsamples segments that are older than the configured ages and deletes the
+	/// oldest segments while the archive is larger than its size cap.
+	pub fn run(&self, now: Duration) -> io::Result<()> {
+		let now = now.as_millis() as u64;
+		let active = self.active;
+
+		for (tier, target, age) in [
+			(Tier::Raw, Tier::Minute, self.settings.minute_after),
+			(Tier::Minute, Tier::Hour, self.settings.hour_after),
+		] {
+			let oldest = now.saturating_sub(age.as_millis() as u64);
+
+			for segment in segments(&self.dir)? {
+				if segment.tier != tier || segment.start + tier.window() > oldest {
+					continue;
+				}
+				if tier == Tier::Raw && Some(segment.start) == active {
+					continue;
+				}
+
+				self.downsample(&segment, target)?;
+				fs::remove_file(&segment.path)?;
+			}
+		}
+
+		let mut remaining = segments(&self.dir)?;
+		let mut size: u64 = remaining.iter().map(|s| s.size).sum();
+		remaining.sort_by_key(|s| s.start + s.tier.window());
+
+		for segment in remaining {
+			if size <= self.settings.max_size {
+				break;
+			}
+			if segment.tier == Tier::Raw && Some(segment.start) == active {
+				continue;
+			}
+
+			fs::remove_file(&segment.path)?;
+			size -= segment.size;
+			println!(
+				"Archive is larger than its size cap, removed {}",
+				segment.path.display()
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Aggregates a segment into buckets of the target tier and adds them to the
+	/// target segments that do not hold them yet.
+	fn downsample(&self, segment: &Segment, target: Tier) -> io::Result<()> {
+		let source = file_name(&segment.path);
+		let mut targets: BTreeMap<u64, BTreeMap<u64, HashMap<SeriesKey, Point>>> = BTreeMap::new();
+
+		read_segment(&segment.path, |key, point| {
+			let bucket = point.timestamp - point.timestamp % target.bucket();
+			let start = bucket - bucket % target.window();
+			let series = targets.entry(start).or_default().entry(bucket).or_default();
+			match series.get_mut(key) {
+				Some(aggregate) => aggregate.merge(&point),
+				None => {
+					series.insert(
+						key.clone(),
+						Point {
+							timestamp: bucket,
+							..point
+						},
+					);
+				}
+			}
+		})?;
+
+		for (start, buckets) in targets {
+			let path = segment_path(&self.dir, target, start);
+			let sources = match read_segment(&path, |_, _| {}) {
+				Ok(sources) => sources,
+				Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+				Err(e) => return Err(e),
+			};
+			if sources.contains(&source) {
+				continue;
+			}
+
+			let temporary = path.with_extension("seg.tmp");
+			if path.exists() {
+				fs::copy(&path, &temporary)?;
+			} else {
+				File::create(&temporary)?;
+			}
+
+			let mut file = BufWriter::new(OpenOptions::new().append(true).open(&temporary)?);
+			writeln!(file, "B {}", source)?;
+
+			let mut ids: HashMap<SeriesKey, u64> = HashMap::new();
+			for (_, series) in buckets {
+				for (key, point) in series {
+					let id = match ids.get(&key) {
+						Some(id) => *id,
+						None => {
+							let id = ids.len() as u64;
+							let series = serde_json::to_string(&key).map_err(io::Error::other)?;
+							writeln!(file, "S {} {}", id, series)?;
+							ids.insert(key, id);
+							id
+						}
+					};
+
+					writeln!(
+						file,
+						"A {} {} {} {} {} {}",
+						id, point.timestamp, point.min, point.max, point.sum, point.count
+					)?;
+				}
+			}
+
+			file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+			fs::rename(&temporary, &path)?;
+		}
+
+		Ok(())
+	}
+}
+
+fn segments(dir: &Path) -> io::Result<Vec<Segment>> {
+	let mut segments = Vec::new();
+
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let file_name = entry.file_name();
+		let file_name = file_name.to_string_lossy();
+
+		let Some(stem) = file_name.strip_suffix(".seg") else {
+			continue;
+		};
+		let Some((prefix, start)) = stem.split_once('-') else {
+			continue;
+		};
+		let Some(tier) = Tier::ALL.into_iter().find(|t| t.prefix() == prefix) else {
+			continue;
+		};
+		let Ok(start) = start.parse::<u64>() else {
+			continue;
+		};
+		// A compaction might have removed it in the meantime
+		let metadata = match entry.metadata() {
+			Ok(metadata) => metadata,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+			Err(e) => return Err(e),
+		};
+
+		segments.push(Segment {
+			tier,
+			start,
+			path: entry.path(),
+			size: metadata.len(),
+		});
+	}
+
+	Ok(segments)
+}
+
+fn segment_path(dir: &Path, tier: Tier, start: u64) -> PathBuf {
+	dir.join(format!("{}-{}.seg", tier.prefix(), start))
+}
+
+fn file_name(path: &Path) -> String {
+	path
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_default()
+}
+
+/// Calls `visit` for every sample or aggregate in a segment file and returns the
+/// segments that its blocks were downsampled from. Lines that can not be parsed,
+/// e.g. one cut off by a crash, are skipped.
+fn read_segment(path: &Path, mut visit: impl FnMut(&SeriesKey, Point)) -> io::Result<Vec<String>> {
+	let reader = BufReader::new(File::open(path)?);
+	let mut series: HashMap<u64, SeriesKey> = HashMap::new();
+	let mut sources = Vec::new();
+
+	for line in reader.lines() {
+		let line = line?;
+		let mut fields = line.splitn(3, ' ');
+
+		match fields.next() {
+			Some("B") => {
+				series.clear();
+				if let Some(source) = fields.next() {
+					sources.push(source.to_string());
+				}
+			}
+			Some("S") => {
+				let id = fields.next().and_then(|id| id.parse::<u64>().ok());
+				let key = fields
+					.next()
+					.and_then(|key| serde_json::from_str::<SeriesKey>(key).ok());
+				if let (Some(id), Some(key)) = (id, key) {
+					series.insert(id, key);
+				}
+			}
+			Some("P") | Some("A") => {
+				let values: Vec<&str> = line.split(' ').skip(1).collect();
+				let Some(point) = parse_point(&values) else {
+					continue;
+				};
+				let Some(key) = values[0].parse::<u64>().ok().and_then(|id| series.get(&id)) else {
+					continue;
+				};
+				visit(key, point);
+			}
+			_ => {}
+		}
+	}
+
+	Ok(sources)
+}
+
+fn parse_point(values: &[&str]) -> Option<Point> {
+	match values.len() {
+		3 => {
+			let value: f64 = values[2].parse().ok()?;
+			Some(Point {
+				timestamp: values[1].parse().ok()?,
+				min: value,
+				max: value,
+				sum: value,
+				count: 1,
+			})
+		}
+		6 => Some(Point {
+			timestamp: values[1].parse().ok()?,
+			min: values[2].parse().ok()?,
+			max: values[3].parse().ok()?,
+			sum: values[4].parse().ok()?,
+			count: values[5].parse().ok()?,
+		}),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::metrics::Sample;
+
+	const METRIC: &str = "rabbit_cpu_usage_percent";
+
+	/// Start of an hour, so of a raw segment.
+	const START: u64 = 1_699_999_200;
+
+	fn directory(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("archive-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		dir
+	}
+
+	fn settings() -> ArchiveSettings {
+		ArchiveSettings {
+			minute_after: Duration::from_secs(3600),
+			hour_after: Duration::from_secs(30 * 86400),
+			max_size: 1024 * 1024 * 1024,
+		}
+	}
+
+	fn families(values: &[(&str, f64)]) -> Vec<MetricFamily> {
+		vec![MetricFamily {
+			name: String::from("cpu_usage"),
+			description: String::from("CPU usage"),
+			metric_type: MetricType::Gauge,
+			unit: Some(String::from("percent")),
+			subsystem: Some(String::from("cpu")),
+			samples: values
+				.iter()
+				.map(|(core, value)| Sample {
+					labels: vec![(String::from("core"), core.to_string())],
+					value: *value,
+					timestamp: None,
+					created: None,
+				})
+				.collect(),
+		}]
+	}
+
+	fn at(seconds: u64) -> Duration {
+		Duration::from_secs(START + seconds)
+	}
+
+	fn samples(archive: &Archive, core: &str) -> Value {
+		let (from, to) = (at(0), at(3600));
+		let segments = archive.segments_between(from, to).unwrap();
+		let labels = HashMap::from([(String::from("core"), core.to_string())]);
+		let result = query(&segments, METRIC, from, to, &labels, &Scopes::all()).unwrap();
+		assert_eq!(result["series"].as_array().unwrap().len(), 1);
+		result["series"][0]["samples"].clone()
+	}
+
+	#[test]
+	fn queries_recorded_samples() {
+		let dir = directory("record");
+		let mut archive = Archive::open(&dir, settings()).unwrap();
+
+		archive
+			.record(&families(&[("0", 10.0), ("1", 20.0)]), at(0))
+			.unwrap();
+		archive
+			.record(&families(&[("0", 30.0), ("1", 40.0)]), at(10))
+			.unwrap();
+
+		let start = START as f64;
+		assert_eq!(
+			samples(&archive, "0"),
+			json!([[start, 10.0, 10.0, 10.0], [start + 10.0, 30.0, 30.0, 30.0]])
+		);
+		assert_eq!(
+			samples(&archive, "1"),
+			json!([[start, 20.0, 20.0, 20.0], [start + 10.0, 40.0, 40.0, 40.0]])
+		);
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn downsamples_a_raw_segment_once() {
+		let dir = directory("downsample");
+		let mut archive = Archive::open(&dir, settings()).unwrap();
+
+		for (seconds, value) in [(0, 10.0), (20, 30.0), (40, 20.0), (60, 50.0)] {
+			archive
+				.record(&families(&[("0", value)]), at(seconds))
+				.unwrap();
+		}
+		// Moves on to the next raw segment
+		archive.record(&families(&[("0", 0.0)]), at(7200)).unwrap();
+
+		let raw = segment_path(&dir, Tier::Raw, START * 1000);
+		let copy = dir.join("copy");
+		fs::copy(&raw, &copy).unwrap();
+
+		archive.compaction().run(at(3 * 3600)).unwrap();
+		assert!(!raw.exists());
+		assert!(segment_path(&dir, Tier::Raw, (START + 7200) * 1000).exists());
+
+		let start = START as f64;
+		let downsampled = json!([[start, 20.0, 10.0, 30.0], [start + 60.0, 50.0, 50.0, 50.0]]);
+		assert_eq!(samples(&archive, "0"), downsampled);
+
+		// A compaction that was cut off before it removed the raw segment
+		fs::copy(&copy, &raw).unwrap();
+		assert_eq!(samples(&archive, "0"), downsampled);
+
+		archive.compaction().run(at(3 * 3600)).unwrap();
+		assert!(!raw.exists());
+		assert_eq!(samples(&archive, "0"), downsampled);
+
+		let day = START - START % 86400;
+		let minute = fs::read_to_string(segment_path(&dir, Tier::Minute, day * 1000)).unwrap();
+		assert_eq!(
+			minute.lines().filter(|line| line.starts_with('B')).count(),
+			1
+		);
+		assert!(minute.starts_with(&format!("B raw-{}.seg\n", START * 1000)));
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -0,0 +1,461 @@
+use axum::http::HeaderMap;
+use axum_extra::headers::authorization::{Basic, Bearer};
+use axum_extra::headers::{Authorization, HeaderMapExt};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+use crate::api;
+use crate::config::{Config, ConfigError, TokenConfig};
+use crate::metrics::{MetricFamily, MetricType, MetricsBuilder};
+use crate::monitor::collector;
+
+/// Name used for the token passed with `--token` or `--token-file`.
+pub const DEFAULT_TOKEN_NAME: &str = "default";
+
+/// Scopes that are not a subsystem: `system` covers the system information and
+/// exporter metrics, `reload` allows `POST /-/reload`.
+pub const EXTRA_SCOPES: [&str; 2] = ["system", "reload"];
+
+/// Length of the identity of Bearer credentials, `sha256:` and 8 hex digits.
+const IDENTITY_LENGTH: usize = 15;
+
+/// Most combinations of reason and token the authentication failures are counted by.
+const MAX_AUTH_FAILURE_SERIES: usize = 100;
+
+pub struct Token {
+	pub name: String,
+	hash: [u8; 32],
+	scopes: Scopes,
+}
+
+/// The client a request was authenticated as.
+#[derive(Clone, Debug)]
+pub struct Client {
+	pub name: String,
+	pub scopes: Scopes,
+}
+
+/// Credentials presented with a request: a Bearer token, or HTTP Basic with the
+/// token's name as user name and the token as password, which lets browsers log in.
+pub struct Credentials {
+	pub username: Option<String>,
+	pub token: String,
+}
+
+impl Credentials {
+	pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+		if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+			return Some(Credentials {
+				username: None,
+				token: bearer.token().to_string(),
+			});
+		}
+
+		if let Some(Authorization(basic)) = headers.typed_get::<Authorization<Basic>>() {
+			return Some(Credentials {
+				username: Some(basic.username().to_string()),
+				token: basic.password().to_string(),
+			});
+		}
+
+		None
+	}
+
+	/// Names the sender of credentials that were rejected without revealing its token:
+	/// the Basic user name, or the start of the hash of a Bearer token.
+	pub fn identity(&self) -> String {
+		match &self.username {
+			Some(username) => username.clone(),
+			None => format_hash(&hash_token(&self.token))[..IDENTITY_LENGTH].to_string(),
+		}
+	}
+}
+
+/// Subsystems, and optionally individual entries of them, a token may access.
+/// Scopes are written as `cpu` or `containers:nginx,redis`. A token without
+/// scopes may access everything.
+#[derive(Clone, Debug, Default)]
+pub struct Scopes {
+	grants: Option<HashMap<String, Option<Vec<String>>>>,
+}
+
+impl Scopes {
+	pub fn all() -> Self {
+		Scopes { grants: None }
+	}
+
+	/// Whether every subsystem and entry is granted, e.g. so a pre-rendered exposition
+	/// can be served as it is.
+	pub fn allows_everything(&self) -> bool {
+		self.grants.is_none()
+	}
+
+	/// Parses a list of scopes. On error the index of the offending scope is returned.
+	pub fn parse(scopes: &[String]) -> Result<Self, (usize, String)> {
+		if scopes.is_empty() {
+			return Ok(Scopes::all());
+		}
+
+		let mut grants: HashMap<String, Option<Vec<String>>> = HashMap::new();
+
+		for (i, scope) in scopes.iter().enumerate() {
+			let (subsystem, entries) = match scope.split_once(':') {
+				Some((subsystem, entries)) => (subsystem.trim(), Some(entries)),
+				None => (scope.trim(), None),
+			};
+
+			if !api::subsystems().contains(&subsystem) && !EXTRA_SCOPES.contains(&subsystem) {
+				return Err((i, format!("unknown scope `{}`", subsystem)));
+			}
+
+			let entries = match entries {
+				Some(entries) => {
+					if entry_labels(subsystem).is_empty() {
+						return Err((i, format!("`{}` can not be limited to entries", subsystem)));
+					}
+
+					let entries: Vec<String> = entries
+						.split(',')
+						.map(|e| e.trim().to_string())
+						.filter(|e| !e.is_empty())
+						.collect();
+					if entries.is_empty() {
+						return Err((i, String::from("no entries listed after `:`")));
+					}
+					Some(entries)
+				}
+				None => None,
+			};
+
+			// Granting a whole subsystem wins over granting some of its entries
+			match (grants.get_mut(subsystem), entries) {
+				(Some(Some(existing)), Some(entries)) => existing.extend(entries),
+				(Some(existing), None) => *existing = None,
+				(Some(None), Some(_)) => {}
+				(None, entries) => {
+					grants.insert(subsystem.to_string(), entries);
+				}
+			}
+		}
+
+		Ok(Scopes {
+			grants: Some(grants),
+		})
+	}
+
+	pub fn allows(&self, subsystem: &str) -> bool {
+		match &self.grants {
+			Some(grants) => grants.contains_key(subsystem),
+			None => true,
+		}
+	}
+
+	/// Checks whether an entry of a subsystem is visible. An entry is identified by
+	/// several names (e.g. device and mount point), any of which may be granted.
+	pub fn allows_entry(&self, subsystem: &str, names: &[&str]) -> bool {
+		match &self.grants {
+			Some(grants) => match grants.get(subsystem) {
+				Some(Some(entries)) => names.iter().any(|name| entries.iter().any(|e| e == name)),
+				Some(None) => true,
+				None => false,
+			},
+			None => true,
+		}
+	}
+
+	/// Checks whether a sample of a metric family in `subsystem` is visible. Samples
+	/// without a subsystem are always visible.
+	pub fn allows_sample(&self, subsystem: Option<&str>, labels: &[(String, String)]) -> bool {
+		let subsystem = match subsystem {
+			Some(subsystem) => subsystem,
+			None => return true,
+		};
+
+		if !self.allows(subsystem) {
+			return false;
+		}
+
+		let entry_labels = entry_labels(subsystem);
+		if entry_labels.is_empty() {
+			return true;
+		}
+
+		let names: Vec<&str> = labels
+			.iter()
+			.filter(|(key, _)| entry_labels.contains(&key.as_str()))
+			.map(|(_, value)| value.as_str())
+			.collect();
+		self.allows_entry(subsystem, &names)
+	}
+
+	/// Drops the samples that are not granted, and the families left without samples.
+	/// Families without a subsystem are always kept.
+	pub fn filter_metrics(&self, families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+		if self.grants.is_none() {
+			return families;
+		}
+
+		families
+			.into_iter()
+			.filter_map(|mut family| {
+				if family.subsystem.is_none() {
+					return Some(family);
+				}
+
+				let subsystem = family.subsystem.clone();
+				family
+					.samples
+					.retain(|sample| self.allows_sample(subsystem.as_deref(), &sample.labels));
+
+				if family.samples.is_empty() {
+					None
+				} else {
+					Some(family)
+				}
+			})
+			.collect()
+	}
+}
+
+/// Metric labels that identify an entry of a subsystem. Subsystems without entries
+/// can only be granted as a whole.
+pub fn entry_labels(subsystem: &str) -> &'static [&'static str] {
+	match collector::subsystem(subsystem) {
+		Some(subsystem) => subsystem.entry_labels,
+		None => &[],
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuthFailure {
+	Missing,
+	Invalid,
+	Forbidden,
+}
+
+impl AuthFailure {
+	pub fn reason(&self) -> &'static str {
+		match self {
+			AuthFailure::Missing => "missing",
+			AuthFailure::Invalid => "invalid",
+			AuthFailure::Forbidden => "forbidden",
+		}
+	}
+}
+
+pub struct Authenticator {
+	tokens: Vec<Token>,
+}
+
+impl Authenticator {
+	/// Resolves every configured token (inline, hashed, from a file or from an
+	/// environment variable) into its SHA-256 hash.
+	pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+		let mut tokens = Vec::new();
+
+		if let Some(token) = &config.token {
+			tokens.push(Token {
+				name: DEFAULT_TOKEN_NAME.to_string(),
+				hash: hash_token(token),
+				scopes: Scopes::all(),
+			});
+		}
+
+		if let Some(path) = &config.token_file {
+			let token = read_token_file(path).map_err(|message| ConfigError::Invalid {
+				key: "token_file".to_string(),
+				message,
+			})?;
+			tokens.push(Token {
+				name: DEFAULT_TOKEN_NAME.to_string(),
+				hash: hash_token(&token),
+				scopes: Scopes::all(),
+			});
+		}
+
+		for (i, token) in config.tokens.iter().enumerate() {
+			let hash = resolve_token(token).map_err(|(field, message)| ConfigError::Invalid {
+				key: format!("tokens[{}].{}", i, field),
+				message,
+			})?;
+			let scopes = Scopes::parse(&token.scopes).map_err(|(j, message)| ConfigError::Invalid {
+				key: format!("tokens[{}].scopes[{}]", i, j),
+				message,
+			})?;
+			tokens.push(Token {
+				name: token.name.clone(),
+				hash,
+				scopes,
+			});
+		}
+
+		Ok(Authenticator { tokens })
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		!self.tokens.is_empty()
+	}
+
+	/// Checks a token against every configured token. All hashes are compared in
+	/// constant time so the position of a match is not leaked. A Basic user name
+	/// has to be the name of the matching token.
+	pub fn authenticate(&self, credentials: Option<&Credentials>) -> Result<Client, AuthFailure> {
+		if !self.is_enabled() {
+			return Ok(Client {
+				name: String::from("anonymous"),
+				scopes: Scopes::all(),
+			});
+		}
+
+		let credentials = credentials.ok_or(AuthFailure::Missing)?;
+		let hash = hash_token(&credentials.token);
+
+		let mut matched: Option<&Token> = None;
+		for token in &self.tokens {
+			if bool::from(token.hash.ct_eq(&hash)) && matched.is_none() {
+				matched = Some(token);
+			}
+		}
+
+		match matched {
+			Some(token)
+				if credentials
+					.username
+					.as_ref()
+					.is_some_and(|u| u != &token.name) =>
+			{
+				Err(AuthFailure::Invalid)
+			}
+			Some(token) => Ok(Client {
+				name: token.name.clone(),
+				scopes: token.scopes.clone(),
+			}),
+			None => Err(AuthFailure::Invalid),
+		}
+	}
+}
+
+/// Counts rejected requests by reason and token name.
+pub struct AuthFailures {
+	counts: Mutex<BTreeMap<(String, String), u64>>,
+	created: u64,
+}
+
+impl AuthFailures {
+	pub fn new(created: u64) -> Self {
+		let mut counts = BTreeMap::new();
+		for failure in [AuthFailure::Missing, AuthFailure::Invalid] {
+			counts.insert((failure.reason().to_string(), String::new()), 0);
+		}
+
+		AuthFailures {
+			counts: Mutex::new(counts),
+			created,
+		}
+	}
+
+	/// Counts a rejected request. `token` is the name of the token or the identity of
+	/// the credentials; once `MAX_AUTH_FAILURE_SERIES` are counted, new ones are
+	/// counted as `other` so random credentials can not grow the metrics without bound.
+	pub fn record(&self, reason: &str, token: &str) {
+		let mut counts = self.counts.lock().unwrap();
+		let mut key = (reason.to_string(), token.to_string());
+		if !counts.contains_key(&key) && counts.len() >= MAX_AUTH_FAILURE_SERIES {
+			key.1 = String::from("other");
+		}
+		*counts.entry(key).or_insert(0) += 1;
+	}
+
+	pub fn metrics(&self, now: Duration) -> Vec<MetricFamily> {
+		let counts = self.counts.lock().unwrap();
+		let mut metrics = MetricsBuilder::new();
+
+		metrics.subsystem("system");
+		metrics.header(
+			"exporter_auth_failures",
+			"Number of requests rejected by authentication",
+			MetricType::Counter,
+			None,
+		);
+		for ((reason, token), count) in counts.iter() {
+			metrics.counter_sample(
+				*count as f64,
+				&[("reason", reason), ("token", token)],
+				now,
+				self.created,
+			);
+		}
+
+		metrics.build()
+	}
+}
+
+/// Hashes a token the way it is stored, e.g. for the `hash` key of a token.
+pub fn hash_token(token: &str) -> [u8; 32] {
+	Sha256::digest(token.as_bytes()).into()
+}
+
+pub fn format_hash(hash: &[u8; 32]) -> String {
+	let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+	format!("sha256:{}", hex)
+}
+
+fn parse_hash(value: &str) -> Option<[u8; 32]> {
+	let hex = value.strip_prefix("sha256:").unwrap_or(value);
+	if hex.len() != 64 || !hex.is_ascii() {
+		return None;
+	}
+
+	let mut hash = [0u8; 32];
+	for (i, byte) in hash.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+	}
+	Some(hash)
+}
+
+fn read_token_file(path: &std::path::Path) -> Result<String, String> {
+	let content =
+		fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+	let token = content.trim();
+
+	if token.is_empty() {
+		return Err(format!("{} is empty", path.display()));
+	}
+
+	Ok(token.to_string())
+}
+
+fn resolve_token(token: &TokenConfig) -> Result<[u8; 32], (&'static str, String)> {
+	if let Some(value) = &token.token {
+		return Ok(hash_token(value));
+	}
+
+	if let Some(hash) = &token.hash {
+		return parse_hash(hash).ok_or((
+			"hash",
+			String::from("must be a SHA-256 hash written as 64 hex characters"),
+		));
+	}
+
+	if let Some(path) = &token.file {
+		return read_token_file(path)
+			.map(|value| hash_token(&value))
+			.map_err(|message| ("file", message));
+	}
+
+	if let Some(name) = &token.env {
+		return match std::env::var(name) {
+			Ok(value) if !value.trim().is_empty() => Ok(hash_token(value.trim())),
+			_ => Err(("env", format!("environment variable {} is not set", name))),
+		};
+	}
+
+	Err((
+		"token",
+		String::from("one of token, hash, file or env is required"),
+	))
+}
@@ -0,0 +1,862 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::monitor::collector;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub address: String,
+	pub port: u16,
+	pub cache: u64,
+	pub collector_timeout: u64,
+	pub collectors: HashMap<String, CollectorConfig>,
+	pub history_retention: u64,
+	pub data_dir: Option<PathBuf>,
+	pub downsample_1m_after: u64,
+	pub downsample_1h_after: u64,
+	pub data_max_size: u64,
+	pub token: Option<String>,
+	pub token_file: Option<PathBuf>,
+	pub tokens: Vec<TokenConfig>,
+	pub access_log: bool,
+	pub rules: Vec<RuleConfig>,
+	pub rules_file: Option<PathBuf>,
+	pub notifiers: Vec<NotifierConfig>,
+	pub dead_letter_file: Option<PathBuf>,
+	pub smtp: Option<SmtpConfig>,
+	pub remote_write: Option<RemoteWriteConfig>,
+	pub influxdb: Option<InfluxConfig>,
+	pub otlp: Option<OtlpConfig>,
+	pub graphite: Option<GraphiteConfig>,
+	pub statsd: Option<StatsdConfig>,
+	pub tls_cert: Option<PathBuf>,
+	pub tls_key: Option<PathBuf>,
+	pub tls_client_ca: Option<PathBuf>,
+	pub interfaces: Vec<String>,
+	pub mounts: Vec<String>,
+	pub components: Vec<String>,
+	pub processes: Vec<String>,
+	pub containers: Vec<String>,
+	pub batteries: Vec<String>,
+	pub disabled_subsystems: Vec<String>,
+	pub timestamps: bool,
+	pub all_metrics: bool,
+	pub cpu_details: bool,
+	pub memory_details: bool,
+	pub swap_details: bool,
+	pub storage_details: bool,
+	pub network_details: bool,
+}
+
+/// A named Bearer token. Exactly one of `token`, `hash`, `file` or `env` has to be set.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+	pub name: String,
+	pub token: Option<String>,
+	/// SHA-256 of the token as `sha256:<hex>`, see `--hash-token`
+	pub hash: Option<String>,
+	pub file: Option<PathBuf>,
+	pub env: Option<String>,
+	/// Subsystems and entries the token may access, e.g. `cpu` or `containers:nginx,redis`
+	#[serde(default)]
+	pub scopes: Vec<String>,
+}
+
+/// An alert rule, see `alerts::Rule` for the expression syntax.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+	pub name: String,
+	/// Condition such as `storage.percent{mount="/"} > 90`
+	pub expr: String,
+	/// How long the condition has to hold before the alert fires, e.g. `5m`
+	#[serde(rename = "for")]
+	pub hold: Option<String>,
+	/// Threshold a firing alert has to cross again before it resolves
+	pub clear: Option<f64>,
+	pub severity: Option<String>,
+	pub description: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+	Webhook,
+	Slack,
+	Discord,
+	Ntfy,
+	Gotify,
+}
+
+/// A target that firing and resolved alerts are delivered to.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NotifierConfig {
+	pub name: String,
+	#[serde(default = "default_notifier_kind")]
+	pub kind: NotifierKind,
+	pub url: String,
+	/// Access token, sent as `X-Gotify-Key` for Gotify and as a Bearer token otherwise
+	pub token: Option<String>,
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+	/// Request body of a webhook with `{{placeholders}}`, the alert as JSON by default
+	pub template: Option<String>,
+	/// Names of the rules to notify about, all rules if empty
+	#[serde(default)]
+	pub rules: Vec<String>,
+	#[serde(default = "default_send_resolved")]
+	pub send_resolved: bool,
+	#[serde(default = "default_retries")]
+	pub retries: u32,
+}
+
+/// Mail relay that alert emails are sent through.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+	pub host: String,
+	#[serde(default = "default_smtp_port")]
+	pub port: u16,
+	/// Upgrade the connection with STARTTLS, required when it is not offered
+	#[serde(default)]
+	pub starttls: bool,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	pub from: String,
+	pub to: Vec<String>,
+	/// Minimum time between two emails, alerts in between are sent as one digest
+	#[serde(default = "default_digest_interval")]
+	pub digest_interval: String,
+	/// Names of the rules to notify about, all rules if empty
+	#[serde(default)]
+	pub rules: Vec<String>,
+	#[serde(default = "default_send_resolved")]
+	pub send_resolved: bool,
+}
+
+/// Prometheus remote_write endpoint that every refresh is pushed to.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteWriteConfig {
+	pub url: String,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	pub bearer_token: Option<String>,
+	/// Labels added to every series, e.g. `instance`, since nothing scrapes this host
+	#[serde(default)]
+	pub labels: HashMap<String, String>,
+	#[serde(default = "default_max_samples_per_send")]
+	pub max_samples_per_send: usize,
+	/// Samples kept in memory while the receiver is down
+	#[serde(default = "default_queue_capacity")]
+	pub queue_capacity: usize,
+	/// Directory that samples beyond the queue capacity are spooled to
+	pub spool_dir: Option<PathBuf>,
+	/// Maximum size of the spool directory in MiB
+	#[serde(default = "default_spool_max_size")]
+	pub spool_max_size: u64,
+}
+
+/// InfluxDB v2 server that every refresh is written to.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxConfig {
+	pub url: String,
+	pub org: String,
+	pub bucket: String,
+	pub token: String,
+	/// Tags added to every line, e.g. `host`
+	#[serde(default)]
+	pub tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OtlpProtocol {
+	#[serde(rename = "http/protobuf")]
+	HttpProtobuf,
+	#[serde(rename = "grpc")]
+	Grpc,
+}
+
+/// OpenTelemetry collector that every refresh is exported to.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+	/// Base URL of the collector, e.g. `http://collector:4318` for HTTP or
+	/// `http://collector:4317` for gRPC
+	pub endpoint: String,
+	#[serde(default = "default_otlp_protocol")]
+	pub protocol: OtlpProtocol,
+	/// Headers sent with every export, e.g. for authentication
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+	/// Resource attributes added to or overriding the detected ones, e.g. `deployment.environment`
+	#[serde(default)]
+	pub resource_attributes: HashMap<String, String>,
+}
+
+/// Graphite server that every refresh is sent to in the plaintext protocol over TCP.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GraphiteConfig {
+	/// `host:port` of the plaintext listener, usually port 2003
+	pub address: String,
+	/// Dotted path every metric path starts with, may be empty
+	#[serde(default = "default_metric_prefix")]
+	pub prefix: String,
+}
+
+/// Refresh interval and timeout of a single collector, in seconds. Unset values fall
+/// back to `cache` and `collector_timeout`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CollectorConfig {
+	pub interval: Option<u64>,
+	pub timeout: Option<u64>,
+}
+
+/// StatsD server that every refresh is sent to as gauges over UDP.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StatsdConfig {
+	/// `host:port` of the StatsD server, usually port 8125
+	pub address: String,
+	/// Dotted path every metric path starts with, may be empty
+	#[serde(default = "default_metric_prefix")]
+	pub prefix: String,
+}
+
+fn default_metric_prefix() -> String {
+	String::from("rabbitmonitor")
+}
+
+fn default_otlp_protocol() -> OtlpProtocol {
+	OtlpProtocol::HttpProtobuf
+}
+
+fn default_max_samples_per_send() -> usize {
+	2000
+}
+
+fn default_queue_capacity() -> usize {
+	100_000
+}
+
+fn default_spool_max_size() -> u64 {
+	256
+}
+
+fn default_smtp_port() -> u16 {
+	25
+}
+
+fn default_digest_interval() -> String {
+	String::from("5m")
+}
+
+fn default_notifier_kind() -> NotifierKind {
+	NotifierKind::Webhook
+}
+
+fn default_send_resolved() -> bool {
+	true
+}
+
+fn default_retries() -> u32 {
+	3
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+	rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Io {
+		path: PathBuf,
+		error: std::io::Error,
+	},
+	Parse {
+		path: PathBuf,
+		message: String,
+	},
+	Invalid {
+		key: String,
+		message: String,
+	},
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ConfigError::Io { path, error } => {
+				write!(f, "Failed to read {}: {}", path.display(), error)
+			}
+			ConfigError::Parse { path, message } => {
+				write!(f, "Failed to parse {}: {}", path.display(), message)
+			}
+			ConfigError::Invalid { key, message } => {
+				write!(f, "Invalid configuration value for `{}`: {}", key, message)
+			}
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+	pub fn new() -> Self {
+		Config {
+			address: String::from("0.0.0.0"),
+			port: 8088,
+			cache: 3,
+			collector_timeout: 10,
+			collectors: HashMap::new(),
+			history_retention: 3600,
+			data_dir: None,
+			downsample_1m_after: 86400,
+			downsample_1h_after: 30 * 86400,
+			data_max_size: 1024,
+			token: None,
+			token_file: None,
+			tokens: Vec::new(),
+			access_log: false,
+			rules: Vec::new(),
+			rules_file: None,
+			notifiers: Vec::new(),
+			dead_letter_file: None,
+			smtp: None,
+			remote_write: None,
+			influxdb: None,
+			otlp: None,
+			graphite: None,
+			statsd: None,
+			tls_cert: None,
+			tls_key: None,
+			tls_client_ca: None,
+			interfaces: Vec::new(),
+			mounts: Vec::new(),
+			components: Vec::new(),
+			processes: Vec::new(),
+			containers: Vec::new(),
+			batteries: Vec::new(),
+			disabled_subsystems: Vec::new(),
+			timestamps: true,
+			all_metrics: false,
+			cpu_details: false,
+			memory_details: false,
+			swap_details: false,
+			storage_details: false,
+			network_details: false,
+		}
+	}
+
+	/// Reads a configuration file. The format is picked from the extension
+	/// (`.yaml` / `.yml` for YAML, anything else is parsed as TOML).
+	pub fn load(path: &Path) -> Result<Self, ConfigError> {
+		parse_file(path)
+	}
+
+	/// Returns the rules of the configuration followed by those of `rules_file`,
+	/// each with the key to report problems with it under.
+	pub fn load_rules(&self) -> Result<Vec<(String, RuleConfig)>, ConfigError> {
+		let mut rules: Vec<(String, RuleConfig)> = self
+			.rules
+			.iter()
+			.enumerate()
+			.map(|(i, rule)| (format!("rules[{}]", i), rule.clone()))
+			.collect();
+
+		if let Some(path) = &self.rules_file {
+			let file: RulesFile = parse_file(path)?;
+			for (i, rule) in file.rules.into_iter().enumerate() {
+				rules.push((format!("{}: rules[{}]", path.display(), i), rule));
+			}
+		}
+
+		for (i, (key, rule)) in rules.iter().enumerate() {
+			if rule.name.trim().is_empty() {
+				return Err(invalid(&format!("{}.name", key), "must not be empty"));
+			}
+
+			if rules[..i].iter().any(|(_, r)| r.name == rule.name) {
+				return Err(invalid(
+					&format!("{}.name", key),
+					&format!("`{}` is used by another rule", rule.name),
+				));
+			}
+		}
+
+		Ok(rules)
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		if self.address.trim().is_empty() {
+			return Err(invalid("address", "must not be empty"));
+		}
+
+		if self.cache == 0 {
+			return Err(invalid("cache", "must be at least 1 second"));
+		}
+
+		if self.collector_timeout == 0 {
+			return Err(invalid("collector_timeout", "must be at least 1 second"));
+		}
+
+		for (name, collector) in &self.collectors {
+			if collector::subsystem(name).is_none() {
+				let names: Vec<&str> = collector::subsystems().iter().map(|s| s.name).collect();
+				return Err(invalid(
+					&format!("collectors.{}", name),
+					&format!("unknown collector, expected one of {}", names.join(", ")),
+				));
+			}
+			if collector.interval == Some(0) {
+				return Err(invalid(
+					&format!("collectors.{}.interval", name),
+					"must be at least 1 second",
+				));
+			}
+			if collector.timeout == Some(0) {
+				return Err(invalid(
+					&format!("collectors.{}.timeout", name),
+					"must be at least 1 second",
+				));
+			}
+		}
+
+		// The archive works with milliseconds
+		if self.downsample_1h_after > u64::MAX / 1000 {
+			return Err(invalid("downsample_1h_after", "is too large"));
+		}
+		match self.downsample_1m_after.checked_add(86400) {
+			Some(minimum) if self.downsample_1h_after >= minimum => {}
+			Some(_) => {
+				return Err(invalid(
+					"downsample_1h_after",
+					"must be at least one day longer than downsample_1m_after",
+				))
+			}
+			None => return Err(invalid("downsample_1m_after", "is too large")),
+		}
+
+		if self.data_max_size == 0 {
+			return Err(invalid("data_max_size", "must be at least 1 MiB"));
+		}
+		if self.data_max_size.checked_mul(1024 * 1024).is_none() {
+			return Err(invalid("data_max_size", "is too large"));
+		}
+
+		if let Some(token) = &self.token {
+			if token.is_empty() {
+				return Err(invalid("token", "must not be empty"));
+			}
+		}
+
+		if self.token.is_some() && self.token_file.is_some() {
+			return Err(invalid("token_file", "can not be combined with token"));
+		}
+
+		for (i, token) in self.tokens.iter().enumerate() {
+			if token.name.trim().is_empty() {
+				return Err(invalid(&format!("tokens[{}].name", i), "must not be empty"));
+			}
+
+			if self.tokens[..i].iter().any(|t| t.name == token.name) {
+				return Err(invalid(
+					&format!("tokens[{}].name", i),
+					&format!("`{}` is used by another token", token.name),
+				));
+			}
+
+			let sources = [
+				token.token.is_some(),
+				token.hash.is_some(),
+				token.file.is_some(),
+				token.env.is_some(),
+			];
+			if sources.iter().filter(|set| **set).count() != 1 {
+				return Err(invalid(
+					&format!("tokens[{}]", i),
+					"exactly one of token, hash, file or env is required",
+				));
+			}
+		}
+
+		for (i, notifier) in self.notifiers.iter().enumerate() {
+			if notifier.name.trim().is_empty() {
+				return Err(invalid(
+					&format!("notifiers[{}].name", i),
+					"must not be empty",
+				));
+			}
+
+			if self.notifiers[..i].iter().any(|n| n.name == notifier.name) {
+				return Err(invalid(
+					&format!("notifiers[{}].name", i),
+					&format!("`{}` is used by another notifier", notifier.name),
+				));
+			}
+
+			if !notifier.url.starts_with("http://") && !notifier.url.starts_with("https://") {
+				return Err(invalid(
+					&format!("notifiers[{}].url", i),
+					"must be an http:// or https:// URL",
+				));
+			}
+
+			if notifier.kind == NotifierKind::Gotify && notifier.token.is_none() {
+				return Err(invalid(
+					&format!("notifiers[{}].token", i),
+					"is required for Gotify",
+				));
+			}
+
+			if notifier.template.is_some() && notifier.kind != NotifierKind::Webhook {
+				return Err(invalid(
+					&format!("notifiers[{}].template", i),
+					"is only supported for webhooks",
+				));
+			}
+		}
+
+		if let Some(smtp) = &self.smtp {
+			if smtp.host.trim().is_empty() {
+				return Err(invalid("smtp.host", "must not be empty"));
+			}
+
+			if smtp.to.is_empty() {
+				return Err(invalid("smtp.to", "needs at least one recipient"));
+			}
+
+			if smtp.username.is_some() != smtp.password.is_some() {
+				return Err(invalid(
+					"smtp.password",
+					"username and password have to be set together",
+				));
+			}
+		}
+
+		if let Some(remote_write) = &self.remote_write {
+			if !remote_write.url.starts_with("http://") && !remote_write.url.starts_with("https://") {
+				return Err(invalid(
+					"remote_write.url",
+					"must be an http:// or https:// URL",
+				));
+			}
+
+			if remote_write.username.is_some() != remote_write.password.is_some() {
+				return Err(invalid(
+					"remote_write.password",
+					"username and password have to be set together",
+				));
+			}
+
+			if remote_write.username.is_some() && remote_write.bearer_token.is_some() {
+				return Err(invalid(
+					"remote_write.bearer_token",
+					"can not be combined with username and password",
+				));
+			}
+
+			for name in remote_write.labels.keys() {
+				let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+					&& name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+					&& !name.starts_with("__");
+				if !valid {
+					return Err(invalid(
+						&format!("remote_write.labels.{}", name),
+						"is not a valid label name",
+					));
+				}
+			}
+
+			if remote_write.max_samples_per_send == 0 {
+				return Err(invalid(
+					"remote_write.max_samples_per_send",
+					"must be at least 1",
+				));
+			}
+
+			if remote_write.queue_capacity < remote_write.max_samples_per_send {
+				return Err(invalid(
+					"remote_write.queue_capacity",
+					"must be at least max_samples_per_send",
+				));
+			}
+
+			if remote_write.spool_max_size == 0 {
+				return Err(invalid(
+					"remote_write.spool_max_size",
+					"must be at least 1 MiB",
+				));
+			}
+		}
+
+		if let Some(influxdb) = &self.influxdb {
+			if !influxdb.url.starts_with("http://") && !influxdb.url.starts_with("https://") {
+				return Err(invalid(
+					"influxdb.url",
+					"must be an http:// or https:// URL",
+				));
+			}
+
+			let required = [
+				("influxdb.org", &influxdb.org),
+				("influxdb.bucket", &influxdb.bucket),
+				("influxdb.token", &influxdb.token),
+			];
+			for (key, value) in required {
+				if value.trim().is_empty() {
+					return Err(invalid(key, "must not be empty"));
+				}
+			}
+		}
+
+		if let Some(otlp) = &self.otlp {
+			if !otlp.endpoint.starts_with("http://") && !otlp.endpoint.starts_with("https://") {
+				return Err(invalid(
+					"otlp.endpoint",
+					"must be an http:// or https:// URL",
+				));
+			}
+
+			for name in otlp.headers.keys() {
+				if name.trim().is_empty() || name.contains(|c: char| c == ':' || c.is_whitespace()) {
+					return Err(invalid(
+						&format!("otlp.headers.{}", name),
+						"is not a valid header name",
+					));
+				}
+			}
+		}
+
+		let push_targets = [
+			(
+				"graphite",
+				self.graphite.as_ref().map(|g| (&g.address, &g.prefix)),
+			),
+			(
+				"statsd",
+				self.statsd.as_ref().map(|s| (&s.address, &s.prefix)),
+			),
+		];
+		for (section, target) in push_targets {
+			let Some((address, prefix)) = target else {
+				continue;
+			};
+
+			let valid_address = address
+				.rsplit_once(':')
+				.is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+			if !valid_address {
+				return Err(invalid(
+					&format!("{}.address", section),
+					"expected host:port",
+				));
+			}
+
+			let valid_prefix = prefix.is_empty()
+				|| prefix.split('.').all(|segment| {
+					!segment.is_empty()
+						&& segment
+							.chars()
+							.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+				});
+			if !valid_prefix {
+				return Err(invalid(
+					&format!("{}.prefix", section),
+					"expected dot separated letters, digits, `_` and `-`",
+				));
+			}
+		}
+
+		match (&self.tls_cert, &self.tls_key) {
+			(Some(_), None) => return Err(invalid("tls_key", "is required when tls_cert is set")),
+			(None, Some(_)) => return Err(invalid("tls_cert", "is required when tls_key is set")),
+			_ => {}
+		}
+
+		if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+			return Err(invalid("tls_client_ca", "requires tls_cert and tls_key"));
+		}
+
+		let lists = [
+			("interfaces", &self.interfaces),
+			("mounts", &self.mounts),
+			("components", &self.components),
+			("processes", &self.processes),
+			("containers", &self.containers),
+			("batteries", &self.batteries),
+		];
+
+		for (key, values) in lists {
+			for (i, value) in values.iter().enumerate() {
+				if value.trim().is_empty() {
+					return Err(invalid(&format!("{}[{}]", key, i), "must not be empty"));
+				}
+			}
+		}
+
+		for (i, name) in self.disabled_subsystems.iter().enumerate() {
+			if collector::subsystem(name).is_none() {
+				let names: Vec<&str> = collector::subsystems().iter().map(|s| s.name).collect();
+				return Err(invalid(
+					&format!("disabled_subsystems[{}]", i),
+					&format!(
+						"unknown subsystem `{}`, expected one of {}",
+						name,
+						names.join(", ")
+					),
+				));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Reads a TOML or YAML file, picking the format from the extension
+/// (`.yaml` / `.yml` for YAML, anything else is parsed as TOML).
+fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+	let content = fs::read_to_string(path).map_err(|error| ConfigError::Io {
+		path: path.to_path_buf(),
+		error,
+	})?;
+
+	let extension = path
+		.extension()
+		.map(|ext| ext.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	match extension.as_str() {
+		"yaml" | "yml" => serde_yaml::from_str(&content).map_err(|e| ConfigError::Parse {
+			path: path.to_path_buf(),
+			message: e.to_string(),
+		}),
+		_ => toml::from_str(&content).map_err(|e| ConfigError::Parse {
+			path: path.to_path_buf(),
+			message: e.to_string(),
+		}),
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+	ConfigError::Invalid {
+		key: key.to_string(),
+		message: message.to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn write(name: &str, content: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("config-{}-{}", std::process::id(), name));
+		fs::write(&path, content).unwrap();
+		path
+	}
+
+	fn load(name: &str, content: &str) -> Result<Config, ConfigError> {
+		let path = write(name, content);
+		let config = Config::load(&path);
+		let _ = fs::remove_file(&path);
+		config
+	}
+
+	fn invalid_key(content: &str) -> String {
+		let config = load("invalid.toml", content).unwrap();
+		match config.validate() {
+			Err(ConfigError::Invalid { key, .. }) => key,
+			result => panic!("expected an invalid value, got {:?}", result),
+		}
+	}
+
+	fn parse_error(name: &str, content: &str) -> String {
+		match load(name, content) {
+			Err(ConfigError::Parse { message, .. }) => message,
+			result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn accepts_the_defaults() {
+		Config::new().validate().unwrap();
+		load("empty.toml", "").unwrap().validate().unwrap();
+	}
+
+	#[test]
+	fn names_the_offending_key() {
+		assert_eq!(invalid_key("address = \" \""), "address");
+		assert_eq!(invalid_key("cache = 0"), "cache");
+		assert_eq!(
+			invalid_key("[collectors.gpu]\ninterval = 5"),
+			"collectors.gpu"
+		);
+		assert_eq!(
+			invalid_key("[collectors.cpu]\ntimeout = 0"),
+			"collectors.cpu.timeout"
+		);
+		assert_eq!(
+			invalid_key("downsample_1m_after = 86400\ndownsample_1h_after = 86400"),
+			"downsample_1h_after"
+		);
+		assert_eq!(
+			invalid_key("data_max_size = 18446744073709551615"),
+			"data_max_size"
+		);
+		assert_eq!(
+			invalid_key("token = \"a\"\ntoken_file = \"/run/token\""),
+			"token_file"
+		);
+		assert_eq!(
+			invalid_key(
+				"[[notifiers]]\nname = \"chat\"\nurl = \"http://localhost\"\n\
+				 [[notifiers]]\nname = \"chat\"\nurl = \"http://localhost\""
+			),
+			"notifiers[1].name"
+		);
+		assert_eq!(
+			invalid_key("[[notifiers]]\nname = \"chat\"\nurl = \"localhost\""),
+			"notifiers[0].url"
+		);
+
+		let error = Config {
+			cache: 0,
+			..Config::new()
+		}
+		.validate()
+		.unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"Invalid configuration value for `cache`: must be at least 1 second"
+		);
+	}
+
+	#[test]
+	fn rejects_unknown_keys() {
+		assert!(parse_error("top.toml", "prot = 9000").contains("unknown field `prot`"));
+		assert!(parse_error(
+			"nested.toml",
+			"[[notifiers]]\nname = \"chat\"\nurl = \"http://localhost\"\ncolour = \"red\""
+		)
+		.contains("unknown field `colour`"));
+		assert!(parse_error("top.yaml", "port: 9000\nprot: 9000\n").contains("unknown field `prot`"));
+	}
+}
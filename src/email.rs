@@ -0,0 +1,469 @@
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{Message, SmtpTransport, Transport};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::alerts::{Alert, AlertState};
+use crate::config::{Config, ConfigError, SmtpConfig};
+use crate::history;
+use crate::notify;
+
+/// Timeout of a single SMTP command.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Alerts waiting for the next digest beyond this are dropped, oldest first.
+const MAX_QUEUED: usize = 1000;
+
+/// Parsed `smtp` section of the configuration.
+#[derive(Clone)]
+pub struct EmailSettings {
+	config: SmtpConfig,
+	from: Mailbox,
+	to: Vec<Mailbox>,
+	digest_interval: Duration,
+}
+
+impl EmailSettings {
+	/// Returns `None` when no `smtp` section is configured.
+	pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+		let Some(smtp) = &config.smtp else {
+			return Ok(None);
+		};
+
+		let from = smtp
+			.from
+			.parse::<Mailbox>()
+			.map_err(|e| ConfigError::Invalid {
+				key: String::from("smtp.from"),
+				message: e.to_string(),
+			})?;
+
+		let mut to = Vec::new();
+		for (i, address) in smtp.to.iter().enumerate() {
+			to.push(
+				address
+					.parse::<Mailbox>()
+					.map_err(|e| ConfigError::Invalid {
+						key: format!("smtp.to[{}]", i),
+						message: e.to_string(),
+					})?,
+			);
+		}
+
+		let digest_interval =
+			history::parse_duration(&smtp.digest_interval).ok_or_else(|| ConfigError::Invalid {
+				key: String::from("smtp.digest_interval"),
+				message: String::from("expected a duration such as 90s, 5m or 1h"),
+			})?;
+
+		Ok(Some(EmailSettings {
+			config: smtp.clone(),
+			from,
+			to,
+			digest_interval,
+		}))
+	}
+}
+
+/// Sends alert emails. The first alert after a quiet period is sent right away, the
+/// ones that follow within the digest interval are collected and sent as one email.
+pub struct Mailer {
+	settings: RwLock<Option<EmailSettings>>,
+	queue: Mutex<Vec<Alert>>,
+	last_sent: Mutex<Option<Instant>>,
+}
+
+impl Mailer {
+	/// Creates the mailer and starts the thread that sends the queued alerts.
+	pub fn start(settings: Option<EmailSettings>) -> Arc<Self> {
+		let mailer = Arc::new(Mailer {
+			settings: RwLock::new(settings),
+			queue: Mutex::new(Vec::new()),
+			last_sent: Mutex::new(None),
+		});
+
+		let sender = mailer.clone();
+		std::thread::spawn(move || loop {
+			sleep(Duration::from_secs(1));
+			sender.flush();
+		});
+
+		mailer
+	}
+
+	pub fn set_settings(&self, settings: Option<EmailSettings>) {
+		if settings.is_none() {
+			self.queue.lock().unwrap().clear();
+		}
+		*self.settings.write().unwrap() = settings;
+	}
+
+	/// Queues `alert` for the next email if the configuration asks for it.
+	pub fn notify(&self, alert: &Alert) {
+		let settings = self.settings.read().unwrap();
+		let Some(settings) = settings.as_ref() else {
+			return;
+		};
+
+		let smtp = &settings.config;
+		if !smtp.rules.is_empty() && !smtp.rules.contains(&alert.rule) {
+			return;
+		}
+		if !smtp.send_resolved && alert.state == AlertState::Resolved {
+			return;
+		}
+
+		let mut queue = self.queue.lock().unwrap();
+		queue.push(alert.clone());
+		trim(&mut queue);
+	}
+
+	/// Sends the queued alerts once the digest interval since the last email has passed.
+	/// A failed email keeps its alerts queued for the next attempt.
+	fn flush(&self) {
+		let Some(settings) = self.settings.read().unwrap().clone() else {
+			return;
+		};
+
+		let last_sent = *self.last_sent.lock().unwrap();
+		if last_sent.is_some_and(|sent| sent.elapsed() < settings.digest_interval) {
+			return;
+		}
+
+		let alerts: Vec<Alert> = std::mem::take(&mut *self.queue.lock().unwrap());
+		if alerts.is_empty() {
+			return;
+		}
+
+		*self.last_sent.lock().unwrap() = Some(Instant::now());
+		match send(&settings, &alerts) {
+			Ok(()) => println!(
+				"Sent an alert email about {} notifications to {}",
+				alerts.len(),
+				settings.config.to.join(", ")
+			),
+			Err(e) => {
+				eprintln!(
+					"Failed to send an alert email through {}: {}, retrying in {}s",
+					settings.config.host,
+					e,
+					settings.digest_interval.as_secs()
+				);
+				let mut queue = self.queue.lock().unwrap();
+				let newer = std::mem::replace(&mut *queue, alerts);
+				queue.extend(newer);
+				trim(&mut queue);
+			}
+		}
+	}
+}
+
+/// Drops the oldest alerts beyond `MAX_QUEUED`.
+fn trim(queue: &mut Vec<Alert>) {
+	if queue.len() > MAX_QUEUED {
+		let dropped = queue.len() - MAX_QUEUED;
+		queue.drain(..dropped);
+	}
+}
+
+fn send(settings: &EmailSettings, alerts: &[Alert]) -> Result<(), Box<dyn std::error::Error>> {
+	let smtp = &settings.config;
+	let host_name = sysinfo::System::host_name().unwrap_or_else(|| String::from("unknown host"));
+
+	let mut builder = SmtpTransport::builder_dangerous(&smtp.host)
+		.port(smtp.port)
+		.timeout(Some(SMTP_TIMEOUT));
+	if smtp.starttls {
+		builder = builder.tls(Tls::Required(TlsParameters::new(smtp.host.clone())?));
+	}
+	if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+		builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+	}
+	let transport = builder.build();
+
+	let mut message = Message::builder()
+		.from(settings.from.clone())
+		.subject(subject(alerts, &host_name));
+	for to in &settings.to {
+		message = message.to(to.clone());
+	}
+	let message = message
+		.header(ContentType::TEXT_PLAIN)
+		.body(body(alerts, &host_name))?;
+
+	transport.send(&message)?;
+	Ok(())
+}
+
+fn subject(alerts: &[Alert], host_name: &str) -> String {
+	if let [alert] = alerts {
+		return format!("[{}] {}", host_name, notify::summary(alert));
+	}
+
+	let firing = alerts
+		.iter()
+		.filter(|alert| alert.state == AlertState::Firing)
+		.count();
+	format!(
+		"[{}] {} alert notifications ({} firing, {} resolved)",
+		host_name,
+		alerts.len(),
+		firing,
+		alerts.len() - firing
+	)
+}
+
+/// Text of the email: one section per alerting entry with its latest state, how often
+/// it fired and resolved since the last email, and the state of the entry.
+fn body(alerts: &[Alert], host_name: &str) -> String {
+	let mut groups: Vec<(&Alert, usize, usize)> = Vec::new();
+	for alert in alerts {
+		let index = match groups
+			.iter()
+			.position(|(a, _, _)| a.rule == alert.rule && a.entry == alert.entry)
+		{
+			Some(index) => index,
+			None => {
+				groups.push((alert, 0, 0));
+				groups.len() - 1
+			}
+		};
+
+		let group = &mut groups[index];
+		group.0 = alert;
+		match alert.state {
+			AlertState::Resolved => group.2 += 1,
+			_ => group.1 += 1,
+		}
+	}
+
+	let mut body = String::new();
+	let _ = writeln!(body, "Rabbit Monitor on {} reports:", host_name);
+
+	for (alert, fired, resolved) in groups {
+		let _ = writeln!(body);
+		let _ = writeln!(body, "{}", notify::summary(alert));
+		let _ = writeln!(body, "  Rule:          {}", alert.rule);
+		if let Some(severity) = &alert.severity {
+			let _ = writeln!(body, "  Severity:      {}", severity);
+		}
+		let _ = writeln!(body, "  Active since:  {}", format_time(alert.active_since));
+		if let Some(resolved_at) = alert.resolved_at {
+			let _ = writeln!(body, "  Resolved at:   {}", format_time(resolved_at));
+		}
+		if fired + resolved > 1 {
+			let _ = writeln!(
+				body,
+				"  Since the last email it fired {} and resolved {}.",
+				times(fired),
+				times(resolved)
+			);
+		}
+
+		if !alert.details.is_null() {
+			let details = serde_json::to_string_pretty(&alert.details).unwrap_or_default();
+			let _ = writeln!(body, "  State of {}:", alert.subsystem);
+			for line in details.lines() {
+				let _ = writeln!(body, "    {}", line);
+			}
+		}
+	}
+
+	body
+}
+
+fn times(count: usize) -> String {
+	match count {
+		1 => String::from("once"),
+		count => format!("{} times", count),
+	}
+}
+
+fn format_time(timestamp: Duration) -> String {
+	DateTime::<Utc>::from_timestamp(timestamp.as_secs() as i64, 0)
+		.map(|time| time.to_rfc3339())
+		.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{BufRead, BufReader, Write as _};
+	use std::net::TcpListener;
+	use std::sync::mpsc::{channel, Receiver};
+
+	/// Accepts SMTP sessions and passes on the data of every message, with folded
+	/// header and quoted-printable lines joined again.
+	fn smtp_sink() -> (u16, Receiver<String>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let (sender, receiver) = channel();
+
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let mut reader = BufReader::new(stream.try_clone().unwrap());
+				write!(stream, "220 localhost ESMTP\r\n").unwrap();
+
+				let mut data: Option<String> = None;
+				loop {
+					let mut line = String::new();
+					if reader.read_line(&mut line).unwrap() == 0 {
+						break;
+					}
+
+					if let Some(message) = data.as_mut() {
+						if line == ".\r\n" {
+							let message = data.take().unwrap();
+							let _ = sender.send(message.replace("=\r\n", "").replace("\r\n ", " "));
+							write!(stream, "250 Queued\r\n").unwrap();
+						} else {
+							message.push_str(&line);
+						}
+						continue;
+					}
+
+					let command = line.get(..4).unwrap_or_default().to_uppercase();
+					match command.as_str() {
+						"DATA" => {
+							data = Some(String::new());
+							write!(stream, "354 Go ahead\r\n").unwrap();
+						}
+						"QUIT" => {
+							write!(stream, "221 Bye\r\n").unwrap();
+							break;
+						}
+						_ => write!(stream, "250 OK\r\n").unwrap(),
+					}
+				}
+			}
+		});
+
+		(port, receiver)
+	}
+
+	/// A port that refuses connections.
+	fn closed_port() -> u16 {
+		TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.port()
+	}
+
+	fn mailer(port: u16) -> Mailer {
+		let config = Config {
+			smtp: Some(SmtpConfig {
+				host: String::from("127.0.0.1"),
+				port,
+				starttls: false,
+				username: None,
+				password: None,
+				from: String::from("monitor@example.com"),
+				to: vec![String::from("admin@example.com")],
+				digest_interval: String::from("1h"),
+				rules: Vec::new(),
+				send_resolved: true,
+			}),
+			..Config::default()
+		};
+
+		Mailer {
+			settings: RwLock::new(EmailSettings::from_config(&config).unwrap()),
+			queue: Mutex::new(Vec::new()),
+			last_sent: Mutex::new(None),
+		}
+	}
+
+	fn queued(mailer: &Mailer) -> Vec<(String, AlertState)> {
+		mailer
+			.queue
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|alert| (alert.rule.clone(), alert.state))
+			.collect()
+	}
+
+	#[test]
+	fn coalesces_alerts_within_the_digest_interval() {
+		let (port, messages) = smtp_sink();
+		let mailer = mailer(port);
+
+		mailer.notify(&Alert::for_test("DiskFull", None, AlertState::Firing));
+		mailer.flush();
+		let message = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert!(message.contains("[FIRING] DiskFull"));
+
+		mailer.notify(&Alert::for_test("LoadHigh", None, AlertState::Firing));
+		mailer.notify(&Alert::for_test("DiskFull", None, AlertState::Resolved));
+		mailer.flush();
+		assert_eq!(queued(&mailer).len(), 2);
+
+		// The digest interval has passed
+		*mailer.last_sent.lock().unwrap() = None;
+		mailer.flush();
+		let message = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert!(message.contains("2 alert notifications (1 firing, 1 resolved)"));
+		assert!(message.contains("[FIRING] LoadHigh"));
+		assert!(message.contains("[RESOLVED] DiskFull"));
+		assert!(queued(&mailer).is_empty());
+		assert!(messages.try_recv().is_err());
+	}
+
+	#[test]
+	fn keeps_the_alerts_of_a_failed_email_in_order() {
+		let mailer = mailer(closed_port());
+
+		mailer.notify(&Alert::for_test("DiskFull", None, AlertState::Firing));
+		mailer.notify(&Alert::for_test("LoadHigh", None, AlertState::Firing));
+		mailer.flush();
+		mailer.notify(&Alert::for_test("DiskFull", None, AlertState::Resolved));
+
+		assert_eq!(
+			queued(&mailer),
+			vec![
+				(String::from("DiskFull"), AlertState::Firing),
+				(String::from("LoadHigh"), AlertState::Firing),
+				(String::from("DiskFull"), AlertState::Resolved),
+			]
+		);
+	}
+
+	#[test]
+	fn describes_a_flapping_entry() {
+		let alerts: Vec<Alert> = [
+			AlertState::Firing,
+			AlertState::Resolved,
+			AlertState::Firing,
+			AlertState::Resolved,
+			AlertState::Firing,
+		]
+		.into_iter()
+		.map(|state| Alert::for_test("DiskFull", Some("/dev/sda1"), state))
+		.collect();
+
+		assert_eq!(
+			subject(&alerts, "host"),
+			"[host] 5 alert notifications (3 firing, 2 resolved)"
+		);
+
+		let text = body(&alerts, "host");
+		assert!(text.starts_with("Rabbit Monitor on host reports:\n\n[FIRING] DiskFull (/dev/sda1)\n"));
+		assert!(text.contains("  Since the last email it fired 3 times and resolved 2 times.\n"));
+		assert_eq!(text.matches("DiskFull (/dev/sda1)").count(), 1);
+
+		assert_eq!(
+			subject(&alerts[..1], "host"),
+			"[host] [FIRING] DiskFull (/dev/sda1)"
+		);
+		assert!(body(&alerts[..2], "host").contains("it fired once and resolved once."));
+		assert!(!body(&alerts[..1], "host").contains("Since the last email"));
+	}
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::Duration;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::metrics::{MetricFamily, MetricType, MetricsBuilder};
+
+/// Subprocesses that could not be started, were killed or exited with an error, by
+/// command and reason.
+static SUBPROCESS_FAILURES: LazyLock<Mutex<BTreeMap<(String, String), u64>>> =
+	LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+static DOCKER_STATS_RESTARTS: AtomicU64 = AtomicU64::new(0);
+
+/// Reused for every snapshot, which is assembled after every refresh, and only
+/// refreshed for the process of Rabbit Monitor.
+static PROCESS: LazyLock<Mutex<System>> = LazyLock::new(|| Mutex::new(System::new()));
+
+/// Counts a failed subprocess, `reason` is `spawn`, `timeout` or `exit`.
+pub fn record_subprocess_failure(command: &str, reason: &str) {
+	let mut failures = SUBPROCESS_FAILURES
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner);
+	*failures
+		.entry((command.to_string(), reason.to_string()))
+		.or_insert(0) += 1;
+}
+
+/// Counts a `docker stats` child that exited and was started again.
+pub fn record_docker_stats_restart() {
+	DOCKER_STATS_RESTARTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Adds the metrics about Rabbit Monitor itself that do not belong to a collector: its
+/// memory and CPU usage, failed subprocesses and restarts of `docker stats`. `created`
+/// is when counting started, in seconds since the Unix epoch.
+pub fn metrics(metrics: &mut MetricsBuilder, now: Duration, created: u64) {
+	metrics.subsystem("system");
+
+	if let Ok(pid) = sysinfo::get_current_pid() {
+		let mut system = PROCESS.lock().unwrap_or_else(PoisonError::into_inner);
+		system.refresh_processes_specifics(
+			ProcessesToUpdate::Some(&[pid]),
+			true,
+			ProcessRefreshKind::nothing().with_cpu().with_memory(),
+		);
+
+		if let Some(process) = system.process(pid) {
+			metrics.gauge(
+				"exporter_resident_memory",
+				"Resident memory of Rabbit Monitor",
+				process.memory() as f64,
+				Some("bytes"),
+				&[],
+				now,
+			);
+			metrics.header(
+				"exporter_cpu",
+				"CPU time used by Rabbit Monitor",
+				MetricType::Counter,
+				Some("seconds"),
+			);
+			metrics.counter_sample(
+				process.accumulated_cpu_time() as f64 / 1000.0,
+				&[],
+				now,
+				created,
+			);
+		}
+	}
+
+	let failures = SUBPROCESS_FAILURES
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner);
+	if !failures.is_empty() {
+		metrics.header(
+			"exporter_subprocess_failures",
+			"Number of subprocesses such as upsc, ipmitool or docker that failed",
+			MetricType::Counter,
+			None,
+		);
+		for ((command, reason), count) in failures.iter() {
+			metrics.counter_sample(
+				*count as f64,
+				&[("command", command), ("reason", reason)],
+				now,
+				created,
+			);
+		}
+	}
+	drop(failures);
+
+	metrics.header(
+		"exporter_docker_stats_restarts",
+		"Number of times docker stats exited and was started again",
+		MetricType::Counter,
+		None,
+	);
+	metrics.counter_sample(
+		DOCKER_STATS_RESTARTS.load(Ordering::Relaxed) as f64,
+		&[],
+		now,
+		created,
+	);
+}
+
+/// Counts the requests served by every endpoint and the time spent on them.
+pub struct Scrapes {
+	counts: Mutex<BTreeMap<String, (u64, Duration)>>,
+	created: u64,
+}
+
+impl Scrapes {
+	pub fn new(created: u64) -> Self {
+		Scrapes {
+			counts: Mutex::new(BTreeMap::new()),
+			created,
+		}
+	}
+
+	/// Records a request, `endpoint` is the route it matched, e.g. `/api/v1/{subsystem}`.
+	pub fn record(&self, endpoint: &str, duration: Duration) {
+		let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+		let (count, total) = counts
+			.entry(endpoint.to_string())
+			.or_insert((0, Duration::ZERO));
+		*count += 1;
+		*total += duration;
+	}
+
+	pub fn metrics(&self, now: Duration) -> Vec<MetricFamily> {
+		let counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+		let mut metrics = MetricsBuilder::new();
+		if counts.is_empty() {
+			return metrics.build();
+		}
+
+		metrics.subsystem("system");
+		metrics.header(
+			"exporter_scrapes",
+			"Number of requests served by an endpoint",
+			MetricType::Counter,
+			None,
+		);
+		for (endpoint, (count, _)) in counts.iter() {
+			metrics.counter_sample(*count as f64, &[("endpoint", endpoint)], now, self.created);
+		}
+
+		metrics.header(
+			"exporter_scrape_duration",
+			"Time spent serving requests to an endpoint",
+			MetricType::Counter,
+			Some("seconds"),
+		);
+		for (endpoint, (_, total)) in counts.iter() {
+			metrics.counter_sample(
+				total.as_secs_f64(),
+				&[("endpoint", endpoint)],
+				now,
+				self.created,
+			);
+		}
+
+		metrics.build()
+	}
+}
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::config::{Config, GraphiteConfig, StatsdConfig};
+use crate::influx;
+use crate::metrics::{MetricFamily, MetricType};
+use crate::monitor::system_info::SystemInfo;
+
+/// Timeout of connecting to Graphite and of a single write.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before the first reconnect, doubled after every failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Refreshes kept in memory while Graphite is down, the oldest are dropped first.
+const MAX_QUEUED: usize = 1000;
+
+/// Largest StatsD datagram, small enough to not be fragmented on common networks.
+const MAX_DATAGRAM: usize = 1432;
+
+/// Path, value and timestamp of a sample.
+type Point = (String, f64, Option<Duration>);
+
+/// Makes a value fit in one path segment by replacing everything but letters, digits,
+/// `_` and `-` with `_`, e.g. `/dev/sda1` becomes `dev_sda1` and `/` becomes `_`.
+pub fn sanitize(value: &str) -> String {
+	let mut out = String::with_capacity(value.len());
+	for c in value.chars() {
+		let c = match c.is_ascii_alphanumeric() || c == '-' {
+			true => c,
+			false => '_',
+		};
+		if c == '_' && out.ends_with('_') {
+			continue;
+		}
+		out.push(c);
+	}
+
+	match out.trim_matches('_') {
+		"" => String::from("_"),
+		trimmed => trimmed.to_string(),
+	}
+}
+
+/// Dotted path of every sample: the prefix, host name, measurement and labels followed
+/// by the field, e.g. `rabbitmonitor.web1.storage.device_dev_sda1.mount__.percent`.
+/// Labels are written as `name_value`, so samples whose label values only differ in
+/// which label they belong to get different paths. Info metrics are left out since
+/// Graphite and StatsD only store numbers.
+pub fn points(families: &[MetricFamily], prefix: &str, host_name: &str) -> Vec<Point> {
+	let mut base = String::new();
+	if !prefix.is_empty() {
+		base.push_str(prefix);
+		base.push('.');
+	}
+	base.push_str(&sanitize(host_name));
+
+	let mut points = Vec::new();
+	for family in families {
+		if family.metric_type == MetricType::Info {
+			continue;
+		}
+
+		let measurement = influx::measurement(family.subsystem.as_deref());
+		let field = sanitize(&influx::field(family));
+
+		for sample in &family.samples {
+			if !sample.value.is_finite() {
+				continue;
+			}
+
+			let mut path = format!("{}.{}", base, measurement);
+			for (name, value) in &sample.labels {
+				let _ = write!(path, ".{}_{}", sanitize(name), sanitize(value));
+			}
+			path.push('.');
+			path.push_str(&field);
+
+			points.push((path, sample.value, sample.timestamp));
+		}
+	}
+	points
+}
+
+/// Resolves `host:port`, preferring the first address returned.
+fn resolve(address: &str) -> std::io::Result<SocketAddr> {
+	address.to_socket_addrs()?.next().ok_or_else(|| {
+		std::io::Error::new(
+			std::io::ErrorKind::NotFound,
+			format!("{} did not resolve to an address", address),
+		)
+	})
+}
+
+/// Sends every refresh to Graphite in the plaintext protocol, reconnecting whenever
+/// the connection breaks.
+pub struct GraphiteWriter {
+	settings: RwLock<Option<GraphiteConfig>>,
+	queue: Mutex<VecDeque<String>>,
+	wakeup: Condvar,
+}
+
+impl GraphiteWriter {
+	/// Creates the writer and starts the thread that sends the queued refreshes.
+	pub fn start(config: &Config) -> Arc<Self> {
+		let writer = Arc::new(GraphiteWriter {
+			settings: RwLock::new(config.graphite.clone()),
+			queue: Mutex::new(VecDeque::new()),
+			wakeup: Condvar::new(),
+		});
+
+		let sender = writer.clone();
+		std::thread::spawn(move || sender.run());
+
+		writer
+	}
+
+	pub fn set_config(&self, config: &Config) {
+		if config.graphite.is_none() {
+			self.queue.lock().unwrap().clear();
+		}
+		*self.settings.write().unwrap() = config.graphite.clone();
+	}
+
+	/// Queues the samples of a refresh as `path value timestamp` lines.
+	pub fn push(&self, families: &[MetricFamily], system_info: &SystemInfo, now: Duration) {
+		let Some(settings) = self.settings.read().unwrap().clone() else {
+			return;
+		};
+
+		let mut body = String::new();
+		for (path, value, timestamp) in points(families, &settings.prefix, &system_info.host_name) {
+			let _ = writeln!(
+				body,
+				"{} {} {}",
+				path,
+				value,
+				timestamp.unwrap_or(now).as_secs()
+			);
+		}
+
+		let mut queue = self.queue.lock().unwrap();
+		queue.push_back(body);
+		if queue.len() > MAX_QUEUED {
+			queue.pop_front();
+			eprintln!("Graphite queue is full, dropped the oldest refresh");
+		}
+		self.wakeup.notify_one();
+	}
+
+	fn run(&self) {
+		let mut connection: Option<(String, TcpStream)> = None;
+		let mut backoff = INITIAL_BACKOFF;
+
+		loop {
+			let body = {
+				let mut queue = self.queue.lock().unwrap();
+				if queue.is_empty() {
+					let _ = self.wakeup.wait_timeout(queue, Duration::from_secs(1));
+					continue;
+				}
+				queue.pop_front().unwrap()
+			};
+			let Some(settings) = self.settings.read().unwrap().clone() else {
+				continue;
+			};
+
+			// Reconnect when the address changed on reload
+			if connection
+				.as_ref()
+				.is_some_and(|(address, _)| *address != settings.address)
+			{
+				connection = None;
+			}
+
+			let result = match &mut connection {
+				Some((_, stream)) => stream.write_all(body.as_bytes()),
+				None => connect(&settings.address).and_then(|mut stream| {
+					stream.write_all(body.as_bytes())?;
+					connection = Some((settings.address.clone(), stream));
+					Ok(())
+				}),
+			};
+
+			match result {
+				Ok(()) => backoff = INITIAL_BACKOFF,
+				Err(e) => {
+					connection = None;
+
+					// Put the refresh back in front of the newer ones
+					let mut queue = self.queue.lock().unwrap();
+					if queue.len() < MAX_QUEUED {
+						queue.push_front(body);
+					}
+					drop(queue);
+
+					eprintln!(
+						"Failed to send metrics to Graphite at {}, reconnecting in {}s: {}",
+						settings.address,
+						backoff.as_secs(),
+						e
+					);
+					std::thread::sleep(backoff);
+					backoff = (backoff * 2).min(MAX_BACKOFF);
+				}
+			}
+		}
+	}
+}
+
+fn connect(address: &str) -> std::io::Result<TcpStream> {
+	let stream = TcpStream::connect_timeout(&resolve(address)?, CONNECT_TIMEOUT)?;
+	stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+	Ok(stream)
+}
+
+/// Sends every refresh to StatsD as gauges. The socket is opened again after a failed
+/// send, so a changed DNS record of the server is picked up.
+pub struct StatsdSender {
+	settings: RwLock<Option<StatsdConfig>>,
+	socket: Mutex<Option<(String, UdpSocket)>>,
+}
+
+impl StatsdSender {
+	pub fn new(config: &Config) -> Self {
+		StatsdSender {
+			settings: RwLock::new(config.statsd.clone()),
+			socket: Mutex::new(None),
+		}
+	}
+
+	pub fn set_config(&self, config: &Config) {
+		*self.settings.write().unwrap() = config.statsd.clone();
+	}
+
+	/// Sends the samples of a refresh as `path:value|g` lines, packed into as few
+	/// datagrams as possible.
+	pub fn push(&self, families: &[MetricFamily], system_info: &SystemInfo) {
+		let Some(settings) = self.settings.read().unwrap().clone() else {
+			return;
+		};
+
+		let mut datagrams: Vec<String> = Vec::new();
+		let mut datagram = String::new();
+		for (path, value, _) in points(families, &settings.prefix, &system_info.host_name) {
+			// A leading sign means a change of the gauge, so negative values are set from 0
+			let line = match value < 0.0 {
+				true => format!("{}:0|g\n{}:{}|g", path, path, value),
+				false => format!("{}:{}|g", path, value),
+			};
+
+			if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+				datagrams.push(std::mem::take(&mut datagram));
+			}
+			if !datagram.is_empty() {
+				datagram.push('\n');
+			}
+			datagram.push_str(&line);
+		}
+		if !datagram.is_empty() {
+			datagrams.push(datagram);
+		}
+
+		let mut socket = self.socket.lock().unwrap();
+		if socket
+			.as_ref()
+			.is_some_and(|(address, _)| *address != settings.address)
+		{
+			*socket = None;
+		}
+		if socket.is_none() {
+			match open(&settings.address) {
+				Ok(opened) => *socket = Some((settings.address.clone(), opened)),
+				Err(e) => {
+					eprintln!(
+						"Failed to open a socket to StatsD at {}: {}",
+						settings.address, e
+					);
+					return;
+				}
+			}
+		}
+
+		let (_, udp) = socket.as_ref().unwrap();
+		for datagram in &datagrams {
+			if let Err(e) = udp.send(datagram.as_bytes()) {
+				eprintln!(
+					"Failed to send metrics to StatsD at {}: {}",
+					settings.address, e
+				);
+				*socket = None;
+				return;
+			}
+		}
+	}
+}
+
+fn open(address: &str) -> std::io::Result<UdpSocket> {
+	let address = resolve(address)?;
+	let local = match address {
+		SocketAddr::V4(_) => "0.0.0.0:0",
+		SocketAddr::V6(_) => "[::]:0",
+	};
+
+	let socket = UdpSocket::bind(local)?;
+	socket.connect(address)?;
+	Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::metrics::MetricsBuilder;
+
+	#[test]
+	fn sanitizes_path_segments() {
+		assert_eq!(sanitize("/dev/sda1"), "dev_sda1");
+		assert_eq!(sanitize("/"), "_");
+		assert_eq!(sanitize("web-1.example.com"), "web-1_example_com");
+	}
+
+	#[test]
+	fn paths_include_label_names() {
+		let mut metrics = MetricsBuilder::new();
+		metrics.subsystem("storage");
+		metrics.header(
+			"storage",
+			"Storage usage",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		metrics.sample(
+			41.2,
+			&[("device", "/dev/sda1"), ("mount", "/")],
+			Duration::ZERO,
+		);
+		metrics.subsystem("processes");
+		metrics.header(
+			"process_cpu",
+			"Process CPU usage",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		metrics.sample(1.0, &[("pid", "42"), ("name", "")], Duration::ZERO);
+		metrics.sample(2.0, &[("pid", ""), ("name", "42")], Duration::ZERO);
+
+		let paths: Vec<String> = points(&metrics.build(), "rabbitmonitor", "web1")
+			.into_iter()
+			.map(|(path, _, _)| path)
+			.collect();
+		assert_eq!(
+			paths[0],
+			"rabbitmonitor.web1.storage.device_dev_sda1.mount__.percent"
+		);
+		assert_ne!(paths[1], paths[2]);
+	}
+}
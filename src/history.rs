@@ -0,0 +1,169 @@
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::auth::Scopes;
+use crate::metrics::{MetricFamily, MetricType};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct SeriesKey {
+	/// Metric name as exposed, e.g. `rabbit_cpu_load_percent`
+	name: String,
+	labels: Vec<(String, String)>,
+}
+
+struct Series {
+	subsystem: Option<String>,
+	/// Sample name, which differs from the metric name for counters
+	sample_name: String,
+	points: VecDeque<(Duration, f64)>,
+}
+
+/// Keeps the samples of every metric for a limited time, so the moments before
+/// a scrape gap can still be inspected.
+pub struct History {
+	retention: Duration,
+	series: HashMap<SeriesKey, Series>,
+}
+
+impl History {
+	pub fn new(retention: Duration) -> Self {
+		History {
+			retention,
+			series: HashMap::new(),
+		}
+	}
+
+	pub fn set_retention(&mut self, retention: Duration) {
+		self.retention = retention;
+	}
+
+	/// Appends the samples of a refresh. Info metrics are left out, and a sample is
+	/// only stored once even if its subsystem was not refreshed in between.
+	pub fn record(&mut self, families: &[MetricFamily], now: Duration) {
+		if self.retention.is_zero() {
+			self.series.clear();
+			return;
+		}
+
+		for family in families {
+			if family.metric_type == MetricType::Info {
+				continue;
+			}
+
+			let name = family.full_name();
+			for sample in &family.samples {
+				let key = SeriesKey {
+					name: name.clone(),
+					labels: sample.labels.clone(),
+				};
+				let series = self.series.entry(key).or_insert_with(|| Series {
+					subsystem: family.subsystem.clone(),
+					sample_name: family.sample_name(),
+					points: VecDeque::new(),
+				});
+
+				let timestamp = sample.timestamp.unwrap_or(now);
+				if series.points.back().is_some_and(|(t, _)| *t >= timestamp) {
+					continue;
+				}
+				series.points.push_back((timestamp, sample.value));
+			}
+		}
+
+		self.prune(now);
+	}
+
+	/// Drops points older than the retention, and series that have no points left.
+	fn prune(&mut self, now: Duration) {
+		let oldest = now.saturating_sub(self.retention);
+
+		self.series.retain(|_, series| {
+			while series.points.front().is_some_and(|(t, _)| *t < oldest) {
+				series.points.pop_front();
+			}
+			!series.points.is_empty()
+		});
+	}
+
+	/// Returns the series of a metric with their points since `since`. The metric can
+	/// be given by its name or, for counters, by its `_total` sample name. Every entry
+	/// of `labels` has to match, and only series granted by `scopes` are returned.
+	pub fn query(
+		&self,
+		metric: &str,
+		since: Duration,
+		labels: &HashMap<String, String>,
+		scopes: &Scopes,
+	) -> Value {
+		let mut series: Vec<(&SeriesKey, &Series)> = self
+			.series
+			.iter()
+			.filter(|(key, series)| key.name == metric || series.sample_name == metric)
+			.filter(|(key, _)| {
+				labels
+					.iter()
+					.all(|(name, value)| key.labels.iter().any(|(k, v)| k == name && v == value))
+			})
+			.filter(|(key, series)| scopes.allows_sample(series.subsystem.as_deref(), &key.labels))
+			.collect();
+		series.sort_by(|(a, _), (b, _)| a.labels.cmp(&b.labels));
+
+		let series: Vec<Value> = series
+			.into_iter()
+			.map(|(key, series)| {
+				let labels: Map<String, Value> = key
+					.labels
+					.iter()
+					.map(|(k, v)| (k.clone(), json!(v)))
+					.collect();
+				let points: Vec<Value> = series
+					.points
+					.iter()
+					.filter(|(t, _)| *t >= since)
+					.map(|(t, value)| json!([t.as_secs_f64(), value]))
+					.collect();
+
+				json!({
+					"labels": labels,
+					"samples": points,
+				})
+			})
+			.collect();
+
+		json!({
+			"metric": metric,
+			"retention": self.retention.as_secs(),
+			"series": series,
+		})
+	}
+}
+
+/// Parses a time parameter: either seconds since the Unix epoch, or a duration
+/// before `now` such as `90s`, `15m`, `1h` or `1d`.
+pub fn parse_time(value: &str, now: Duration) -> Option<Duration> {
+	if let Ok(seconds) = value.parse::<f64>() {
+		return Duration::try_from_secs_f64(seconds).ok();
+	}
+
+	Some(now.saturating_sub(parse_duration(value)?))
+}
+
+/// Parses a duration such as `90s`, `15m`, `1h` or `1d`. A plain number is taken as seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+	if let Ok(seconds) = value.parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+
+	let unit = value.chars().last()?;
+	let amount: u64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+	let seconds = match unit {
+		's' => Some(amount),
+		'm' => amount.checked_mul(60),
+		'h' => amount.checked_mul(3600),
+		'd' => amount.checked_mul(86400),
+		_ => None,
+	}?;
+
+	Some(Duration::from_secs(seconds))
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::config::{Config, InfluxConfig};
+use crate::metrics::{MetricFamily, Sample};
+
+/// Timeout of a single write.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before the first retry, doubled after every failed write.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Refreshes kept in memory while InfluxDB is down, the oldest are dropped first.
+const MAX_QUEUED: usize = 1000;
+
+/// Measurement the samples of a subsystem are written to.
+pub fn measurement(subsystem: Option<&str>) -> &'static str {
+	match subsystem {
+		Some("system") => "system",
+		Some("cpu") => "cpu",
+		Some("memory") => "memory",
+		Some("swap") => "swap",
+		Some("energy") => "energy",
+		Some("storage") => "storage",
+		Some("network") => "network",
+		Some("components") => "component",
+		Some("ups") => "ups",
+		Some("batteries") => "battery",
+		Some("containers") => "docker",
+		Some("processes") => "process",
+		_ => "rabbitmonitor",
+	}
+}
+
+/// Name of the field a metric family is written to: its name without the `rabbit_`
+/// and measurement prefixes, e.g. `read_speed_bytes_per_second` for
+/// `rabbit_storage_read_speed_bytes_per_second`.
+pub fn field(family: &MetricFamily) -> String {
+	let measurement = measurement(family.subsystem.as_deref());
+	let name = family.sample_name();
+	let name = name.strip_prefix("rabbit_").unwrap_or(&name);
+	name
+		.strip_prefix(measurement)
+		.and_then(|rest| rest.strip_prefix('_'))
+		.unwrap_or(name)
+		.to_string()
+}
+
+/// Measurement with tags, timestamp and fields of one line.
+type Line = (String, Option<Duration>, Vec<(String, f64)>);
+
+/// Renders metric families as InfluxDB line protocol. Every subsystem becomes a
+/// measurement, labels become tags and the metrics of a subsystem become fields,
+/// e.g. `storage,device=/dev/sda1,mount=/ percent=41.2,used_bytes=...`.
+/// Samples without a timestamp get `fallback`, or none if that is `None`.
+pub fn encode(
+	families: &[MetricFamily],
+	tags: &HashMap<String, String>,
+	timestamps: bool,
+	fallback: Option<Duration>,
+) -> String {
+	// Fields of one line, keyed by measurement, tags and timestamp in the order they appear
+	let mut lines: Vec<Line> = Vec::new();
+	let mut index: HashMap<(String, Option<Duration>), usize> = HashMap::new();
+
+	for family in families {
+		let measurement = measurement(family.subsystem.as_deref());
+		let field = field(family);
+
+		for sample in &family.samples {
+			if !sample.value.is_finite() {
+				continue;
+			}
+
+			let series = format!("{}{}", escape(measurement, ", "), tag_set(sample, tags));
+			let timestamp = match timestamps {
+				true => sample.timestamp.or(fallback),
+				false => None,
+			};
+
+			let position = *index.entry((series.clone(), timestamp)).or_insert_with(|| {
+				lines.push((series, timestamp, Vec::new()));
+				lines.len() - 1
+			});
+			lines[position].2.push((field.clone(), sample.value));
+		}
+	}
+
+	let mut out = String::new();
+	for (series, timestamp, fields) in lines {
+		let fields: Vec<String> = fields
+			.iter()
+			.map(|(name, value)| format!("{}={}", escape(name, ",= "), value))
+			.collect();
+		let _ = write!(out, "{} {}", series, fields.join(","));
+		if let Some(timestamp) = timestamp {
+			let _ = write!(out, " {}", timestamp.as_nanos());
+		}
+		out.push('\n');
+	}
+	out
+}
+
+/// Tags of a sample with the additional `tags`, sorted by key as InfluxDB prefers.
+/// Empty values are left out since line protocol does not allow them.
+fn tag_set(sample: &Sample, tags: &HashMap<String, String>) -> String {
+	let mut pairs: Vec<(&str, &str)> = sample
+		.labels
+		.iter()
+		.map(|(k, v)| (k.as_str(), v.as_str()))
+		.collect();
+	for (key, value) in tags {
+		if !pairs.iter().any(|(k, _)| k == key) {
+			pairs.push((key, value));
+		}
+	}
+	pairs.sort();
+
+	let mut out = String::new();
+	for (key, value) in pairs {
+		if value.is_empty() {
+			continue;
+		}
+		let _ = write!(out, ",{}={}", escape(key, ",= "), escape(value, ",= "));
+	}
+	out
+}
+
+/// Escapes `special` characters and backslashes with a backslash. Line breaks can not
+/// be escaped in line protocol, they are replaced with spaces, e.g. in the model of a
+/// UPS or the name of a process.
+fn escape(value: &str, special: &str) -> String {
+	let mut out = String::with_capacity(value.len());
+	for c in value.chars() {
+		let c = match c {
+			'\n' | '\r' => ' ',
+			c => c,
+		};
+		if c == '\\' || special.contains(c) {
+			out.push('\\');
+		}
+		out.push(c);
+	}
+	out
+}
+
+/// Writes every refresh to an InfluxDB v2 `/api/v2/write` endpoint.
+pub struct InfluxWriter {
+	settings: RwLock<Option<InfluxConfig>>,
+	queue: Mutex<VecDeque<String>>,
+	wakeup: Condvar,
+	agent: ureq::Agent,
+}
+
+impl InfluxWriter {
+	/// Creates the writer and starts the thread that sends the queued refreshes.
+	pub fn start(config: &Config) -> Arc<Self> {
+		let agent: ureq::Agent = ureq::Agent::config_builder()
+			.timeout_global(Some(REQUEST_TIMEOUT))
+			.http_status_as_error(false)
+			.build()
+			.into();
+
+		let writer = Arc::new(InfluxWriter {
+			settings: RwLock::new(config.influxdb.clone()),
+			queue: Mutex::new(VecDeque::new()),
+			wakeup: Condvar::new(),
+			agent,
+		});
+
+		let sender = writer.clone();
+		std::thread::spawn(move || sender.run());
+
+		writer
+	}
+
+	pub fn set_config(&self, config: &Config) {
+		if config.influxdb.is_none() {
+			self.queue.lock().unwrap().clear();
+		}
+		*self.settings.write().unwrap() = config.influxdb.clone();
+	}
+
+	/// Queues the samples of a refresh.
+	pub fn push(&self, families: &[MetricFamily], now: Duration) {
+		let Some(settings) = self.settings.read().unwrap().clone() else {
+			return;
+		};
+
+		let body = encode(families, &settings.tags, true, Some(now));
+		let mut queue = self.queue.lock().unwrap();
+		queue.push_back(body);
+		if queue.len() > MAX_QUEUED {
+			queue.pop_front();
+			eprintln!("InfluxDB write queue is full, dropped the oldest refresh");
+		}
+		self.wakeup.notify_one();
+	}
+
+	fn run(&self) {
+		let mut backoff = INITIAL_BACKOFF;
+
+		loop {
+			let body = {
+				let mut queue = self.queue.lock().unwrap();
+				if queue.is_empty() {
+					let _ = self.wakeup.wait_timeout(queue, Duration::from_secs(1));
+					continue;
+				}
+				queue.pop_front().unwrap()
+			};
+			let Some(settings) = self.settings.read().unwrap().clone() else {
+				continue;
+			};
+
+			let url = format!("{}/api/v2/write", settings.url.trim_end_matches('/'));
+			let result = self
+				.agent
+				.post(&url)
+				.query("org", &settings.org)
+				.query("bucket", &settings.bucket)
+				.query("precision", "ns")
+				.header("Authorization", format!("Token {}", settings.token))
+				.header("Content-Type", "text/plain; charset=utf-8")
+				.send(body.as_str());
+
+			let failure = match result {
+				Ok(mut response) => match response.status().as_u16() {
+					200..=299 => None,
+					status @ (429 | 500..=599) => Some(format!("HTTP status {}", status)),
+					status => {
+						let message = response.body_mut().read_to_string().unwrap_or_default();
+						eprintln!(
+							"InfluxDB rejected a write with HTTP status {}, dropping it: {}",
+							status,
+							message.trim()
+						);
+						None
+					}
+				},
+				Err(e) => Some(e.to_string()),
+			};
+
+			match failure {
+				None => backoff = INITIAL_BACKOFF,
+				Some(reason) => {
+					// Put the refresh back in front of the newer ones
+					let mut queue = self.queue.lock().unwrap();
+					if queue.len() < MAX_QUEUED {
+						queue.push_front(body);
+					}
+					drop(queue);
+
+					eprintln!(
+						"Failed to write to InfluxDB at {}, retrying in {}s: {}",
+						settings.url,
+						backoff.as_secs(),
+						reason
+					);
+					std::thread::sleep(backoff);
+					backoff = (backoff * 2).min(MAX_BACKOFF);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn escapes_special_characters() {
+		assert_eq!(escape("a,b=c d", ",= "), "a\\,b\\=c\\ d");
+		assert_eq!(escape("C:\\", ",= "), "C:\\\\");
+		assert_eq!(escape("cpu usage", ", "), "cpu\\ usage");
+	}
+
+	#[test]
+	fn replaces_line_breaks() {
+		assert_eq!(escape("Smart-UPS\r\n1500", ",= "), "Smart-UPS\\ \\ 1500");
+		assert!(!escape("a\nb", ", ").contains('\n'));
+	}
+}
@@ -1,25 +1,83 @@
-use axum::extract::State;
-use axum::http::{header, HeaderValue, StatusCode};
-use axum::response::IntoResponse;
-use axum::{response::Html, routing::get, Router};
-use axum_extra::headers::{authorization::Bearer, Authorization};
-use axum_extra::TypedHeader;
-use clap::Parser;
+use axum::extract::{ConnectInfo, MatchedPath, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{response::Html, Extension, Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::Utc;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use futures_util::stream;
 use monitor::Monitor;
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::{thread::sleep, time::Duration};
+use serde_json::json;
+use starship_battery::units::thermodynamic_temperature::degree_celsius;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast::{self, error::RecvError};
 
+use crate::alerts::{AlertState, Alerts};
+use crate::api::ApiError;
+use crate::archive::{Archive, ArchiveSettings};
+use crate::auth::{AuthFailure, AuthFailures, Authenticator, Client, Credentials};
+use crate::config::{Config, ConfigError};
+use crate::email::{EmailSettings, Mailer};
+use crate::exporter::Scrapes;
+use crate::graphite::{GraphiteWriter, StatsdSender};
+use crate::history::History;
+use crate::influx::InfluxWriter;
+use crate::metrics::{Format, MetricFamily};
+use crate::monitor::battery::Battery;
 use crate::monitor::docker::DockerMonitor;
 use crate::monitor::energy::Energy;
-use crate::monitor::settings::EnergySettings;
+use crate::monitor::scheduler;
+use crate::monitor::settings::{EnergySettings, Settings};
+use crate::monitor::snapshot::Snapshot;
 use crate::monitor::ups::UPS;
+use crate::notify::Notifier;
+use crate::otlp::OtlpExporter;
+use crate::remote_write::RemoteWriter;
+use crate::tls::TlsFiles;
 
+pub mod alerts;
+pub mod api;
+pub mod archive;
+pub mod auth;
+pub mod config;
+pub mod email;
+pub mod exporter;
+pub mod graphite;
+pub mod history;
+pub mod influx;
+pub mod metrics;
 pub mod monitor;
+pub mod notify;
+pub mod otlp;
+pub mod protobuf;
+pub mod remote_write;
+pub mod tls;
 pub mod utils;
 
-#[derive(Parser, Debug)]
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// How often persisted samples are downsampled and the size cap is enforced.
+const ARCHIVE_COMPACT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+	/// Path to a TOML or YAML configuration file (command line flags take precedence)
+	#[arg(long)]
+	config: Option<PathBuf>,
+
 	/// Bind the server to specific address
 	#[arg(short, long, default_value_t = String::from("0.0.0.0"))]
 	address: String,
@@ -32,10 +90,62 @@ struct Args {
 	#[arg(short, long, default_value_t = 3)]
 	cache: u64,
 
-	/// Bearer token for authentication (optional)
-	#[arg(short, long)]
+	/// Seconds a collector may take to refresh before its subprocesses are killed
+	#[arg(long, default_value_t = 10)]
+	collector_timeout: u64,
+
+	/// How long the history of every metric is kept in memory in seconds (0 disables it)
+	#[arg(long, default_value_t = 3600)]
+	history_retention: u64,
+
+	/// Directory to persist the history of every metric in (disabled by default)
+	#[arg(long)]
+	data_dir: Option<PathBuf>,
+
+	/// Age in seconds after which persisted samples are downsampled to 1-minute aggregates
+	#[arg(long, default_value_t = 86400)]
+	downsample_1m_after: u64,
+
+	/// Age in seconds after which 1-minute aggregates are downsampled to 1-hour aggregates
+	#[arg(long, default_value_t = 2592000)]
+	downsample_1h_after: u64,
+
+	/// Maximum size of the data directory in MiB, the oldest data is removed first
+	#[arg(long, default_value_t = 1024)]
+	data_max_size: u64,
+
+	/// Path to a TOML or YAML file with alert rules
+	#[arg(long)]
+	rules_file: Option<PathBuf>,
+
+	/// Bearer token for authentication (optional, prefer --token-file or the environment variable)
+	#[arg(short, long, env = "RABBITMONITOR_TOKEN", hide_env_values = true)]
 	token: Option<String>,
 
+	/// Path to a file containing the Bearer token
+	#[arg(long)]
+	token_file: Option<PathBuf>,
+
+	/// Read a token from standard input, print its hash for the `tokens` configuration and exit
+	#[arg(long)]
+	hash_token: bool,
+
+	/// Log every authenticated request together with the name of its token
+	#[arg(long, default_value_t = false)]
+	access_log: bool,
+
+	/// Path to a PEM certificate (chain) to serve HTTPS with
+	#[arg(long)]
+	tls_cert: Option<PathBuf>,
+
+	/// Path to the PEM private key of the TLS certificate
+	#[arg(long)]
+	tls_key: Option<PathBuf>,
+
+	/// Path to a PEM CA bundle used to verify client certificates (enables mutual TLS)
+	#[arg(long)]
+	tls_client_ca: Option<PathBuf>,
+
 	/// Show available network interfaces and exit
 	#[arg(long)]
 	interface_list: bool,
@@ -84,6 +194,18 @@ struct Args {
 	#[arg(long, value_delimiter = ',')]
 	containers: Vec<String>,
 
+	/// Comma-separated list of batteries to monitor (e.g., "BAT0,BAT1")
+	#[arg(long, value_delimiter = ',')]
+	batteries: Vec<String>,
+
+	/// Comma-separated list of subsystems not to collect (e.g., "processes,containers")
+	#[arg(long, value_delimiter = ',')]
+	disabled_subsystems: Vec<String>,
+
+	/// Leave sample timestamps out of the /metrics output
+	#[arg(long, default_value_t = false)]
+	no_timestamps: bool,
+
 	/// Enable all detailed metrics
 	#[arg(long, default_value_t = false)]
 	all_metrics: bool,
@@ -111,10 +233,25 @@ struct Args {
 
 #[tokio::main]
 async fn main() {
-	let args: Args = Args::parse();
-	let monitor: Arc<Mutex<Monitor>> = Arc::new(Mutex::new(Monitor::new()));
-	let cloned: Arc<Mutex<Monitor>> = monitor.clone();
-	let token: Option<String> = args.token.clone();
+	let matches: ArgMatches = Args::command().get_matches();
+	let args: Args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+	if args.hash_token {
+		let mut token = String::new();
+		if let Err(e) = std::io::stdin().read_line(&mut token) {
+			eprintln!("Failed to read token: {}", e);
+			std::process::exit(1);
+		}
+
+		let token = token.trim();
+		if token.is_empty() {
+			eprintln!("Token must not be empty");
+			std::process::exit(1);
+		}
+
+		println!("{}", auth::format_hash(&auth::hash_token(token)));
+		return;
+	}
 
 	if args.interface_list {
 		let interfaces = sysinfo::Networks::new_with_refreshed_list();
@@ -198,15 +335,19 @@ async fn main() {
 		for ups_name in upses {
 			println!("- {}", ups_name);
 
-			match UPS::get_ups_data(&ups_name, Duration::from_secs(0)) {
-				Some(ups) => {
+			match UPS::get_ups_data(
+				&ups_name,
+				Duration::from_secs(0),
+				Duration::from_secs(args.collector_timeout),
+			) {
+				Ok(ups) => {
 					println!("  - Model: {}", ups.model);
 					println!("  - Status: {}", ups.status);
 					println!("  - Load: {:.1}%", ups.load_percent);
 					println!("  - Charge: {:.1}%", ups.charge_percent);
 				}
-				None => {
-					println!("  - Failed to fetch UPS data.");
+				Err(e) => {
+					println!("  - Failed to fetch UPS data: {}", e);
 				}
 			}
 		}
@@ -214,169 +355,938 @@ async fn main() {
 	}
 
 	if args.battery_list {
-		match starship_battery::Manager::new() {
-			Ok(manager) => match manager.batteries() {
-				Ok(batteries) => {
-					println!("Available batteries:");
-					for (i, battery) in batteries.enumerate() {
-						match battery {
-							Ok(bat) => {
-								println!("\nBattery #{}:", i + 1);
-								println!("  - Vendor: {}", bat.vendor().unwrap_or("Unknown"));
-								println!("  - Model: {}", bat.model().unwrap_or("Unknown"));
-								println!("  - Serial: {}", bat.serial_number().unwrap_or("Unknown"));
-								println!("  - Technology: {:?}", bat.technology());
-								println!("  - State: {:?}", bat.state());
-								println!("  - Charge: {:.1}%", bat.state_of_charge().value * 100.0);
-								println!(
-									"  - Energy: {:.2} Wh / {:.2} Wh",
-									bat.energy().value / 3600.0,
-									bat.energy_full().value / 3600.0
-								);
-								println!("  - Health: {:.2}%", bat.state_of_health().value * 100.0);
-								println!("  - Voltage: {:.2} V", bat.voltage().value);
-								if let Some(temp) = bat.temperature() {
-									println!("  - Temperature: {:.1}°C", temp.value);
-								}
-								if let Some(cycles) = bat.cycle_count() {
-									println!("  - Cycle count: {}", cycles);
-								}
-								if let Some(time) = bat.time_to_full() {
-									println!("  - Time to charge: {:.2} minutes", time.value / 60.0);
-								}
-								if let Some(time) = bat.time_to_empty() {
-									println!("  - Time to discharge: {:.2} minutes", time.value / 60.0);
-								}
-								println!();
-							}
-							Err(e) => {
-								println!("  - Error reading battery info: {}", e);
-							}
-						}
-					}
-				}
-				Err(e) => {
-					println!("Failed to list batteries: {}", e);
-				}
-			},
+		let batteries = match Battery::detect_batteries() {
+			Ok(batteries) => batteries,
 			Err(e) => {
-				println!("Failed to initialize battery manager: {}", e);
+				println!("{}", e);
+				return;
+			}
+		};
+
+		if batteries.is_empty() {
+			println!("No batteries detected.");
+			return;
+		}
+
+		println!("Available batteries:");
+		for (name, bat) in batteries {
+			println!("\n{}:", name);
+			println!("  - Vendor: {}", bat.vendor().unwrap_or("Unknown"));
+			println!("  - Model: {}", bat.model().unwrap_or("Unknown"));
+			println!("  - Serial: {}", bat.serial_number().unwrap_or("Unknown"));
+			println!("  - Technology: {:?}", bat.technology());
+			println!("  - State: {:?}", bat.state());
+			println!("  - Charge: {:.1}%", bat.state_of_charge().value * 100.0);
+			println!(
+				"  - Energy: {:.2} Wh / {:.2} Wh",
+				bat.energy().value / 3600.0,
+				bat.energy_full().value / 3600.0
+			);
+			println!("  - Health: {:.2}%", bat.state_of_health().value * 100.0);
+			println!("  - Voltage: {:.2} V", bat.voltage().value);
+			if let Some(temp) = bat.temperature() {
+				println!("  - Temperature: {:.1}°C", temp.get::<degree_celsius>());
+			}
+			if let Some(cycles) = bat.cycle_count() {
+				println!("  - Cycle count: {}", cycles);
+			}
+			if let Some(time) = bat.time_to_full() {
+				println!("  - Time to charge: {:.2} minutes", time.value / 60.0);
+			}
+			if let Some(time) = bat.time_to_empty() {
+				println!("  - Time to discharge: {:.2} minutes", time.value / 60.0);
 			}
 		}
 		return;
 	}
 
-	let enable_ipmitool = Energy::get_power_usage_w().is_some();
+	let config: Config = match load_config(&args, &matches) {
+		Ok(config) => config,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
+
+	let authenticator = match Authenticator::from_config(&config) {
+		Ok(authenticator) => authenticator,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
+
+	let rules = match Alerts::load_rules(&config) {
+		Ok(rules) => rules,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
+
+	let email_settings = match EmailSettings::from_config(&config) {
+		Ok(settings) => settings,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
 
-	let power_usage_interval = Energy::get_dcmi_power_with_info()
+	let address = format!("{}:{}", config.address, config.port);
+	let auth_enabled = authenticator.is_enabled();
+	let tls = TlsFiles::from_config(&config);
+
+	let ipmitool_timeout = Duration::from_secs(config.collector_timeout);
+	let enable_ipmitool = Energy::get_power_usage_w(ipmitool_timeout).is_some();
+
+	let power_usage_interval = Energy::get_dcmi_power_with_info(ipmitool_timeout)
 		.and_then(|dcmi| dcmi.power.and(dcmi.sampling_period_seconds));
 
 	let upses = UPS::detect_ups().unwrap_or(Vec::new());
 
-	let enable_docker = !args.containers.is_empty() || DockerMonitor::is_docker_available();
+	let enable_docker = !config.containers.is_empty() || DockerMonitor::is_docker_available();
+
+	let mut settings = Settings::new();
+	settings.apply_config(&config);
+	settings.energy = EnergySettings {
+		enabled: enable_ipmitool,
+		interval: power_usage_interval,
+	};
+	settings.upses = upses;
+	settings.docker = enable_docker;
+
+	let monitor: Arc<Monitor> = Arc::new(Monitor::new(settings));
+	let cloned: Arc<Monitor> = monitor.clone();
+
+	let (updates, _) = broadcast::channel::<()>(16);
+	let notifier = updates.clone();
+	let metric_history = Arc::new(Mutex::new(History::new(Duration::from_secs(
+		config.history_retention,
+	))));
+	let recorder = metric_history.clone();
+
+	let metric_archive: Option<Arc<Mutex<Archive>>> = match &config.data_dir {
+		Some(dir) => match Archive::open(dir, ArchiveSettings::from_config(&config)) {
+			Ok(archive) => Some(Arc::new(Mutex::new(archive))),
+			Err(e) => {
+				eprintln!("Failed to open data directory {}: {}", dir.display(), e);
+				std::process::exit(1);
+			}
+		},
+		None => None,
+	};
+	let archiver = metric_archive.clone();
+
+	if !rules.is_empty() {
+		println!("Loaded {} alert rules", rules.len());
+	}
+	let alert_engine = Arc::new(Mutex::new(Alerts::new(rules)));
+	let evaluator = alert_engine.clone();
+	let alert_notifier = Arc::new(Notifier::new(&config));
+	let dispatcher = alert_notifier.clone();
+	let mailer = Mailer::start(email_settings);
+	let postman = mailer.clone();
+	let remote_writer = RemoteWriter::start(&config);
+	let pusher = remote_writer.clone();
+	let influx_writer = InfluxWriter::start(&config);
+	let influx_pusher = influx_writer.clone();
+	let otlp_exporter = OtlpExporter::start(&config);
+	let otlp_pusher = otlp_exporter.clone();
+	let graphite_writer = GraphiteWriter::start(&config);
+	let graphite_pusher = graphite_writer.clone();
+	let statsd_sender = Arc::new(StatsdSender::new(&config));
+	let statsd_pusher = statsd_sender.clone();
+
+	let published = monitor.subscribe();
+	scheduler::start(monitor.clone());
 
+	// Every published snapshot is recorded, exported, checked against the alert rules
+	// and sent to live update subscribers. Only the samples of the collectors that
+	// refreshed are recorded and exported, so none is pushed twice.
 	std::thread::spawn(move || {
-		{
-			let mut temp: MutexGuard<Monitor> = monitor.lock().unwrap();
-			temp.settings.cache = args.cache;
-			temp.settings.interfaces = args.interfaces;
-			temp.settings.energy = EnergySettings {
-				enabled: enable_ipmitool,
-				interval: power_usage_interval,
-			};
-			temp.settings.upses = upses;
-			temp.settings.mounts = args.mounts;
-			temp.settings.components = args.components;
-			temp.settings.processes = args.processes;
-			temp.settings.containers = args.containers;
-			temp.settings.all_metrics = args.all_metrics;
-			temp.settings.cpu_details = args.cpu_details;
-			temp.settings.memory_details = args.memory_details;
-			temp.settings.swap_details = args.swap_details;
-			temp.settings.storage_details = args.storage_details;
-			temp.settings.network_details = args.network_details;
-
-			if enable_docker {
-				temp.start_docker_monitor();
+		while let Ok(update) = published.recv() {
+			// Refreshes that finished while the last snapshot was handled are handled
+			// together with the latest snapshot
+			let mut snapshot = update.snapshot;
+			let mut refreshed = update.refreshed;
+			while let Ok(update) = published.try_recv() {
+				snapshot = update.snapshot;
+				refreshed.extend(update.refreshed);
 			}
-		}
 
-		loop {
-			{
-				let mut temp: MutexGuard<Monitor> = monitor.lock().unwrap();
-				temp.refresh();
+			let families = &snapshot.refreshed_families(&refreshed);
+			let system_info = &snapshot.system_info;
+			let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+			recorder.lock().unwrap().record(families, now);
+			pusher.push(families, now);
+			influx_pusher.push(families, now);
+			otlp_pusher.push(families, system_info, now);
+			graphite_pusher.push(families, system_info, now);
+			statsd_pusher.push(families, system_info);
+			if let Some(archive) = &archiver {
+				if let Err(e) = archive.lock().unwrap().record(families, now) {
+					eprintln!("Failed to persist samples: {}", e);
+				}
+			}
+
+			for alert in evaluator.lock().unwrap().evaluate(&snapshot, now) {
+				println!(
+					"Alert {}{} is {} (value: {})",
+					alert.rule,
+					alert
+						.entry
+						.as_ref()
+						.map(|e| format!(" [{}]", e))
+						.unwrap_or_default(),
+					alert.state,
+					alert.value
+				);
+				dispatcher.notify(&alert);
+				postman.notify(&alert);
 			}
-			sleep(Duration::from_millis(args.cache * 1000));
+
+			// Nobody might be listening, which is fine
+			let _ = notifier.send(());
 		}
 	});
 
-	let app = Router::new()
+	if let Some(archive) = metric_archive.clone() {
+		std::thread::spawn(move || loop {
+			sleep(ARCHIVE_COMPACT_INTERVAL);
+			let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+			let compaction = archive.lock().unwrap().compaction();
+			if let Err(e) = compaction.run(now) {
+				eprintln!("Failed to compact data directory: {}", e);
+			}
+		});
+	}
+
+	let state = AppState {
+		monitor: cloned,
+		updates,
+		config: Arc::new(RwLock::new(config)),
+		args: Arc::new((args, matches)),
+		authenticator: Arc::new(RwLock::new(authenticator)),
+		auth_failures: Arc::new(AuthFailures::new(Utc::now().timestamp() as u64)),
+		scrapes: Arc::new(Scrapes::new(Utc::now().timestamp() as u64)),
+		history: metric_history,
+		archive: metric_archive,
+		alerts: alert_engine,
+		notifier: alert_notifier,
+		mailer,
+		remote_writer,
+		influx_writer,
+		otlp_exporter,
+		graphite_writer,
+		statsd_sender,
+	};
+
+	#[cfg(unix)]
+	{
+		let state = state.clone();
+		tokio::spawn(async move {
+			let mut hangup = match signal(SignalKind::hangup()) {
+				Ok(hangup) => hangup,
+				Err(e) => {
+					eprintln!("Failed to install SIGHUP handler: {}", e);
+					return;
+				}
+			};
+
+			while hangup.recv().await.is_some() {
+				println!("Received SIGHUP, reloading configuration");
+				let state = state.clone();
+				if let Ok(Err(e)) = tokio::task::spawn_blocking(move || reload_config(&state)).await {
+					eprintln!("Failed to reload configuration: {}", e);
+				}
+			}
+		});
+	}
+
+	let protected = Router::new()
 		.route("/", get(index))
 		.route("/metrics", get(metrics))
-		.with_state((cloned, token));
-
-	let address = format!("{}:{}", args.address, args.port);
-	let listener = tokio::net::TcpListener::bind(&address).await.unwrap();
-	println!(
-		"Rabbit Monitor listening on {} (Auth: {})",
-		&address,
-		if args.token.is_some() {
-			"Enabled"
-		} else {
-			"Disabled"
+		.route("/influx", get(influx))
+		.route("/api/v1/snapshot", get(snapshot))
+		.route("/api/v1/stream", get(stream))
+		.route("/api/v1/history", get(history))
+		.route("/api/v1/archive", get(archive))
+		.route("/api/v1/alerts", get(alerts))
+		.route("/api/v1/{subsystem}", get(subsystem))
+		.route("/-/reload", post(reload))
+		.route_layer(middleware::from_fn_with_state(state.clone(), authenticate));
+
+	let app = Router::new().merge(protected).with_state(state.clone());
+
+	match tls {
+		Some(tls) => {
+			let server_config = match tls.server_config() {
+				Ok(server_config) => server_config,
+				Err(e) => {
+					eprintln!("Failed to load TLS certificate: {}", e);
+					std::process::exit(1);
+				}
+			};
+			let rustls_config = RustlsConfig::from_config(server_config);
+			tokio::spawn(tls::watch(rustls_config.clone(), state.config.clone()));
+
+			let listener = std::net::TcpListener::bind(&address).unwrap();
+			listener.set_nonblocking(true).unwrap();
+			println!(
+				"Rabbit Monitor listening on {} (Auth: {}, TLS: {})",
+				&address,
+				if auth_enabled { "Enabled" } else { "Disabled" },
+				if tls.client_ca.is_some() {
+					"Enabled with client certificates"
+				} else {
+					"Enabled"
+				}
+			);
+			axum_server::from_tcp_rustls(listener, rustls_config)
+				.unwrap()
+				.serve(app.into_make_service_with_connect_info::<SocketAddr>())
+				.await
+				.unwrap();
 		}
-	);
-	axum::serve(listener, app).await.unwrap();
+		None => {
+			let listener = tokio::net::TcpListener::bind(&address).await.unwrap();
+			println!(
+				"Rabbit Monitor listening on {} (Auth: {})",
+				&address,
+				if auth_enabled { "Enabled" } else { "Disabled" }
+			);
+			axum::serve(
+				listener,
+				app.into_make_service_with_connect_info::<SocketAddr>(),
+			)
+			.await
+			.unwrap();
+		}
+	}
 }
 
-async fn index(
-	State((state, token)): State<(Arc<Mutex<Monitor>>, Option<String>)>,
-) -> impl IntoResponse {
-	if token.is_some() {
-		return (
-			StatusCode::NOT_FOUND,
-			"Rabbit Monitor v10.2.1\n\n\nMain page is disabled when Bearer authentication is enabled.",
-		)
-			.into_response();
+#[derive(Clone)]
+struct AppState {
+	monitor: Arc<Monitor>,
+	updates: broadcast::Sender<()>,
+	config: Arc<RwLock<Config>>,
+	args: Arc<(Args, ArgMatches)>,
+	authenticator: Arc<RwLock<Authenticator>>,
+	auth_failures: Arc<AuthFailures>,
+	scrapes: Arc<Scrapes>,
+	history: Arc<Mutex<History>>,
+	archive: Option<Arc<Mutex<Archive>>>,
+	alerts: Arc<Mutex<Alerts>>,
+	notifier: Arc<Notifier>,
+	mailer: Arc<Mailer>,
+	remote_writer: Arc<RemoteWriter>,
+	influx_writer: Arc<InfluxWriter>,
+	otlp_exporter: Arc<OtlpExporter>,
+	graphite_writer: Arc<GraphiteWriter>,
+	statsd_sender: Arc<StatsdSender>,
+}
+
+/// Re-reads the configuration file and applies it to the running monitor.
+/// The listening address and port can only be changed with a restart.
+fn reload_config(state: &AppState) -> Result<(), ConfigError> {
+	let (args, matches) = state.args.as_ref();
+	let config = load_config(args, matches)?;
+	let authenticator = Authenticator::from_config(&config)?;
+	let rules = Alerts::load_rules(&config)?;
+	let email_settings = EmailSettings::from_config(&config)?;
+
+	{
+		let current = state.config.read().unwrap();
+		if current.address != config.address || current.port != config.port {
+			eprintln!("Changing the address or port requires a restart, keeping the current listener");
+		}
+		if current.data_dir != config.data_dir {
+			eprintln!("Changing the data directory requires a restart, keeping the current one");
+		}
+	}
+
+	state.monitor.reload_settings(&config);
+	state
+		.history
+		.lock()
+		.unwrap()
+		.set_retention(Duration::from_secs(config.history_retention));
+	if let Some(archive) = &state.archive {
+		archive
+			.lock()
+			.unwrap()
+			.set_settings(ArchiveSettings::from_config(&config));
 	}
+	state.alerts.lock().unwrap().set_rules(rules);
+	state.notifier.set_config(&config);
+	state.mailer.set_settings(email_settings);
+	state.remote_writer.set_config(&config);
+	state.influx_writer.set_config(&config);
+	state.otlp_exporter.set_config(&config);
+	state.graphite_writer.set_config(&config);
+	state.statsd_sender.set_config(&config);
+	*state.authenticator.write().unwrap() = authenticator;
+	*state.config.write().unwrap() = config;
 
-	Html(utils::main_page(state)).into_response()
+	println!("Configuration reloaded");
+	Ok(())
 }
 
-async fn metrics(
-	auth: Option<TypedHeader<Authorization<Bearer>>>,
-	State((state, token)): State<(Arc<Mutex<Monitor>>, Option<String>)>,
-) -> impl IntoResponse {
-	if let Some(token) = &token {
-		if let Some(TypedHeader(auth)) = auth {
-			if auth.token() == token {
-				let body = utils::create_metrics(state);
-				return (
-					StatusCode::OK,
-					[(
-						header::CONTENT_TYPE,
-						HeaderValue::from_static("application/openmetrics-text; version=1.0.0; charset=utf-8"),
-					)],
-					body,
-				)
-					.into_response();
+/// Checks the credentials of every request to a protected route. The matching
+/// token's name is attached to the request and used in the access log; rejected
+/// requests are logged and counted, accepted ones are counted and timed by route.
+async fn authenticate(
+	State(state): State<AppState>,
+	ConnectInfo(address): ConnectInfo<SocketAddr>,
+	mut request: Request,
+	next: Next,
+) -> Response {
+	let credentials = Credentials::from_headers(request.headers());
+	let result = state
+		.authenticator
+		.read()
+		.unwrap()
+		.authenticate(credentials.as_ref());
+
+	let method = request.method().clone();
+	let path = request.uri().path().to_string();
+	let endpoint = request
+		.extensions()
+		.get::<MatchedPath>()
+		.map(|matched| matched.as_str().to_string())
+		.unwrap_or_else(|| path.clone());
+
+	match result {
+		Ok(client) => {
+			let name = client.name.clone();
+			request.extensions_mut().insert(client);
+			let started = Instant::now();
+			let response = next.run(request).await;
+			state.scrapes.record(&endpoint, started.elapsed());
+
+			if state.config.read().unwrap().access_log {
+				println!(
+					"{} {} {} {} token={}",
+					address,
+					method,
+					path,
+					response.status().as_u16(),
+					name
+				);
 			}
+			response
 		}
-		return (
-			StatusCode::UNAUTHORIZED,
-			"Unauthorized: A valid Bearer token is required to access this endpoint.",
+		Err(failure) => {
+			let identity = credentials
+				.as_ref()
+				.map(Credentials::identity)
+				.unwrap_or_default();
+			state.auth_failures.record(failure.reason(), &identity);
+			eprintln!(
+				"Rejected {} {} from {}: {} token{}",
+				method,
+				path,
+				address,
+				failure.reason(),
+				match identity.is_empty() {
+					true => String::new(),
+					false => format!(" {}", identity),
+				}
+			);
+			unauthorized()
+		}
+	}
+}
+
+/// Rejects a request for something the token's scopes do not grant.
+fn forbidden(state: &AppState, client: &Client, error: ApiError) -> Response {
+	state
+		.auth_failures
+		.record(AuthFailure::Forbidden.reason(), &client.name);
+	eprintln!("Rejected request of token {}: {}", client.name, error);
+	(StatusCode::FORBIDDEN, error.to_string()).into_response()
+}
+
+/// Asks for credentials. The Basic challenge makes browsers show a login prompt
+/// for the dashboard; scrapers keep sending their Bearer token.
+fn unauthorized() -> Response {
+	(
+		StatusCode::UNAUTHORIZED,
+		[(
+			header::WWW_AUTHENTICATE,
+			HeaderValue::from_static("Basic realm=\"Rabbit Monitor\", charset=\"UTF-8\""),
+		)],
+		"Unauthorized: A valid Bearer token or login is required to access this endpoint.",
+	)
+		.into_response()
+}
+
+/// Builds the effective configuration: defaults, then the `--config` file, then
+/// any flag that was explicitly passed on the command line.
+fn load_config(args: &Args, matches: &ArgMatches) -> Result<Config, ConfigError> {
+	let mut config = match &args.config {
+		Some(path) => Config::load(path)?,
+		None => Config::new(),
+	};
+
+	let from_cli = |id: &str| {
+		matches!(
+			matches.value_source(id),
+			Some(ValueSource::CommandLine | ValueSource::EnvVariable)
 		)
-			.into_response();
+	};
+
+	if from_cli("address") {
+		config.address = args.address.clone();
+	}
+	if from_cli("port") {
+		config.port = args.port;
+	}
+	if from_cli("cache") {
+		config.cache = args.cache;
+	}
+	if from_cli("collector_timeout") {
+		config.collector_timeout = args.collector_timeout;
+	}
+	if from_cli("history_retention") {
+		config.history_retention = args.history_retention;
+	}
+	if from_cli("data_dir") {
+		config.data_dir = args.data_dir.clone();
+	}
+	if from_cli("downsample_1m_after") {
+		config.downsample_1m_after = args.downsample_1m_after;
+	}
+	if from_cli("downsample_1h_after") {
+		config.downsample_1h_after = args.downsample_1h_after;
+	}
+	if from_cli("data_max_size") {
+		config.data_max_size = args.data_max_size;
+	}
+	if from_cli("rules_file") {
+		config.rules_file = args.rules_file.clone();
+	}
+	if from_cli("token") {
+		config.token = args.token.clone();
+	}
+	if from_cli("token_file") {
+		config.token_file = args.token_file.clone();
+	}
+	if from_cli("access_log") {
+		config.access_log = args.access_log;
+	}
+	if from_cli("tls_cert") {
+		config.tls_cert = args.tls_cert.clone();
 	}
+	if from_cli("tls_key") {
+		config.tls_key = args.tls_key.clone();
+	}
+	if from_cli("tls_client_ca") {
+		config.tls_client_ca = args.tls_client_ca.clone();
+	}
+	if from_cli("interfaces") {
+		config.interfaces = args.interfaces.clone();
+	}
+	if from_cli("mounts") {
+		config.mounts = args.mounts.clone();
+	}
+	if from_cli("components") {
+		config.components = args.components.clone();
+	}
+	if from_cli("processes") {
+		config.processes = args.processes.clone();
+	}
+	if from_cli("containers") {
+		config.containers = args.containers.clone();
+	}
+	if from_cli("batteries") {
+		config.batteries = args.batteries.clone();
+	}
+	if from_cli("disabled_subsystems") {
+		config.disabled_subsystems = args.disabled_subsystems.clone();
+	}
+	if from_cli("no_timestamps") {
+		config.timestamps = !args.no_timestamps;
+	}
+	if from_cli("all_metrics") {
+		config.all_metrics = args.all_metrics;
+	}
+	if from_cli("cpu_details") {
+		config.cpu_details = args.cpu_details;
+	}
+	if from_cli("memory_details") {
+		config.memory_details = args.memory_details;
+	}
+	if from_cli("swap_details") {
+		config.swap_details = args.swap_details;
+	}
+	if from_cli("storage_details") {
+		config.storage_details = args.storage_details;
+	}
+	if from_cli("network_details") {
+		config.network_details = args.network_details;
+	}
+
+	config.validate()?;
+	Ok(config)
+}
+
+/// The dashboard is a static page that loads everything from the JSON API, so
+/// it only shows what the scopes of the logged in token grant.
+async fn index() -> impl IntoResponse {
+	Html(DASHBOARD)
+}
+
+async fn metrics(
+	headers: HeaderMap,
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+	let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+	let format = Format::from_accept(accept);
+	let snapshot = state.monitor.snapshot.load_full();
+
+	// Tokens that may see everything get the exposition rendered after the last refresh
+	let body = if client.scopes.allows_everything() {
+		let mut body = snapshot.exposition(format).to_string();
+		body += &metrics::encode_families(&request_metrics(&state), format, snapshot.timestamps);
+		body += metrics::end_of_exposition(format);
+		body
+	} else {
+		let families = exposed_metrics(&state, &snapshot, &client);
+		metrics::encode(&families, format, snapshot.timestamps)
+	};
+	(
+		StatusCode::OK,
+		[(
+			header::CONTENT_TYPE,
+			HeaderValue::from_static(format.content_type()),
+		)],
+		body,
+	)
+		.into_response()
+}
+
+async fn influx(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+	let timestamps = state.config.read().unwrap().timestamps;
 
-	let body = utils::create_metrics(state);
+	let snapshot = state.monitor.snapshot.load_full();
+	let families = exposed_metrics(&state, &snapshot, &client);
+	let body = influx::encode(&families, &HashMap::new(), timestamps, None);
 	(
 		StatusCode::OK,
 		[(
 			header::CONTENT_TYPE,
-			HeaderValue::from_static("application/openmetrics-text; version=1.0.0; charset=utf-8"),
+			HeaderValue::from_static("text/plain; charset=utf-8"),
 		)],
 		body,
 	)
 		.into_response()
 }
+
+/// Collects the metric families of a snapshot a client may see, including the
+/// request counters.
+fn exposed_metrics(state: &AppState, snapshot: &Snapshot, client: &Client) -> Vec<MetricFamily> {
+	let mut families = snapshot.families.clone();
+	families.extend(request_metrics(state));
+
+	client.scopes.filter_metrics(families)
+}
+
+/// The requests served by every endpoint and, when authentication is enabled, the
+/// rejected ones. Both change with every request, so they are never pre-rendered.
+fn request_metrics(state: &AppState) -> Vec<MetricFamily> {
+	let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+	let mut families = state.scrapes.metrics(now);
+	if state.authenticator.read().unwrap().is_enabled() {
+		families.extend(state.auth_failures.metrics(now));
+	}
+
+	families
+}
+
+async fn snapshot(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+	let snapshot = state.monitor.snapshot.load_full();
+	Json(api::snapshot(&snapshot, &client.scopes)).into_response()
+}
+
+async fn subsystem(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+	Path(name): Path<String>,
+	Query(query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+	let snapshot = state.monitor.snapshot.load_full();
+	match api::subsystem(&snapshot, &name, &query, &client.scopes) {
+		Ok(value) => Json(value).into_response(),
+		Err(e @ ApiError::UnknownSubsystem(_)) => {
+			(StatusCode::NOT_FOUND, e.to_string()).into_response()
+		}
+		Err(e @ (ApiError::UnknownFilter(_) | ApiError::InvalidParameter(..))) => {
+			(StatusCode::BAD_REQUEST, e.to_string()).into_response()
+		}
+		Err(e @ ApiError::Forbidden(_)) => forbidden(&state, &client, e),
+	}
+}
+
+async fn stream(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+	Query(query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+	let subsystems: Vec<String> = match query.get("subsystems") {
+		Some(list) => list.split(',').map(|s| s.trim().to_string()).collect(),
+		None => api::subsystems()
+			.into_iter()
+			.filter(|s| client.scopes.allows(s))
+			.map(|s| s.to_string())
+			.collect(),
+	};
+
+	if let Some(unknown) = subsystems
+		.iter()
+		.find(|s| !api::subsystems().contains(&s.as_str()))
+	{
+		return (
+			StatusCode::NOT_FOUND,
+			ApiError::UnknownSubsystem(unknown.clone()).to_string(),
+		)
+			.into_response();
+	}
+
+	if let Some(denied) = subsystems.iter().find(|s| !client.scopes.allows(s)) {
+		return forbidden(&state, &client, ApiError::Forbidden(denied.clone()));
+	}
+
+	let receiver = state.updates.subscribe();
+	let selection = (subsystems, client.scopes);
+	let initial = (receiver, state.monitor, selection, HashMap::new(), true);
+
+	// The first event carries the full state, later ones only the subsystems that changed
+	let events = stream::unfold(
+		initial,
+		|(mut receiver, monitor, selection, mut last, first)| async move {
+			loop {
+				if !first {
+					match receiver.recv().await {
+						Ok(()) | Err(RecvError::Lagged(_)) => {}
+						Err(RecvError::Closed) => return None,
+					}
+				}
+
+				let (subsystems, scopes) = &selection;
+				let snapshot = monitor.snapshot.load_full();
+				let delta = api::delta(&snapshot, subsystems, scopes, &mut last);
+				if first || !delta.is_empty() {
+					let event = Event::default().event("update").json_data(delta).unwrap();
+					return Some((
+						Ok::<Event, Infallible>(event),
+						(receiver, monitor, selection, last, false),
+					));
+				}
+			}
+		},
+	);
+
+	Sse::new(events)
+		.keep_alive(KeepAlive::default())
+		.into_response()
+}
+
+/// Takes a time parameter out of the query, see `history::parse_time`.
+fn time_parameter(
+	query: &mut HashMap<String, String>,
+	key: &str,
+	now: Duration,
+	default: Duration,
+) -> Result<Duration, ApiError> {
+	match query.remove(key) {
+		Some(value) => history::parse_time(&value, now).ok_or_else(|| {
+			ApiError::InvalidParameter(
+				key.to_string(),
+				String::from("expected seconds since the Unix epoch or a duration like 15m"),
+			)
+		}),
+		None => Ok(default),
+	}
+}
+
+fn metric_parameter(query: &mut HashMap<String, String>) -> Result<String, ApiError> {
+	query
+		.remove("metric")
+		.ok_or_else(|| ApiError::InvalidParameter(String::from("metric"), String::from("is required")))
+}
+
+async fn history(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+	Query(mut query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+	let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+	let metric = match metric_parameter(&mut query) {
+		Ok(metric) => metric,
+		Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+	};
+	let since = match time_parameter(&mut query, "since", now, Duration::ZERO) {
+		Ok(since) => since,
+		Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+	};
+
+	// The remaining parameters select series by label
+	let value = state
+		.history
+		.lock()
+		.unwrap()
+		.query(&metric, since, &query, &client.scopes);
+	Json(value).into_response()
+}
+
+async fn archive(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+	Query(mut query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+	let Some(archive) = state.archive.clone() else {
+		return (
+			StatusCode::NOT_FOUND,
+			"The archive is disabled, start Rabbit Monitor with --data-dir to enable it.",
+		)
+			.into_response();
+	};
+
+	let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+	let metric = match metric_parameter(&mut query) {
+		Ok(metric) => metric,
+		Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+	};
+	let from = match time_parameter(&mut query, "from", now, Duration::ZERO) {
+		Ok(from) => from,
+		Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+	};
+	let to = match time_parameter(&mut query, "to", now, now) {
+		Ok(to) => to,
+		Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+	};
+
+	// Reading segment files blocks, and the remaining parameters select series by label.
+	// The archive is only locked to find the segments, not while they are read.
+	let result = tokio::task::spawn_blocking(move || {
+		let segments = archive.lock().unwrap().segments_between(from, to)?;
+		archive::query(&segments, &metric, from, to, &query, &client.scopes)
+	})
+	.await
+	.unwrap();
+
+	match result {
+		Ok(value) => Json(value).into_response(),
+		Err(e) => {
+			eprintln!("Failed to read the archive: {}", e);
+			(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				format!("Failed to read the archive: {}", e),
+			)
+				.into_response()
+		}
+	}
+}
+
+async fn alerts(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+	Query(query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+	let filter = match query.get("state").map(|s| s.as_str()) {
+		None => None,
+		Some("pending") => Some(AlertState::Pending),
+		Some("firing") => Some(AlertState::Firing),
+		Some("resolved") => Some(AlertState::Resolved),
+		Some(_) => {
+			let error = ApiError::InvalidParameter(
+				String::from("state"),
+				String::from("expected pending, firing or resolved"),
+			);
+			return (StatusCode::BAD_REQUEST, error.to_string()).into_response();
+		}
+	};
+
+	let alerts = state.alerts.lock().unwrap();
+	Json(json!({
+		"rules": alerts.rule_count(),
+		"alerts": alerts.list(&client.scopes, filter),
+	}))
+	.into_response()
+}
+
+async fn reload(
+	State(state): State<AppState>,
+	Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+	if !client.scopes.allows("reload") {
+		return forbidden(&state, &client, ApiError::Forbidden(String::from("reload")));
+	}
+
+	let result = tokio::task::spawn_blocking(move || reload_config(&state))
+		.await
+		.unwrap();
+
+	match result {
+		Ok(()) => (StatusCode::OK, "Configuration reloaded.").into_response(),
+		Err(e) => {
+			eprintln!("Failed to reload configuration: {}", e);
+			(
+				StatusCode::BAD_REQUEST,
+				format!("Failed to reload configuration: {}", e),
+			)
+				.into_response()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+	use std::path::Path;
+
+	fn load(config: &Path, flags: &[&str]) -> Result<Config, ConfigError> {
+		let mut arguments = vec!["rabbitmonitor", "--config", config.to_str().unwrap()];
+		arguments.extend_from_slice(flags);
+		let matches = Args::command().try_get_matches_from(arguments).unwrap();
+		let args = Args::from_arg_matches(&matches).unwrap();
+		load_config(&args, &matches)
+	}
+
+	#[test]
+	fn prefers_the_command_line_over_the_environment_over_the_file() {
+		let path = std::env::temp_dir().join(format!("main-config-{}.toml", std::process::id()));
+		fs::write(&path, "port = 9000\ncache = 5\ntoken = \"file\"\n").unwrap();
+
+		std::env::remove_var("RABBITMONITOR_TOKEN");
+		let config = load(&path, &[]).unwrap();
+		assert_eq!((config.port, config.cache), (9000, 5));
+		assert_eq!(config.token.as_deref(), Some("file"));
+
+		std::env::set_var("RABBITMONITOR_TOKEN", "environment");
+		let config = load(&path, &[]).unwrap();
+		assert_eq!(config.port, 9000);
+		assert_eq!(config.token.as_deref(), Some("environment"));
+
+		let config = load(&path, &["--port", "9100", "--token", "command-line"]).unwrap();
+		std::env::remove_var("RABBITMONITOR_TOKEN");
+		assert_eq!((config.port, config.cache), (9100, 5));
+		assert_eq!(config.token.as_deref(), Some("command-line"));
+
+		let error = load(&path, &["--cache", "0"]);
+		let _ = fs::remove_file(&path);
+		match error {
+			Err(ConfigError::Invalid { key, .. }) => assert_eq!(key, "cache"),
+			result => panic!("expected an invalid value, got {:?}", result.map(|_| ())),
+		}
+	}
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MetricType {
+	Gauge,
+	Counter,
+	Info,
+}
+
+impl MetricType {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			MetricType::Gauge => "gauge",
+			MetricType::Counter => "counter",
+			MetricType::Info => "info",
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+	pub labels: Vec<(String, String)>,
+	pub value: f64,
+	pub timestamp: Option<Duration>,
+	/// Creation time of a counter in seconds since the Unix epoch.
+	pub created: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MetricFamily {
+	pub name: String,
+	pub description: String,
+	pub metric_type: MetricType,
+	pub unit: Option<String>,
+	/// Subsystem the family belongs to, used to apply token scopes.
+	pub subsystem: Option<String>,
+	pub samples: Vec<Sample>,
+}
+
+impl MetricFamily {
+	/// Full metric name as exposed, e.g. `rabbit_storage_read_speed_bytes_per_second`.
+	pub fn full_name(&self) -> String {
+		match &self.unit {
+			Some(unit) => format!("rabbit_{}_{}", self.name, unit),
+			None => format!("rabbit_{}", self.name),
+		}
+	}
+
+	/// Name of the individual samples, which carries the `_total` suffix for counters.
+	pub fn sample_name(&self) -> String {
+		match self.metric_type {
+			MetricType::Counter => format!("{}_total", self.full_name()),
+			_ => self.full_name(),
+		}
+	}
+}
+
+/// Collects metric families in the order they are rendered.
+pub struct MetricsBuilder {
+	families: Vec<MetricFamily>,
+	subsystem: Option<String>,
+}
+
+impl MetricsBuilder {
+	pub fn new() -> Self {
+		MetricsBuilder {
+			families: Vec::new(),
+			subsystem: None,
+		}
+	}
+
+	/// Sets the subsystem of the families started afterwards.
+	pub fn subsystem(&mut self, subsystem: &str) {
+		self.subsystem = Some(subsystem.to_string());
+	}
+
+	/// Starts a new metric family. Samples added afterwards belong to it.
+	pub fn header(
+		&mut self,
+		name: &str,
+		description: &str,
+		metric_type: MetricType,
+		unit: Option<&str>,
+	) {
+		self.families.push(MetricFamily {
+			name: name.to_string(),
+			description: description.to_string(),
+			metric_type,
+			unit: unit.map(|u| u.to_string()),
+			subsystem: self.subsystem.clone(),
+			samples: Vec::new(),
+		});
+	}
+
+	pub fn info(&mut self, name: &str, description: &str, labels: &[(&str, &str)]) {
+		self.header(name, description, MetricType::Info, None);
+		self.push(1.0, labels, None, None);
+	}
+
+	pub fn gauge(
+		&mut self,
+		name: &str,
+		description: &str,
+		value: f64,
+		unit: Option<&str>,
+		labels: &[(&str, &str)],
+		timestamp: Duration,
+	) {
+		self.header(name, description, MetricType::Gauge, unit);
+		self.sample(value, labels, timestamp);
+	}
+
+	pub fn sample(&mut self, value: f64, labels: &[(&str, &str)], timestamp: Duration) {
+		self.push(value, labels, Some(timestamp), None);
+	}
+
+	pub fn counter_sample(
+		&mut self,
+		value: f64,
+		labels: &[(&str, &str)],
+		timestamp: Duration,
+		created: u64,
+	) {
+		self.push(value, labels, Some(timestamp), Some(created));
+	}
+
+	fn push(
+		&mut self,
+		value: f64,
+		labels: &[(&str, &str)],
+		timestamp: Option<Duration>,
+		created: Option<u64>,
+	) {
+		if let Some(family) = self.families.last_mut() {
+			family.samples.push(Sample {
+				labels: labels
+					.iter()
+					.map(|(k, v)| (k.to_string(), v.to_string()))
+					.collect(),
+				value,
+				timestamp,
+				created,
+			});
+		}
+	}
+
+	pub fn build(self) -> Vec<MetricFamily> {
+		self.families
+	}
+}
+
+impl Default for MetricsBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+	OpenMetrics,
+	Prometheus,
+}
+
+impl Format {
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+			Format::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+		}
+	}
+
+	/// Picks the exposition format from an `Accept` header. The media type with the
+	/// highest quality wins; OpenMetrics is used when nothing supported is requested.
+	pub fn from_accept(accept: Option<&str>) -> Format {
+		let accept = match accept {
+			Some(accept) => accept,
+			None => return Format::OpenMetrics,
+		};
+
+		let mut best: Option<(Format, f32)> = None;
+
+		for media_range in accept.split(',') {
+			let mut parts = media_range.split(';').map(|p| p.trim());
+			let media_type = parts.next().unwrap_or("").to_lowercase();
+
+			let format = match media_type.as_str() {
+				"application/openmetrics-text" => Format::OpenMetrics,
+				"text/plain" => Format::Prometheus,
+				_ => continue,
+			};
+
+			let quality = parts
+				.filter_map(|p| p.strip_prefix("q="))
+				.find_map(|q| q.parse::<f32>().ok())
+				.unwrap_or(1.0);
+
+			if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+				best = Some((format, quality));
+			}
+		}
+
+		best
+			.map(|(format, _)| format)
+			.unwrap_or(Format::OpenMetrics)
+	}
+}
+
+fn escape_label_value(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(String, String)]) -> String {
+	if labels.is_empty() {
+		return String::new();
+	}
+
+	let label_pairs = labels
+		.iter()
+		.map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+		.collect::<Vec<_>>()
+		.join(",");
+	format!("{{{}}}", label_pairs)
+}
+
+/// Renders metric families as OpenMetrics 1.0 or Prometheus text format 0.0.4.
+/// Sample timestamps are left out when `timestamps` is false.
+pub fn encode(families: &[MetricFamily], format: Format, timestamps: bool) -> String {
+	let mut out = encode_families(families, format, timestamps);
+	out += end_of_exposition(format);
+	out
+}
+
+/// Renders metric families like `encode`, but leaves the exposition open so more
+/// families can be appended, e.g. to a pre-rendered one.
+pub fn encode_families(families: &[MetricFamily], format: Format, timestamps: bool) -> String {
+	let mut out = String::new();
+
+	for family in families {
+		let full_name = family.full_name();
+
+		match format {
+			Format::OpenMetrics => {
+				let _ = writeln!(out, "# HELP {} {}", full_name, family.description);
+				let _ = writeln!(out, "# TYPE {} {}", full_name, family.metric_type.as_str());
+				if let Some(unit) = &family.unit {
+					let _ = writeln!(out, "# UNIT {} {}", full_name, unit);
+				}
+			}
+			Format::Prometheus => {
+				// The classic text format has no info type and names counters by their sample
+				let (name, metric_type) = match family.metric_type {
+					MetricType::Counter => (family.sample_name(), "counter"),
+					MetricType::Gauge => (full_name.clone(), "gauge"),
+					MetricType::Info => (full_name.clone(), "gauge"),
+				};
+				let _ = writeln!(out, "# HELP {} {}", name, family.description);
+				let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+			}
+		}
+
+		let sample_name = family.sample_name();
+		for sample in &family.samples {
+			let labels = format_labels(&sample.labels);
+			let timestamp = match (timestamps, sample.timestamp) {
+				(true, Some(ts)) => match format {
+					Format::OpenMetrics => format!(" {:.3}", ts.as_secs_f64()),
+					Format::Prometheus => format!(" {}", ts.as_millis()),
+				},
+				_ => String::new(),
+			};
+
+			let _ = writeln!(
+				out,
+				"{}{} {}{}",
+				sample_name, labels, sample.value, timestamp
+			);
+
+			if let (Format::OpenMetrics, Some(created)) = (format, sample.created) {
+				let _ = writeln!(
+					out,
+					"{}_created{} {:.3}{}",
+					full_name, labels, created as f64, timestamp
+				);
+			}
+		}
+	}
+
+	out
+}
+
+/// The line that ends an exposition, OpenMetrics requires `# EOF`.
+pub fn end_of_exposition(format: Format) -> &'static str {
+	match format {
+		Format::OpenMetrics => "# EOF\n",
+		Format::Prometheus => "",
+	}
+}
+
+/// Rounds a value to two decimal places, the precision used for percentages and watts.
+pub fn round2<T: Into<f64>>(value: T) -> f64 {
+	(value.into() * 100.0).round() / 100.0
+}
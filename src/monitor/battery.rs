@@ -1,14 +1,32 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::time::Duration;
 
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 use starship_battery::{
 	units::{
-		electric_potential::volt, energy::watt_hour, power::watt, ratio::percent, ElectricPotential,
-		Energy, Power, Ratio, ThermodynamicTemperature, Time,
+		electric_potential::volt, energy::watt_hour, power::watt, ratio::percent,
+		thermodynamic_temperature::degree_celsius, time::second, ElectricPotential, Energy, Power,
+		Ratio, ThermodynamicTemperature, Time,
 	},
-	State, Technology,
+	Manager, State, Technology,
 };
 
+use crate::api::select;
+use crate::metrics::{round2, MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+
+/// Where Linux lists the power supplies, batteries are named after their directory
+/// there, e.g. `BAT0`.
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+#[derive(Clone)]
 pub struct Battery {
+	pub name: String,
 	pub state_of_charge: Ratio,
 	pub energy: Energy,
 	pub energy_full: Energy,
@@ -31,6 +49,7 @@ pub struct Battery {
 impl Battery {
 	pub fn new() -> Self {
 		Battery {
+			name: String::new(),
 			state_of_charge: Ratio::new::<percent>(0.0),
 			energy: Energy::new::<watt_hour>(0.0),
 			energy_full: Energy::new::<watt_hour>(0.0),
@@ -50,6 +69,153 @@ impl Battery {
 			refreshed: Duration::from_secs(0),
 		}
 	}
+
+	/// Lists all batteries reported by the OS. On Linux they are named after their
+	/// directory in `/sys/class/power_supply`, so a name stays with its battery even
+	/// when another one is added or removed. Elsewhere they are named `BAT0`, `BAT1`...
+	/// in detection order.
+	pub fn detect_batteries() -> Result<Vec<(String, starship_battery::Battery)>, String> {
+		// Without sysfs, e.g. in some containers, there are no batteries to read
+		if cfg!(target_os = "linux") && !Path::new(POWER_SUPPLY_DIR).exists() {
+			return Ok(Vec::new());
+		}
+
+		let manager = Manager::new().map_err(|e| format!("Failed to detect batteries: {}", e))?;
+		let batteries = manager
+			.batteries()
+			.map_err(|e| format!("Failed to detect batteries: {}", e))?;
+
+		let mut power_supplies = power_supplies();
+		let mut devices: Vec<(String, starship_battery::Battery)> = Vec::new();
+		for (i, battery) in batteries.enumerate() {
+			// One unreadable battery does not hide the others
+			let battery = match battery {
+				Ok(battery) => battery,
+				Err(e) => {
+					eprintln!("Failed to read battery {}: {}", i, e);
+					continue;
+				}
+			};
+
+			// Batteries are listed in the order of the directory, so batteries that
+			// can not be told apart are still matched to the right directory
+			let identity = [
+				identity_field(battery.vendor()),
+				identity_field(battery.model()),
+				identity_field(battery.serial_number()),
+			];
+			let name = match power_supplies
+				.iter()
+				.position(|(_, fields)| *fields == identity)
+			{
+				Some(position) => power_supplies.remove(position).0,
+				None => format!("BAT{}", i),
+			};
+			devices.push((name, battery));
+		}
+
+		Ok(devices)
+	}
+
+	pub fn get_battery_data(
+		name: &str,
+		bat: &starship_battery::Battery,
+		refreshed: Duration,
+	) -> Battery {
+		Battery {
+			name: name.to_string(),
+			state_of_charge: bat.state_of_charge(),
+			energy: bat.energy(),
+			energy_full: bat.energy_full(),
+			energy_full_design: bat.energy_full_design(),
+			energy_rate: bat.energy_rate(),
+			voltage: bat.voltage(),
+			state_of_health: bat.state_of_health(),
+			state: bat.state(),
+			technology: bat.technology(),
+			temperature: bat.temperature(),
+			cycle_count: bat.cycle_count(),
+			time_to_full: bat.time_to_full(),
+			time_to_empty: bat.time_to_empty(),
+			vendor: bat.vendor().map(|s| s.trim().to_string()),
+			model: bat.model().map(|s| s.trim().to_string()),
+			serial_number: bat.serial_number().map(|s| s.trim().to_string()),
+			refreshed,
+		}
+	}
+}
+
+/// The system batteries in `POWER_SUPPLY_DIR`, in the order of the directory, with
+/// their vendor, model and serial number. Empty where there is no such directory.
+fn power_supplies() -> Vec<(String, [Option<String>; 3])> {
+	let Ok(entries) = fs::read_dir(POWER_SUPPLY_DIR) else {
+		return Vec::new();
+	};
+	let read =
+		|path: &Path, name: &str| identity_field(fs::read_to_string(path.join(name)).ok().as_deref());
+
+	entries
+		.flatten()
+		.filter(|entry| {
+			let path = entry.path();
+			read(&path, "type").as_deref() == Some("Battery")
+				&& read(&path, "scope").as_deref() != Some("Device")
+		})
+		.map(|entry| {
+			let path = entry.path();
+			(
+				entry.file_name().to_string_lossy().to_string(),
+				[
+					read(&path, "manufacturer"),
+					read(&path, "model_name"),
+					read(&path, "serial_number"),
+				],
+			)
+		})
+		.collect()
+}
+
+fn identity_field(value: Option<&str>) -> Option<String> {
+	value
+		.map(|s| s.trim().to_string())
+		.filter(|s| !s.is_empty())
+}
+
+impl Serialize for Battery {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut state = serializer.serialize_struct("Battery", 18)?;
+		state.serialize_field("name", &self.name)?;
+		state.serialize_field("state_of_charge", &self.state_of_charge.get::<percent>())?;
+		state.serialize_field("energy", &self.energy.get::<watt_hour>())?;
+		state.serialize_field("energy_full", &self.energy_full.get::<watt_hour>())?;
+		state.serialize_field(
+			"energy_full_design",
+			&self.energy_full_design.get::<watt_hour>(),
+		)?;
+		state.serialize_field("energy_rate", &self.energy_rate.get::<watt>())?;
+		state.serialize_field("voltage", &self.voltage.get::<volt>())?;
+		state.serialize_field("state_of_health", &self.state_of_health.get::<percent>())?;
+		state.serialize_field("state", &self.state.to_string())?;
+		state.serialize_field("technology", &self.technology.to_string())?;
+		state.serialize_field(
+			"temperature",
+			&self.temperature.map(|t| t.get::<degree_celsius>()),
+		)?;
+		state.serialize_field("cycle_count", &self.cycle_count)?;
+		state.serialize_field(
+			"time_to_full",
+			&self.time_to_full.map(|t| t.get::<second>()),
+		)?;
+		state.serialize_field(
+			"time_to_empty",
+			&self.time_to_empty.map(|t| t.get::<second>()),
+		)?;
+		state.serialize_field("vendor", &self.vendor)?;
+		state.serialize_field("model", &self.model)?;
+		state.serialize_field("serial_number", &self.serial_number)?;
+		state.serialize_field("refreshed", &self.refreshed.as_secs_f64())?;
+		state.end()
+	}
 }
 
 impl Default for Battery {
@@ -57,3 +223,336 @@ impl Default for Battery {
 		Self::new()
 	}
 }
+
+/// Collects the charge, energy and health of the batteries reported by the OS.
+pub struct BatteryCollector {
+	batteries: HashMap<String, Battery>,
+}
+
+impl BatteryCollector {
+	pub fn new() -> Self {
+		BatteryCollector {
+			batteries: HashMap::new(),
+		}
+	}
+}
+
+impl Default for BatteryCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for BatteryCollector {
+	fn name(&self) -> &'static str {
+		"batteries"
+	}
+
+	fn key(&self) -> &'static str {
+		"batteries"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["battery"]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let batteries = Battery::detect_batteries()?;
+
+		let mut reported = Vec::new();
+		for (name, bat) in batteries {
+			if !settings.batteries.is_empty() && !settings.batteries.contains(&name) {
+				continue; // Skip if not in the user-defined battery list
+			}
+
+			let battery = Battery::get_battery_data(&name, &bat, now);
+			self.batteries.insert(name.clone(), battery);
+			reported.push(name);
+		}
+
+		// Batteries that are no longer reported, e.g. a removed one, are dropped
+		self.batteries.retain(|name, _| reported.contains(name));
+
+		Ok(())
+	}
+
+	fn prune(&mut self, settings: &Settings) {
+		self
+			.batteries
+			.retain(|name, _| settings.batteries.is_empty() || settings.batteries.contains(name));
+	}
+
+	fn metrics(&self, _settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.batteries.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"battery_charge",
+			"Battery state of charge",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.state_of_charge.get::<percent>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		metrics.header(
+			"battery_energy",
+			"Energy stored in the battery",
+			MetricType::Gauge,
+			Some("watt_hours"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.energy.get::<watt_hour>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		metrics.header(
+			"battery_energy_full",
+			"Energy stored in the battery when fully charged",
+			MetricType::Gauge,
+			Some("watt_hours"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.energy_full.get::<watt_hour>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		metrics.header(
+			"battery_energy_full_design",
+			"Designed battery capacity",
+			MetricType::Gauge,
+			Some("watt_hours"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.energy_full_design.get::<watt_hour>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		metrics.header(
+			"battery_energy_rate",
+			"Rate at which energy flows into or out of the battery",
+			MetricType::Gauge,
+			Some("watts"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.energy_rate.get::<watt>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		metrics.header(
+			"battery_voltage",
+			"Battery voltage",
+			MetricType::Gauge,
+			Some("volts"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.voltage.get::<volt>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		metrics.header(
+			"battery_health",
+			"Battery state of health",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				round2(battery.state_of_health.get::<percent>()),
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+				],
+				battery.refreshed,
+			);
+		}
+
+		if self
+			.batteries
+			.values()
+			.any(|battery| battery.temperature.is_some())
+		{
+			metrics.header(
+				"battery_temperature",
+				"Battery temperature in celsius",
+				MetricType::Gauge,
+				Some("celsius"),
+			);
+			for (battery_name, battery) in &self.batteries {
+				if let Some(value) = battery.temperature {
+					metrics.sample(
+						round2(value.get::<degree_celsius>()),
+						&[
+							("battery", battery_name),
+							("vendor", battery.vendor.as_deref().unwrap_or("")),
+							("model", battery.model.as_deref().unwrap_or("")),
+						],
+						battery.refreshed,
+					);
+				}
+			}
+		}
+
+		if self
+			.batteries
+			.values()
+			.any(|battery| battery.cycle_count.is_some())
+		{
+			metrics.header(
+				"battery_cycle_count",
+				"Number of charge cycles the battery went through",
+				MetricType::Gauge,
+				None,
+			);
+			for (battery_name, battery) in &self.batteries {
+				if let Some(value) = battery.cycle_count {
+					metrics.sample(
+						value as f64,
+						&[
+							("battery", battery_name),
+							("vendor", battery.vendor.as_deref().unwrap_or("")),
+							("model", battery.model.as_deref().unwrap_or("")),
+						],
+						battery.refreshed,
+					);
+				}
+			}
+		}
+
+		if self
+			.batteries
+			.values()
+			.any(|battery| battery.time_to_full.is_some())
+		{
+			metrics.header(
+				"battery_time_to_full",
+				"Estimated time until the battery is fully charged",
+				MetricType::Gauge,
+				Some("seconds"),
+			);
+			for (battery_name, battery) in &self.batteries {
+				if let Some(value) = battery.time_to_full {
+					metrics.sample(
+						round2(value.get::<second>()),
+						&[
+							("battery", battery_name),
+							("vendor", battery.vendor.as_deref().unwrap_or("")),
+							("model", battery.model.as_deref().unwrap_or("")),
+						],
+						battery.refreshed,
+					);
+				}
+			}
+		}
+
+		if self
+			.batteries
+			.values()
+			.any(|battery| battery.time_to_empty.is_some())
+		{
+			metrics.header(
+				"battery_time_to_empty",
+				"Estimated time until the battery is fully discharged",
+				MetricType::Gauge,
+				Some("seconds"),
+			);
+			for (battery_name, battery) in &self.batteries {
+				if let Some(value) = battery.time_to_empty {
+					metrics.sample(
+						round2(value.get::<second>()),
+						&[
+							("battery", battery_name),
+							("vendor", battery.vendor.as_deref().unwrap_or("")),
+							("model", battery.model.as_deref().unwrap_or("")),
+						],
+						battery.refreshed,
+					);
+				}
+			}
+		}
+
+		metrics.header(
+			"battery_state_info",
+			"Battery charging state and technology",
+			MetricType::Info,
+			None,
+		);
+		for (battery_name, battery) in &self.batteries {
+			metrics.sample(
+				1.0,
+				&[
+					("battery", battery_name),
+					("vendor", battery.vendor.as_deref().unwrap_or("")),
+					("model", battery.model.as_deref().unwrap_or("")),
+					("state", &battery.state.to_string()),
+					("technology", &battery.technology.to_string()),
+				],
+				battery.refreshed,
+			);
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let batteries = self.batteries.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(
+				&batteries,
+				query,
+				filters,
+				(subsystem, scopes),
+				|_, name, _| name.to_string(),
+			)
+		})
+	}
+}
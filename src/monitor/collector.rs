@@ -0,0 +1,311 @@
+use arc_swap::ArcSwap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant};
+
+use crate::api::{check_filters, ApiError};
+use crate::auth::Scopes;
+use crate::metrics::{MetricFamily, MetricsBuilder};
+use crate::monitor::battery::BatteryCollector;
+use crate::monitor::components::ComponentCollector;
+use crate::monitor::docker::DockerCollector;
+use crate::monitor::energy::EnergyCollector;
+use crate::monitor::memory::MemoryCollector;
+use crate::monitor::network::NetworkCollector;
+use crate::monitor::processes::ProcessCollector;
+use crate::monitor::processor::CpuCollector;
+use crate::monitor::settings::Settings;
+use crate::monitor::storage::StorageCollector;
+use crate::monitor::swap::SwapCollector;
+use crate::monitor::system_info::SystemInfo;
+use crate::monitor::ups::UpsCollector;
+
+/// A subsystem Rabbit Monitor collects, such as CPU or storage. A collector keeps the
+/// state of its subsystem and renders it as metrics and as JSON.
+pub trait Collector: Send {
+	/// Name of the subsystem in the API, token scopes and alert rules, e.g. `storage`.
+	fn name(&self) -> &'static str;
+
+	/// Key of the subsystem in the snapshot returned by `/api/v1`, e.g. `storage_devices`.
+	fn key(&self) -> &'static str;
+
+	/// Query parameters that select entries, e.g. `name` and `mount` for storage.
+	fn filters(&self) -> &'static [&'static str] {
+		&[]
+	}
+
+	/// Metric labels that identify an entry. Subsystems without entries can only be
+	/// granted to tokens as a whole.
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&[]
+	}
+
+	/// Field of an entry in `/api/v1/{subsystem}` that holds the value of each filter,
+	/// in the order of `filters`, or `None` for the key the entry is listed under. They
+	/// name the entry for token scopes, e.g. for alerts about it. By default an entry is
+	/// only named by its key.
+	fn entry_fields(&self) -> &'static [Option<&'static str>] {
+		&[None]
+	}
+
+	/// Whether the subsystem can be collected on this host. Disabled collectors are
+	/// neither refreshed nor rendered.
+	fn enabled(&self, _settings: &Settings) -> bool {
+		true
+	}
+
+	/// Reads the current state of the subsystem. On error the last good state has to be
+	/// kept, it is then reported as stale.
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String>;
+
+	/// Drops entries the settings no longer select, called after a reload.
+	fn prune(&mut self, _settings: &Settings) {}
+
+	/// Adds the metric families of the subsystem.
+	fn metrics(&self, settings: &Settings, system_info: &SystemInfo, metrics: &mut MetricsBuilder);
+
+	/// Captures the current entries. The view renders those matching a query that the
+	/// scopes grant access to, without the collector.
+	fn json(&self) -> JsonView;
+}
+
+/// Renders the entries captured by `Collector::json` that match `query` and that
+/// `scopes` grants access to.
+pub type JsonView =
+	Box<dyn Fn(&HashMap<String, String>, &Scopes) -> Result<Value, ApiError> + Send + Sync>;
+
+/// What a collector rendered after its last refresh. It is never changed, requests
+/// read it while the collector refreshes again.
+pub struct Published {
+	/// Whether the collector was active, inactive collectors render as null
+	pub active: bool,
+	pub families: Vec<MetricFamily>,
+	pub json: JsonView,
+}
+
+impl Published {
+	/// Renders a collector with the settings it was refreshed with.
+	pub fn new(collector: &dyn Collector, settings: &Settings, system_info: &SystemInfo) -> Self {
+		if !is_active(collector, settings) {
+			let filters = collector.filters();
+			return Published {
+				active: false,
+				families: Vec::new(),
+				json: Box::new(move |query, _scopes| {
+					check_filters(query, filters)?;
+					Ok(Value::Null)
+				}),
+			};
+		}
+
+		let mut metrics = MetricsBuilder::new();
+		metrics.subsystem(collector.name());
+		collector.metrics(settings, system_info, &mut metrics);
+
+		Published {
+			active: true,
+			families: metrics.build(),
+			json: collector.json(),
+		}
+	}
+}
+
+/// Creates the built-in collectors, in the order they are refreshed and rendered.
+/// Creating a collector is cheap, the system is only read on the first refresh.
+pub fn registry() -> Vec<Box<dyn Collector>> {
+	vec![
+		Box::new(CpuCollector::new()),
+		Box::new(MemoryCollector::new()),
+		Box::new(SwapCollector::new()),
+		Box::new(EnergyCollector::new()),
+		Box::new(StorageCollector::new()),
+		Box::new(NetworkCollector::new()),
+		Box::new(ComponentCollector::new()),
+		Box::new(ProcessCollector::new()),
+		Box::new(DockerCollector::new()),
+		Box::new(UpsCollector::new()),
+		Box::new(BatteryCollector::new()),
+	]
+}
+
+/// Whether a collector is refreshed and rendered: it is not listed in
+/// `disabled_subsystems` and its subsystem is available on this host.
+pub fn is_active(collector: &dyn Collector, settings: &Settings) -> bool {
+	!settings
+		.disabled
+		.iter()
+		.any(|name| name == collector.name())
+		&& collector.enabled(settings)
+}
+
+/// State of the refreshes of a collector.
+#[derive(Clone)]
+pub struct CollectorStatus {
+	/// Start of the refresh in progress
+	pub running: Option<Instant>,
+	pub next_run: Instant,
+	/// How long the last finished refresh took
+	pub last_duration: Option<Duration>,
+	pub last_success: Option<Duration>,
+	/// Why the last refresh failed or that it timed out, cleared by the next success
+	pub error: Option<String>,
+	/// Number of refreshes that failed or timed out since the start
+	pub errors: u64,
+	/// The refresh in progress was counted as an error when it timed out, so its late
+	/// result is not counted again
+	pub timed_out: bool,
+	/// The settings were reloaded while the collector was refreshing, so its entries are
+	/// pruned before the next refresh
+	pub prune: bool,
+}
+
+impl CollectorStatus {
+	pub fn new() -> Self {
+		CollectorStatus {
+			running: None,
+			next_run: Instant::now(),
+			last_duration: None,
+			last_success: None,
+			error: None,
+			errors: 0,
+			timed_out: false,
+			prune: false,
+		}
+	}
+
+	/// Whether the metrics of the collector are left over from an earlier refresh.
+	pub fn is_stale(&self) -> bool {
+		self.error.is_some()
+	}
+}
+
+impl Default for CollectorStatus {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// A registered collector with the state of its refreshes and what it published last.
+/// The collector stays locked while it refreshes, the status only briefly, so a hanging
+/// refresh never blocks the scheduler. Requests only read the published output.
+pub struct CollectorSlot {
+	pub name: &'static str,
+	pub collector: Mutex<Box<dyn Collector>>,
+	pub status: Mutex<CollectorStatus>,
+	pub published: ArcSwap<Published>,
+}
+
+impl CollectorSlot {
+	pub fn new(collector: Box<dyn Collector>, settings: &Settings, system_info: &SystemInfo) -> Self {
+		let published = Published::new(collector.as_ref(), settings, system_info);
+
+		CollectorSlot {
+			name: collector.name(),
+			collector: Mutex::new(collector),
+			status: Mutex::new(CollectorStatus::new()),
+			published: ArcSwap::from_pointee(published),
+		}
+	}
+
+	/// Locks the collector. A refresh that panicked leaves its lock poisoned, the
+	/// collector is used anyway since every refresh reads the system again.
+	pub fn lock(&self) -> MutexGuard<'_, Box<dyn Collector>> {
+		self
+			.collector
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Locks the collector unless it is refreshing, see `lock`.
+	pub fn try_lock(&self) -> Option<MutexGuard<'_, Box<dyn Collector>>> {
+		match self.collector.try_lock() {
+			Ok(collector) => Some(collector),
+			Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+			Err(TryLockError::WouldBlock) => None,
+		}
+	}
+
+	/// Renders the collector again, e.g. after the settings changed.
+	pub fn publish(&self, collector: &dyn Collector, settings: &Settings, system_info: &SystemInfo) {
+		let published = Published::new(collector, settings, system_info);
+		self.published.store(Arc::new(published));
+	}
+
+	/// Refreshes the collector, publishes what it read and records the outcome. Errors
+	/// are only logged when they change, so a UPS that stays unreachable does not flood
+	/// the log.
+	pub fn refresh(&self, settings: &Settings, system_info: &SystemInfo, now: Duration) {
+		let mut collector = self.lock();
+		if std::mem::take(&mut self.status.lock().unwrap().prune) {
+			collector.prune(settings);
+		}
+		let started = Instant::now();
+		let result = match panic::catch_unwind(AssertUnwindSafe(|| collector.refresh(settings, now))) {
+			Ok(result) => result,
+			Err(_) => Err(String::from("refresh panicked")),
+		};
+		let duration = started.elapsed();
+		self.publish(collector.as_ref(), settings, system_info);
+		drop(collector);
+
+		let mut status = self.status.lock().unwrap();
+		status.running = None;
+		status.last_duration = Some(duration);
+		let timed_out = std::mem::take(&mut status.timed_out);
+		match result {
+			Ok(()) => {
+				if status.error.is_some() {
+					println!("Collector {} recovered", self.name);
+				}
+				status.last_success = Some(now);
+				status.error = None;
+			}
+			Err(e) => {
+				if status.error.as_ref() != Some(&e) {
+					eprintln!(
+						"Failed to refresh {}, keeping its last values: {}",
+						self.name, e
+					);
+				}
+				status.error = Some(e);
+				if !timed_out {
+					status.errors += 1;
+				}
+			}
+		}
+	}
+}
+
+/// What is known about a subsystem without a monitor, e.g. to validate token scopes
+/// and alert rules.
+pub struct Subsystem {
+	pub name: &'static str,
+	pub key: &'static str,
+	pub filters: &'static [&'static str],
+	pub entry_labels: &'static [&'static str],
+	pub entry_fields: &'static [Option<&'static str>],
+}
+
+static SUBSYSTEMS: LazyLock<Vec<Subsystem>> = LazyLock::new(|| {
+	registry()
+		.iter()
+		.map(|collector| Subsystem {
+			name: collector.name(),
+			key: collector.key(),
+			filters: collector.filters(),
+			entry_labels: collector.entry_labels(),
+			entry_fields: collector.entry_fields(),
+		})
+		.collect()
+});
+
+/// The subsystems of the registered collectors.
+pub fn subsystems() -> &'static [Subsystem] {
+	&SUBSYSTEMS
+}
+
+pub fn subsystem(name: &str) -> Option<&'static Subsystem> {
+	SUBSYSTEMS.iter().find(|subsystem| subsystem.name == name)
+}
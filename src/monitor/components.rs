@@ -1,10 +1,21 @@
+use crate::api::select;
+use crate::metrics::{round2, MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::serialize_timestamp;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::time::Duration;
+use sysinfo::Components;
 
+#[derive(Clone, Serialize)]
 pub struct Component {
 	pub label: String,
 	pub temperature: Option<f32>,
 	pub critical: Option<f32>,
 	pub max: Option<f32>,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -25,3 +36,110 @@ impl Default for Component {
 		Self::new()
 	}
 }
+
+/// Collects the temperatures of the hardware components with a sensor.
+pub struct ComponentCollector {
+	components: Option<Components>,
+	component_list: HashMap<String, Component>,
+}
+
+impl ComponentCollector {
+	pub fn new() -> Self {
+		ComponentCollector {
+			components: None,
+			component_list: HashMap::new(),
+		}
+	}
+}
+
+impl Default for ComponentCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for ComponentCollector {
+	fn name(&self) -> &'static str {
+		"components"
+	}
+
+	fn key(&self) -> &'static str {
+		"component_list"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["component"]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let components = self
+			.components
+			.get_or_insert_with(Components::new_with_refreshed_list);
+		components.refresh(true);
+
+		for component in components.list() {
+			let label = component.label().to_string();
+
+			if !settings.components.is_empty() && !settings.components.contains(&label) {
+				continue; // Skip if not in the user-defined components list
+			}
+
+			self.component_list.insert(
+				component.label().to_string(),
+				Component {
+					label: component.label().to_string(),
+					temperature: component.temperature(),
+					critical: component.critical(),
+					max: component.max(),
+					refreshed: now,
+				},
+			);
+		}
+
+		Ok(())
+	}
+
+	fn prune(&mut self, settings: &Settings) {
+		self
+			.component_list
+			.retain(|label, _| settings.components.is_empty() || settings.components.contains(label));
+	}
+
+	fn metrics(&self, _settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.component_list.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"hardware_component_temperature",
+			"Temperature of hardware components in celsius",
+			MetricType::Gauge,
+			Some("celsius"),
+		);
+		for (label, component) in &self.component_list {
+			metrics.sample(
+				round2(component.temperature.unwrap_or(0.0)),
+				&[("component", label)],
+				component.refreshed,
+			);
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let component_list = self.component_list.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(
+				&component_list,
+				query,
+				filters,
+				(subsystem, scopes),
+				|_, name, _| name.to_string(),
+			)
+		})
+	}
+}
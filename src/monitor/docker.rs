@@ -1,12 +1,27 @@
+use serde::Serialize;
 use std::collections::HashMap;
 use std::fs;
 use std::io::{BufRead, BufReader};
-use std::process::{Child, Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::utils::mega_bits;
+use crate::api::select;
+use crate::exporter;
+use crate::metrics::{round2, MetricType, MetricsBuilder};
+use crate::monitor::collector::{self, Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::{command_output, mega_bits, serialize_timestamp};
+
+/// Delay before `docker stats` is started again after it exited for the first time. It
+/// doubles with every exit up to `MAX_RESTART_DELAY`.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay before `docker stats` is started again. A child that ran for longer
+/// than this before it exited is restarted after `MIN_RESTART_DELAY` again.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(300);
 
 struct NetDevCounters {
 	rx_bytes: u64,
@@ -17,6 +32,7 @@ struct NetDevCounters {
 	tx_errors: u64,
 }
 
+#[derive(Clone, Serialize)]
 pub struct DockerContainer {
 	pub name: String,
 	pub pid: u32,
@@ -35,6 +51,7 @@ pub struct DockerContainer {
 	pub block_read_bytes: u64,
 	pub block_write_bytes: u64,
 	pub pids: u64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -143,11 +160,21 @@ impl DockerMonitor {
 				});
 			}
 			Err(e) => {
+				exporter::record_subprocess_failure("docker", "spawn");
 				eprintln!("Failed to start docker stats: {}", e);
 			}
 		}
 	}
 
+	/// How the `docker stats` child exited, if it was started and is no longer
+	/// running, e.g. because the Docker daemon restarted.
+	pub fn exit_status(&mut self) -> Option<ExitStatus> {
+		match self.child.as_mut() {
+			Some(child) => child.try_wait().ok().flatten(),
+			None => None,
+		}
+	}
+
 	pub fn stop(&mut self) {
 		if let Some(ref mut child) = self.child {
 			let _ = child.kill();
@@ -181,7 +208,7 @@ impl DockerMonitor {
 		}
 	}
 
-	pub fn snapshot(&mut self, now: Duration) -> HashMap<String, DockerContainer> {
+	pub fn snapshot(&mut self, now: Duration, timeout: Duration) -> HashMap<String, DockerContainer> {
 		let mut map = self.containers.lock().unwrap();
 		let mut result = HashMap::new();
 
@@ -192,7 +219,7 @@ impl DockerMonitor {
 			.collect();
 
 		if !names_needing_pid.is_empty() {
-			let pids = lookup_container_pids(&names_needing_pid);
+			let pids = lookup_container_pids(&names_needing_pid, timeout);
 			for (name, pid) in pids {
 				if let Some(c) = map.get_mut(&name) {
 					c.pid = pid;
@@ -271,13 +298,14 @@ impl Drop for DockerMonitor {
 	}
 }
 
-fn lookup_container_pids(names: &[String]) -> HashMap<String, u32> {
+fn lookup_container_pids(names: &[String], timeout: Duration) -> HashMap<String, u32> {
 	let mut result = HashMap::new();
 
 	for name in names {
-		let output = Command::new("docker")
-			.args(["inspect", "--format", "{{.State.Pid}}", name])
-			.output();
+		let output = command_output(
+			Command::new("docker").args(["inspect", "--format", "{{.State.Pid}}", name]),
+			timeout,
+		);
 
 		if let Ok(out) = output {
 			if out.status.success() {
@@ -458,3 +486,345 @@ fn parse_size(s: &str) -> u64 {
 
 	0
 }
+
+/// Collects the resource usage of Docker containers from a `docker stats` child that
+/// is started on the first refresh and restarted when the container filter changes or,
+/// with an increasing delay, when it exits. The child is stopped when containers are no
+/// longer collected.
+pub struct DockerCollector {
+	docker_monitor: Option<DockerMonitor>,
+	containers: Vec<String>,
+	docker_containers: HashMap<String, DockerContainer>,
+	/// When the current `docker stats` child was started
+	started: Instant,
+	/// Number of times `docker stats` exited in a row, which sets the restart delay
+	exits: u32,
+	/// When `docker stats` is started again after it exited, with the error reported
+	/// until then
+	restart: Option<(Instant, String)>,
+}
+
+impl DockerCollector {
+	pub fn new() -> Self {
+		DockerCollector {
+			docker_monitor: None,
+			containers: Vec::new(),
+			docker_containers: HashMap::new(),
+			started: Instant::now(),
+			exits: 0,
+			restart: None,
+		}
+	}
+
+	fn start_docker_monitor(&mut self, containers: &[String]) {
+		let mut dm = DockerMonitor::new();
+		dm.start(containers.to_vec());
+		self.docker_monitor = Some(dm);
+		self.containers = containers.to_vec();
+		self.started = Instant::now();
+	}
+
+	fn stop_docker_monitor(&mut self) {
+		if let Some(mut dm) = self.docker_monitor.take() {
+			dm.stop();
+		}
+	}
+}
+
+impl Default for DockerCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for DockerCollector {
+	fn name(&self) -> &'static str {
+		"containers"
+	}
+
+	fn key(&self) -> &'static str {
+		"docker_containers"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["container"]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		// The docker stats child is only restarted when the container filter changed
+		let restart = self.docker_monitor.is_some() && self.containers != settings.containers;
+		if restart {
+			self.stop_docker_monitor();
+		}
+
+		// A child that exited is started again once its delay passed, the last values
+		// are kept until then
+		if let Some((restart_at, error)) = &self.restart {
+			if Instant::now() < *restart_at {
+				return Err(error.clone());
+			}
+			self.restart = None;
+			exporter::record_docker_stats_restart();
+			self.start_docker_monitor(&settings.containers);
+		}
+
+		if self.docker_monitor.is_none()
+			&& (restart || settings.docker || !settings.containers.is_empty())
+		{
+			self.start_docker_monitor(&settings.containers);
+		}
+
+		let exit_status = self.docker_monitor.as_mut().and_then(|dm| dm.exit_status());
+		if let Some(status) = exit_status {
+			self.stop_docker_monitor();
+			if self.started.elapsed() >= MAX_RESTART_DELAY {
+				self.exits = 0;
+			}
+			let delay = MIN_RESTART_DELAY
+				.saturating_mul(2u32.saturating_pow(self.exits))
+				.min(MAX_RESTART_DELAY);
+			self.exits = self.exits.saturating_add(1);
+
+			let error = format!(
+				"docker stats exited ({}), restarting it in {}s",
+				status,
+				delay.as_secs()
+			);
+			self.restart = Some((Instant::now() + delay, error.clone()));
+			return Err(error);
+		}
+
+		let timeout = settings.schedule(self.name()).timeout;
+		if let Some(ref mut dm) = self.docker_monitor {
+			self.docker_containers = dm.snapshot(now, timeout);
+		}
+
+		Ok(())
+	}
+
+	fn prune(&mut self, settings: &Settings) {
+		// Nothing is collected any more, so docker stats is not needed either
+		if !collector::is_active(self, settings) || (!settings.docker && settings.containers.is_empty())
+		{
+			self.stop_docker_monitor();
+			self.restart = None;
+			self.exits = 0;
+			self.docker_containers.clear();
+			return;
+		}
+
+		self
+			.docker_containers
+			.retain(|name, _| settings.containers.is_empty() || settings.containers.contains(name));
+	}
+
+	fn metrics(&self, _settings: &Settings, system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.docker_containers.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"docker_cpu_usage",
+			"Docker container CPU usage in percent",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				round2(container.cpu_percent),
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_memory",
+			"Docker container memory usage in percent",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				round2(container.memory_percent),
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_memory_usage",
+			"Docker container memory usage in bytes",
+			MetricType::Gauge,
+			Some("bytes"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.memory_usage as f64,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_memory_limit",
+			"Docker container memory limit in bytes",
+			MetricType::Gauge,
+			Some("bytes"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.memory_limit as f64,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_network_download_speed",
+			"Docker container download speed in bytes/sec",
+			MetricType::Gauge,
+			Some("bytes_per_second"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.download,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_network_upload_speed",
+			"Docker container upload speed in bytes/sec",
+			MetricType::Gauge,
+			Some("bytes_per_second"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.upload,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_network_packets_received",
+			"Docker container total number of incoming packets",
+			MetricType::Counter,
+			None,
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.counter_sample(
+				container.total_packets_received as f64,
+				&[("container", name)],
+				container.refreshed,
+				system_info.boot_time,
+			);
+		}
+
+		metrics.header(
+			"docker_network_packets_transmitted",
+			"Docker container total number of outgoing packets",
+			MetricType::Counter,
+			None,
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.counter_sample(
+				container.total_packets_transmitted as f64,
+				&[("container", name)],
+				container.refreshed,
+				system_info.boot_time,
+			);
+		}
+
+		metrics.header(
+			"docker_network_errors_received",
+			"Docker container total number of incoming errors",
+			MetricType::Counter,
+			None,
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.counter_sample(
+				container.total_errors_on_received as f64,
+				&[("container", name)],
+				container.refreshed,
+				system_info.boot_time,
+			);
+		}
+
+		metrics.header(
+			"docker_network_errors_transmitted",
+			"Docker container total number of outgoing errors",
+			MetricType::Counter,
+			None,
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.counter_sample(
+				container.total_errors_on_transmitted as f64,
+				&[("container", name)],
+				container.refreshed,
+				system_info.boot_time,
+			);
+		}
+
+		metrics.header(
+			"docker_block_read",
+			"Docker container block read in bytes",
+			MetricType::Gauge,
+			Some("bytes"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.block_read_bytes as f64,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_block_write",
+			"Docker container block write in bytes",
+			MetricType::Gauge,
+			Some("bytes"),
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.block_write_bytes as f64,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+
+		metrics.header(
+			"docker_pids",
+			"Docker container number of PIDs",
+			MetricType::Gauge,
+			None,
+		);
+		for (name, container) in &self.docker_containers {
+			metrics.sample(
+				container.pids as f64,
+				&[("container", name)],
+				container.refreshed,
+			);
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let docker_containers = self.docker_containers.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(
+				&docker_containers,
+				query,
+				filters,
+				(subsystem, scopes),
+				|_, name, _| name.to_string(),
+			)
+		})
+	}
+}
@@ -1,3 +1,11 @@
+use crate::api::check_filters;
+use crate::metrics::{round2, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::{command_output, serialize_timestamp};
+use serde::Serialize;
+use serde_json::json;
 use std::{process::Command, time::Duration};
 
 pub struct DCMI {
@@ -5,10 +13,11 @@ pub struct DCMI {
 	pub sampling_period_seconds: Option<u64>,
 }
 
+#[derive(Serialize)]
 pub struct Energy {
 	pub power_consumption: f64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
-	pub is_updating: bool,
 }
 
 impl Energy {
@@ -16,21 +25,21 @@ impl Energy {
 		Energy {
 			power_consumption: 0.0,
 			refreshed: Duration::from_secs(0),
-			is_updating: false,
 		}
 	}
 
-	pub fn get_power_usage_w() -> Option<f64> {
-		Self::get_dcmi_power_with_info()
+	pub fn get_power_usage_w(timeout: Duration) -> Option<f64> {
+		Self::get_dcmi_power_with_info(timeout)
 			.and_then(|dcmi| dcmi.power)
-			.or_else(Self::get_sensor_power)
+			.or_else(|| Self::get_sensor_power(timeout))
 	}
 
-	pub fn get_dcmi_power_with_info() -> Option<DCMI> {
-		let output = Command::new("ipmitool")
-			.args(["dcmi", "power", "reading"])
-			.output()
-			.ok()?;
+	pub fn get_dcmi_power_with_info(timeout: Duration) -> Option<DCMI> {
+		let output = command_output(
+			Command::new("ipmitool").args(["dcmi", "power", "reading"]),
+			timeout,
+		)
+		.ok()?;
 
 		if !output.status.success() {
 			return None;
@@ -69,8 +78,8 @@ impl Energy {
 		})
 	}
 
-	pub fn get_sensor_power() -> Option<f64> {
-		let output = Command::new("ipmitool").arg("sensor").output().ok()?;
+	pub fn get_sensor_power(timeout: Duration) -> Option<f64> {
+		let output = command_output(Command::new("ipmitool").arg("sensor"), timeout).ok()?;
 
 		if !output.status.success() {
 			return None;
@@ -134,26 +143,89 @@ impl Energy {
 			None
 		}
 	}
+}
 
-	pub fn refresh_dcmi(&mut self, now: Duration) {
-		if let Some(power) = Self::get_dcmi_power_with_info().and_then(|dcmi| dcmi.power) {
-			self.power_consumption = power;
-		}
-
-		self.refreshed = now;
+impl Default for Energy {
+	fn default() -> Self {
+		Self::new()
 	}
+}
 
-	pub fn refresh_sensor(&mut self, now: Duration) {
-		if let Some(power) = Self::get_sensor_power() {
-			self.power_consumption = power;
-		}
+/// Collects the power consumption reported by IPMI.
+pub struct EnergyCollector {
+	energy: Energy,
+}
 
-		self.refreshed = now;
+impl EnergyCollector {
+	pub fn new() -> Self {
+		EnergyCollector {
+			energy: Energy::new(),
+		}
 	}
 }
 
-impl Default for Energy {
+impl Default for EnergyCollector {
 	fn default() -> Self {
 		Self::new()
 	}
 }
+
+impl Collector for EnergyCollector {
+	fn name(&self) -> &'static str {
+		"energy"
+	}
+
+	fn key(&self) -> &'static str {
+		"energy"
+	}
+
+	fn enabled(&self, settings: &Settings) -> bool {
+		settings.energy.enabled
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let schedule = settings.schedule(self.name());
+
+		// DCMI is fast but averaged over its sampling period, only worth it when
+		// the collector refreshes at least that rarely
+		let use_dcmi = settings
+			.energy
+			.interval
+			.map_or(false, |interval| interval <= schedule.interval.as_secs());
+
+		let power = if use_dcmi {
+			Energy::get_dcmi_power_with_info(schedule.timeout).and_then(|dcmi| dcmi.power)
+		} else {
+			Energy::get_sensor_power(schedule.timeout)
+		};
+
+		match power {
+			Some(power) => {
+				self.energy.power_consumption = power;
+				self.energy.refreshed = now;
+				Ok(())
+			}
+			None => Err(String::from("ipmitool returned no power reading")),
+		}
+	}
+
+	fn metrics(&self, _settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		metrics.gauge(
+			"power_consumption",
+			"Power consumption in watts",
+			round2(self.energy.power_consumption),
+			Some("watts"),
+			&[],
+			self.energy.refreshed,
+		);
+	}
+
+	fn json(&self) -> JsonView {
+		let energy = json!(self.energy);
+		let filters = self.filters();
+		Box::new(move |query, _scopes| {
+			check_filters(query, filters)?;
+			Ok(energy.clone())
+		})
+	}
+}
@@ -1,11 +1,22 @@
+use crate::api::check_filters;
+use crate::metrics::{round2, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::serialize_timestamp;
+use serde::Serialize;
+use serde_json::json;
 use std::time::Duration;
+use sysinfo::{MemoryRefreshKind, System};
 
+#[derive(Serialize)]
 pub struct Memory {
 	pub total: u64,
 	pub available: u64,
 	pub used: u64,
 	pub free: u64,
 	pub percent: f64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -27,3 +38,113 @@ impl Default for Memory {
 		Self::new()
 	}
 }
+
+/// Collects the usage of the physical memory.
+pub struct MemoryCollector {
+	system: System,
+	memory: Memory,
+}
+
+impl MemoryCollector {
+	pub fn new() -> Self {
+		MemoryCollector {
+			system: System::new(),
+			memory: Memory::new(),
+		}
+	}
+}
+
+impl Default for MemoryCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for MemoryCollector {
+	fn name(&self) -> &'static str {
+		"memory"
+	}
+
+	fn key(&self) -> &'static str {
+		"memory"
+	}
+
+	fn refresh(&mut self, _settings: &Settings, now: Duration) -> Result<(), String> {
+		self
+			.system
+			.refresh_memory_specifics(MemoryRefreshKind::nothing().with_ram());
+		self.memory.total = self.system.total_memory();
+		self.memory.available = self.system.available_memory();
+		self.memory.used = self.system.used_memory();
+		self.memory.free = self.system.free_memory();
+
+		let memory_percent: f64 =
+			(self.system.used_memory() as f64 / self.system.total_memory() as f64) * 100.0;
+		self.memory.percent = if !f64::is_nan(memory_percent) {
+			memory_percent
+		} else {
+			0.0
+		};
+
+		self.memory.refreshed = now;
+
+		Ok(())
+	}
+
+	fn metrics(&self, settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		let memory = &self.memory;
+
+		if settings.memory_details || settings.all_metrics {
+			metrics.gauge(
+				"memory_total",
+				"Total memory in bytes",
+				memory.total as f64,
+				Some("bytes"),
+				&[],
+				memory.refreshed,
+			);
+			metrics.gauge(
+				"memory_available",
+				"Available memory in bytes",
+				memory.available as f64,
+				Some("bytes"),
+				&[],
+				memory.refreshed,
+			);
+			metrics.gauge(
+				"memory_used",
+				"Used memory in bytes",
+				memory.used as f64,
+				Some("bytes"),
+				&[],
+				memory.refreshed,
+			);
+			metrics.gauge(
+				"memory_free",
+				"Free memory in bytes",
+				memory.free as f64,
+				Some("bytes"),
+				&[],
+				memory.refreshed,
+			);
+		}
+
+		metrics.gauge(
+			"memory",
+			"Used memory in percent",
+			round2(memory.percent),
+			Some("percent"),
+			&[],
+			memory.refreshed,
+		);
+	}
+
+	fn json(&self) -> JsonView {
+		let memory = json!(self.memory);
+		let filters = self.filters();
+		Box::new(move |query, _scopes| {
+			check_filters(query, filters)?;
+			Ok(memory.clone())
+		})
+	}
+}
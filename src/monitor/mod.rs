@@ -1,27 +1,16 @@
-use self::{
-	memory::Memory, network::Network, processor::Processor, settings::Settings, storage::Storage,
-	swap::Swap,
-};
-use crate::monitor::energy::Energy;
-use crate::monitor::processor::Thread;
-use crate::monitor::ups::UPS;
-use crate::utils::mega_bits;
-use battery::Battery;
+use self::collector::{Collector, CollectorSlot};
+use self::settings::Settings;
+use self::snapshot::Snapshot;
+use crate::config::Config;
+use arc_swap::ArcSwap;
 use chrono::Utc;
-use components::Component;
-use docker::DockerContainer;
-use processes::Process;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
-use sysinfo::{
-	Components, CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, Networks, Pid,
-	ProcessRefreshKind, System,
-};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use sysinfo::System;
 use system_info::SystemInfo;
 
 pub mod battery;
+pub mod collector;
 pub mod components;
 pub mod docker;
 pub mod energy;
@@ -29,45 +18,43 @@ pub mod memory;
 pub mod network;
 pub mod processes;
 pub mod processor;
+pub mod scheduler;
 pub mod settings;
+pub mod snapshot;
 pub mod storage;
 pub mod swap;
 pub mod system_info;
 pub mod ups;
 
+/// The collectors and the latest snapshot of what they read. Nothing here is behind a
+/// lock that requests wait for: settings and snapshots are replaced as a whole.
 pub struct Monitor {
-	pub system: System,
-	pub disks: Disks,
-	pub networks: Networks,
-	pub components: Components,
-	pub settings: Settings,
+	pub settings: ArcSwap<Settings>,
 	pub system_info: SystemInfo,
-	pub processor: Processor,
-	pub memory: Memory,
-	pub swap: Swap,
-	pub energy: Arc<Mutex<Energy>>,
-	pub upses: HashMap<String, UPS>,
-	pub batteries: HashMap<String, Battery>,
-	pub storage_devices: HashMap<String, Storage>,
-	pub network_interfaces: HashMap<String, Network>,
-	pub component_list: HashMap<String, Component>,
-	pub process_list: HashMap<String, Process>,
-	pub docker_monitor: Option<docker::DockerMonitor>,
-	pub docker_containers: HashMap<String, DockerContainer>,
-	pub refreshed: Instant,
+	pub collectors: Vec<Arc<CollectorSlot>>,
+	pub snapshot: ArcSwap<Snapshot>,
+	/// When Rabbit Monitor started, in seconds since the Unix epoch
+	pub started: u64,
+	/// Held while a snapshot is assembled, so an older one never replaces a newer one,
+	/// with where every snapshot is sent once it is published
+	publishing: Mutex<Option<Sender<Update>>>,
 }
 
-impl Monitor {
-	pub fn new() -> Self {
-		let mut sys = System::new_all();
-		sys.refresh_all();
+/// A published snapshot, as sent to the subscriber of the monitor.
+pub struct Update {
+	pub snapshot: Arc<Snapshot>,
+	/// Collectors that refreshed for this snapshot. It is empty when the snapshot was
+	/// published for another reason, e.g. a reload or a refresh that timed out.
+	pub refreshed: Vec<&'static str>,
+}
 
-		let disks = Disks::new_with_refreshed_list_specifics(
-			DiskRefreshKind::nothing().with_storage().with_io_usage(),
-		);
-		let networks = Networks::new_with_refreshed_list();
-		let components = Components::new_with_refreshed_list();
+impl Monitor {
+	pub fn new(settings: Settings) -> Self {
+		Self::with_collectors(settings, collector::registry())
+	}
 
+	/// Creates a monitor of other collectors than the built-in ones, e.g. in tests.
+	pub fn with_collectors(settings: Settings, collectors: Vec<Box<dyn Collector>>) -> Self {
 		let system_info = SystemInfo {
 			name: System::name().unwrap_or("unknown".to_string()),
 			kernel_version: System::kernel_version().unwrap_or("unknown".to_string()),
@@ -78,328 +65,86 @@ impl Monitor {
 			boot_time: System::boot_time(),
 		};
 
-		let mut processor = Processor::new();
-		processor.arch = System::cpu_arch();
-		processor.thread_count = sys.cpus().len() as u64;
+		let collectors: Vec<Arc<CollectorSlot>> = collectors
+			.into_iter()
+			.map(|collector| Arc::new(CollectorSlot::new(collector, &settings, &system_info)))
+			.collect();
+		let started = Utc::now().timestamp() as u64;
+		let snapshot = Snapshot::new(&settings, &system_info, &collectors, started);
 
 		Monitor {
-			system: sys,
-			disks,
-			networks,
-			components,
-			settings: Settings::new(),
+			settings: ArcSwap::from_pointee(settings),
 			system_info,
-			processor,
-			memory: Memory::new(),
-			swap: Swap::new(),
-			energy: Arc::new(Mutex::new(Energy::new())),
-			storage_devices: HashMap::new(),
-			network_interfaces: HashMap::new(),
-			component_list: HashMap::new(),
-			process_list: HashMap::new(),
-			docker_monitor: None,
-			docker_containers: HashMap::new(),
-			upses: HashMap::new(),
-			batteries: HashMap::new(),
-			refreshed: Instant::now(),
+			collectors,
+			snapshot: ArcSwap::from_pointee(snapshot),
+			started,
+			publishing: Mutex::new(None),
 		}
 	}
 
-	pub fn start_docker_monitor(&mut self) {
-		let mut dm = docker::DockerMonitor::new();
-		dm.start(self.settings.containers.clone());
-		self.docker_monitor = Some(dm);
-	}
-
-	pub fn refresh(&mut self) {
-		let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
-
-		self.cpu(now);
-		self.memory(now);
-		self.swap(now);
-		self.storage(now);
-		self.network(now);
-		self.componenet(now);
-		self.processes(now);
-		self.ups(now);
-		self.docker(now);
-
-		if self.settings.energy.enabled {
-			self.energy_async(now);
-		}
-
-		self.refreshed = Instant::now();
-	}
-
-	pub fn cpu(&mut self, now: Duration) {
-		let load_average = System::load_average();
-		self.processor.min1 = load_average.one;
-		self.processor.min5 = load_average.five;
-		self.processor.min15 = load_average.fifteen;
-
-		self
-			.system
-			.refresh_cpu_specifics(CpuRefreshKind::nothing().with_cpu_usage().with_frequency());
-
-		self.processor.percent = self.system.global_cpu_usage();
-
-		self.processor.threads = self
-			.system
-			.cpus()
-			.iter()
-			.map(|cpu| Thread {
-				name: cpu.name().into(),
-				brand: cpu.brand().into(),
-				cpu_usage: cpu.cpu_usage(),
-				frequency: cpu.frequency(),
-			})
-			.collect();
-
-		self.processor.refreshed = now;
-	}
-
-	pub fn memory(&mut self, now: Duration) {
-		self
-			.system
-			.refresh_memory_specifics(MemoryRefreshKind::nothing().with_ram());
-		self.memory.total = self.system.total_memory();
-		self.memory.available = self.system.available_memory();
-		self.memory.used = self.system.used_memory();
-		self.memory.free = self.system.free_memory();
-
-		let memory_percent: f64 =
-			(self.system.used_memory() as f64 / self.system.total_memory() as f64) * 100.0;
-		self.memory.percent = if !f64::is_nan(memory_percent) {
-			memory_percent
-		} else {
-			0.0
-		};
-
-		self.memory.refreshed = now;
-	}
-
-	pub fn swap(&mut self, now: Duration) {
-		self
-			.system
-			.refresh_memory_specifics(MemoryRefreshKind::nothing().with_swap());
-		self.swap.total = self.system.total_swap();
-		self.swap.used = self.system.used_swap();
-		self.swap.free = self.system.free_swap();
-
-		let swap_percent: f64 =
-			(self.system.used_swap() as f64 / self.system.total_swap() as f64) * 100.0;
-		self.swap.percent = if !f64::is_nan(swap_percent) {
-			swap_percent
-		} else {
-			0.0
-		};
-
-		self.swap.refreshed = now;
-	}
-
-	pub fn energy_async(&self, now: Duration) {
-		let energy_clone = Arc::clone(&self.energy);
-		let use_dcmi = self
-			.settings
-			.energy
-			.interval
-			.map_or(false, |interval| interval <= self.settings.cache);
-
-		// Check if we're already updating
-		{
-			let energy_guard = energy_clone.lock().unwrap();
-			if energy_guard.is_updating {
-				return;
-			}
-		}
-
-		if use_dcmi {
-			// DCMI is fast, do it synchronously
-			let mut energy_guard = energy_clone.lock().unwrap();
-			energy_guard.refresh_dcmi(now);
-		} else {
-			// Sensor reading is slow, spawn background thread
-			thread::spawn(move || {
-				let mut energy_guard = energy_clone.lock().unwrap();
-				energy_guard.is_updating = true;
-				drop(energy_guard); // Release lock during slow operation
-
-				if let Some(power) = Energy::get_sensor_power() {
-					let mut energy_guard = energy_clone.lock().unwrap();
-					energy_guard.power_consumption = power;
-					energy_guard.refreshed = now;
-					energy_guard.is_updating = false;
-				} else {
-					let mut energy_guard = energy_clone.lock().unwrap();
-					energy_guard.is_updating = false;
+	/// Applies a reloaded configuration, removes the entries that are no longer
+	/// selected by the new filters and publishes the result. Collectors that are
+	/// refreshing are pruned before their next refresh instead, so a hanging refresh
+	/// does not hold up the reload.
+	pub fn reload_settings(&self, config: &Config) {
+		let mut settings = Settings::clone(&self.settings.load());
+		settings.apply_config(config);
+		self.settings.store(Arc::new(settings));
+
+		let settings = self.settings.load();
+		for slot in &self.collectors {
+			match slot.try_lock() {
+				Some(mut collector) => {
+					collector.prune(&settings);
+					slot.publish(collector.as_ref(), &settings, &self.system_info);
 				}
-			});
-		}
-	}
-
-	pub fn storage(&mut self, now: Duration) {
-		let monitoring_time: u64 = self.refreshed.elapsed().as_millis() as u64;
-		self.disks.refresh_specifics(
-			true,
-			DiskRefreshKind::nothing().with_storage().with_io_usage(),
-		);
-
-		let mut millis: f64 = monitoring_time as f64 / 1000.0;
-		if millis == 0.0 {
-			millis = 1.0;
-		}
-
-		for disk in self.disks.list() {
-			let mount = disk.mount_point().to_string_lossy().to_string();
-
-			if !self.settings.mounts.is_empty() && !self.settings.mounts.contains(&mount) {
-				continue; // Skip if not in the user-defined mount list
-			}
-
-			let name = disk.name().to_string_lossy().to_string();
-			let total = disk.total_space();
-			let free = disk.available_space();
-			let used = total - free;
-			let usage = disk.usage();
-
-			let mut percent: f64 = (used as f64 / total as f64) * 100.0;
-			percent = if !f64::is_nan(percent) { percent } else { 0.0 };
-
-			self.storage_devices.insert(
-				name.clone(),
-				Storage {
-					name,
-					mount_point: mount,
-					total,
-					used,
-					free,
-					percent,
-					read_speed: usage.read_bytes as f64 / millis,
-					write_speed: usage.written_bytes as f64 / millis,
-					total_read_bytes: usage.total_read_bytes,
-					total_written_bytes: usage.total_written_bytes,
-					refreshed: now,
-				},
-			);
-		}
-	}
-
-	pub fn network(&mut self, now: Duration) {
-		let monitoring_time: u64 = self.refreshed.elapsed().as_millis() as u64;
-		self.networks.refresh(true);
-
-		let mut millis: f64 = monitoring_time as f64 / 1000.0;
-		if millis == 0.0 {
-			millis = 1.0;
-		}
-
-		for (iface, network) in self.networks.list() {
-			if !self.settings.interfaces.is_empty() && !self.settings.interfaces.contains(iface) {
-				continue; // Skip if not in the user-defined interface list
-			}
-
-			let download = mega_bits(network.received() as f64 / millis);
-			let upload = mega_bits(network.transmitted() as f64 / millis);
-			self.network_interfaces.insert(
-				iface.clone(),
-				Network {
-					download,
-					upload,
-					total_errors_on_received: network.total_errors_on_received(),
-					total_errors_on_transmitted: network.total_errors_on_transmitted(),
-					total_packets_received: network.total_packets_received(),
-					total_packets_transmitted: network.total_packets_transmitted(),
-					refreshed: now,
-				},
-			);
-		}
-	}
-
-	pub fn componenet(&mut self, now: Duration) {
-		self.components.refresh(true);
-
-		for component in self.components.list() {
-			let label = component.label().to_string();
-
-			if !self.settings.components.is_empty() && self.settings.components.contains(&label) {
-				continue; // Skip if not in the user-defined components list
+				None => slot.status.lock().unwrap().prune = true,
 			}
-
-			self.component_list.insert(
-				component.label().to_string(),
-				Component {
-					label: component.label().to_string(),
-					temperature: component.temperature(),
-					critical: component.critical(),
-					max: component.max(),
-					refreshed: now,
-				},
-			);
 		}
-	}
 
-	pub fn ups(&mut self, now: Duration) {
-		for ups_name in &self.settings.upses {
-			let ups_data = UPS::get_ups_data(ups_name, now).unwrap_or(UPS::new());
-			self.upses.insert(ups_name.to_string(), ups_data);
-		}
+		self.publish(&[]);
 	}
 
-	pub fn docker(&mut self, now: Duration) {
-		if let Some(ref mut dm) = self.docker_monitor {
-			self.docker_containers = dm.snapshot(now);
-		}
+	/// Returns the snapshots published from now on, in the order they were published.
+	/// Only the last subscriber receives them.
+	pub fn subscribe(&self) -> Receiver<Update> {
+		let (sender, receiver) = mpsc::channel();
+		*self
+			.publishing
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = Some(sender);
+		receiver
 	}
 
-	pub fn processes(&mut self, now: Duration) {
-		let mut pids_to_refresh = Vec::new();
-
-		for key in &self.settings.processes {
-			if let Ok(pid_num) = key.parse::<u32>() {
-				let pid = Pid::from_u32(pid_num);
-				if self.system.process(pid).is_some() {
-					pids_to_refresh.push(pid);
-				}
-			} else {
-				for (pid, process) in self.system.processes() {
-					if process.name().to_string_lossy() == key.as_str() {
-						pids_to_refresh.push(*pid);
-					}
-				}
-			}
-		}
-
-		self.system.refresh_processes_specifics(
-			sysinfo::ProcessesToUpdate::Some(&pids_to_refresh),
-			true,
-			ProcessRefreshKind::nothing().with_cpu().with_memory(),
-		);
-
-		for pid in pids_to_refresh {
-			if let Some(sys_proc) = self.system.process(pid) {
-				let pid_str = pid.as_u32().to_string();
-
-				let key = if self.process_list.contains_key(&pid_str) {
-					pid_str
-				} else {
-					sys_proc.name().to_string_lossy().to_string()
-				};
-
-				self.process_list.entry(key.clone()).or_default().pid = pid.as_u32();
-
-				let proc_entry = self.process_list.get_mut(&key).unwrap();
-				proc_entry.name = sys_proc.name().to_string_lossy().to_string();
-				proc_entry.cpu = sys_proc.cpu_usage();
-				proc_entry.memory = sys_proc.memory();
-				proc_entry.virtual_memory = sys_proc.virtual_memory();
-				proc_entry.refreshed = now;
-			}
+	/// Assembles a snapshot of what every collector published last, replaces the
+	/// current one with it and sends it to the subscriber. `refreshed` are the
+	/// collectors whose refresh the snapshot is published for.
+	pub fn publish(&self, refreshed: &[&'static str]) {
+		let publishing = self
+			.publishing
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+
+		let snapshot = Arc::new(Snapshot::new(
+			&self.settings.load(),
+			&self.system_info,
+			&self.collectors,
+			self.started,
+		));
+		self.snapshot.store(snapshot.clone());
+
+		if let Some(sender) = publishing.as_ref() {
+			// The subscriber might be gone, which is fine
+			let _ = sender.send(Update {
+				snapshot,
+				refreshed: refreshed.to_vec(),
+			});
 		}
 	}
 }
 
 impl Default for Monitor {
 	fn default() -> Self {
-		Self::new()
+		Self::new(Settings::new())
 	}
 }
@@ -1,12 +1,25 @@
-use std::time::Duration;
+use crate::api::select;
+use crate::metrics::{MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::{mega_bits, serialize_timestamp};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use sysinfo::Networks;
 
+#[derive(Clone, Serialize)]
 pub struct Network {
 	pub download: f64,
 	pub upload: f64,
+	pub total_received: u64,
+	pub total_transmitted: u64,
 	pub total_errors_on_received: u64,
 	pub total_errors_on_transmitted: u64,
 	pub total_packets_received: u64,
 	pub total_packets_transmitted: u64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -15,6 +28,8 @@ impl Network {
 		Network {
 			download: 0.0,
 			upload: 0.0,
+			total_received: 0,
+			total_transmitted: 0,
 			total_errors_on_received: 0,
 			total_errors_on_transmitted: 0,
 			total_packets_received: 0,
@@ -29,3 +44,224 @@ impl Default for Network {
 		Self::new()
 	}
 }
+
+/// Collects the throughput and the traffic, packet and error counters of the network
+/// interfaces.
+pub struct NetworkCollector {
+	networks: Option<Networks>,
+	network_interfaces: HashMap<String, Network>,
+	refreshed: Instant,
+}
+
+impl NetworkCollector {
+	pub fn new() -> Self {
+		NetworkCollector {
+			networks: None,
+			network_interfaces: HashMap::new(),
+			refreshed: Instant::now(),
+		}
+	}
+}
+
+impl Default for NetworkCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for NetworkCollector {
+	fn name(&self) -> &'static str {
+		"network"
+	}
+
+	fn key(&self) -> &'static str {
+		"network_interfaces"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["interface"]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let networks = self
+			.networks
+			.get_or_insert_with(Networks::new_with_refreshed_list);
+
+		let monitoring_time: u64 = self.refreshed.elapsed().as_millis() as u64;
+		networks.refresh(true);
+
+		let mut millis: f64 = monitoring_time as f64 / 1000.0;
+		if millis == 0.0 {
+			millis = 1.0;
+		}
+
+		for (iface, network) in networks.list() {
+			if !settings.interfaces.is_empty() && !settings.interfaces.contains(iface) {
+				continue; // Skip if not in the user-defined interface list
+			}
+
+			let download = mega_bits(network.received() as f64 / millis);
+			let upload = mega_bits(network.transmitted() as f64 / millis);
+			self.network_interfaces.insert(
+				iface.clone(),
+				Network {
+					download,
+					upload,
+					total_received: network.total_received(),
+					total_transmitted: network.total_transmitted(),
+					total_errors_on_received: network.total_errors_on_received(),
+					total_errors_on_transmitted: network.total_errors_on_transmitted(),
+					total_packets_received: network.total_packets_received(),
+					total_packets_transmitted: network.total_packets_transmitted(),
+					refreshed: now,
+				},
+			);
+		}
+
+		self.refreshed = Instant::now();
+
+		Ok(())
+	}
+
+	fn prune(&mut self, settings: &Settings) {
+		self
+			.network_interfaces
+			.retain(|iface, _| settings.interfaces.is_empty() || settings.interfaces.contains(iface));
+	}
+
+	fn metrics(&self, settings: &Settings, system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.network_interfaces.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"network_download_speed",
+			"Download speed in bytes/sec",
+			MetricType::Gauge,
+			Some("bytes_per_second"),
+		);
+		for (iface, network) in &self.network_interfaces {
+			metrics.sample(network.download, &[("interface", iface)], network.refreshed);
+		}
+
+		metrics.header(
+			"network_upload_speed",
+			"Upload speed in bytes/sec",
+			MetricType::Gauge,
+			Some("bytes_per_second"),
+		);
+		for (iface, network) in &self.network_interfaces {
+			metrics.sample(network.upload, &[("interface", iface)], network.refreshed);
+		}
+
+		if settings.network_details || settings.all_metrics {
+			metrics.header(
+				"network_received",
+				"Total number of received bytes",
+				MetricType::Counter,
+				Some("bytes"),
+			);
+			for (iface, network) in &self.network_interfaces {
+				metrics.counter_sample(
+					network.total_received as f64,
+					&[("interface", iface)],
+					network.refreshed,
+					system_info.boot_time,
+				);
+			}
+
+			metrics.header(
+				"network_transmitted",
+				"Total number of transmitted bytes",
+				MetricType::Counter,
+				Some("bytes"),
+			);
+			for (iface, network) in &self.network_interfaces {
+				metrics.counter_sample(
+					network.total_transmitted as f64,
+					&[("interface", iface)],
+					network.refreshed,
+					system_info.boot_time,
+				);
+			}
+
+			metrics.header(
+				"network_packets_received",
+				"Total number of incoming packets",
+				MetricType::Counter,
+				None,
+			);
+			for (iface, network) in &self.network_interfaces {
+				metrics.counter_sample(
+					network.total_packets_received as f64,
+					&[("interface", iface)],
+					network.refreshed,
+					system_info.boot_time,
+				);
+			}
+
+			metrics.header(
+				"network_packets_transmitted",
+				"Total number of outcoming packets",
+				MetricType::Counter,
+				None,
+			);
+			for (iface, network) in &self.network_interfaces {
+				metrics.counter_sample(
+					network.total_packets_transmitted as f64,
+					&[("interface", iface)],
+					network.refreshed,
+					system_info.boot_time,
+				);
+			}
+
+			metrics.header(
+				"network_errors_received",
+				"Total number of incoming errors",
+				MetricType::Counter,
+				None,
+			);
+			for (iface, network) in &self.network_interfaces {
+				metrics.counter_sample(
+					network.total_errors_on_received as f64,
+					&[("interface", iface)],
+					network.refreshed,
+					system_info.boot_time,
+				);
+			}
+
+			metrics.header(
+				"network_errors_transmitted",
+				"Total number of outcoming errors",
+				MetricType::Counter,
+				None,
+			);
+			for (iface, network) in &self.network_interfaces {
+				metrics.counter_sample(
+					network.total_errors_on_transmitted as f64,
+					&[("interface", iface)],
+					network.refreshed,
+					system_info.boot_time,
+				);
+			}
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let network_interfaces = self.network_interfaces.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(
+				&network_interfaces,
+				query,
+				filters,
+				(subsystem, scopes),
+				|_, name, _| name.to_string(),
+			)
+		})
+	}
+}
@@ -1,11 +1,22 @@
+use crate::api::select;
+use crate::metrics::{round2, MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::serialize_timestamp;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::time::Duration;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
 
+#[derive(Clone, Serialize)]
 pub struct Process {
 	pub pid: u32,
 	pub name: String,
 	pub cpu: f32,
 	pub memory: u64,
 	pub virtual_memory: u64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -27,3 +38,167 @@ impl Default for Process {
 		Self::new()
 	}
 }
+
+/// Collects the CPU and memory usage of the processes selected by PID or name.
+pub struct ProcessCollector {
+	system: Option<System>,
+	process_list: HashMap<String, Process>,
+}
+
+impl ProcessCollector {
+	pub fn new() -> Self {
+		ProcessCollector {
+			system: None,
+			process_list: HashMap::new(),
+		}
+	}
+}
+
+impl Default for ProcessCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for ProcessCollector {
+	fn name(&self) -> &'static str {
+		"processes"
+	}
+
+	fn key(&self) -> &'static str {
+		"process_list"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name", "pid"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["pid", "name"]
+	}
+
+	fn entry_fields(&self) -> &'static [Option<&'static str>] {
+		&[Some("name"), Some("pid")]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let system = self.system.get_or_insert_with(System::new_all);
+		let mut pids_to_refresh = Vec::new();
+
+		for key in &settings.processes {
+			if let Ok(pid_num) = key.parse::<u32>() {
+				let pid = Pid::from_u32(pid_num);
+				if system.process(pid).is_some() {
+					pids_to_refresh.push(pid);
+				}
+			} else {
+				for (pid, process) in system.processes() {
+					if process.name().to_string_lossy() == key.as_str() {
+						pids_to_refresh.push(*pid);
+					}
+				}
+			}
+		}
+
+		system.refresh_processes_specifics(
+			ProcessesToUpdate::Some(&pids_to_refresh),
+			true,
+			ProcessRefreshKind::nothing().with_cpu().with_memory(),
+		);
+
+		for pid in pids_to_refresh {
+			if let Some(sys_proc) = system.process(pid) {
+				let pid_str = pid.as_u32().to_string();
+
+				let key = if self.process_list.contains_key(&pid_str) {
+					pid_str
+				} else {
+					sys_proc.name().to_string_lossy().to_string()
+				};
+
+				self.process_list.entry(key.clone()).or_default().pid = pid.as_u32();
+
+				let proc_entry = self.process_list.get_mut(&key).unwrap();
+				proc_entry.name = sys_proc.name().to_string_lossy().to_string();
+				proc_entry.cpu = sys_proc.cpu_usage();
+				proc_entry.memory = sys_proc.memory();
+				proc_entry.virtual_memory = sys_proc.virtual_memory();
+				proc_entry.refreshed = now;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn prune(&mut self, settings: &Settings) {
+		self.process_list.retain(|_, process| {
+			settings.processes.contains(&process.pid.to_string())
+				|| settings.processes.contains(&process.name)
+		});
+	}
+
+	fn metrics(&self, _settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.process_list.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"process_cpu_usage",
+			"CPU usage of the monitored process",
+			MetricType::Gauge,
+			None,
+		);
+		for process in self.process_list.values() {
+			metrics.sample(
+				round2(process.cpu),
+				&[("pid", &process.pid.to_string()), ("name", &process.name)],
+				process.refreshed,
+			);
+		}
+
+		metrics.header(
+			"process_memory_usage",
+			"Memory usage of the monitored process",
+			MetricType::Gauge,
+			Some("bytes"),
+		);
+		for process in self.process_list.values() {
+			metrics.sample(
+				process.memory as f64,
+				&[("pid", &process.pid.to_string()), ("name", &process.name)],
+				process.refreshed,
+			);
+		}
+
+		metrics.header(
+			"process_virtual_memory_usage",
+			"Virtual memory usage of the monitored process",
+			MetricType::Gauge,
+			Some("bytes"),
+		);
+		for process in self.process_list.values() {
+			metrics.sample(
+				process.virtual_memory as f64,
+				&[("pid", &process.pid.to_string()), ("name", &process.name)],
+				process.refreshed,
+			);
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let process_list = self.process_list.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(
+				&process_list,
+				query,
+				filters,
+				(subsystem, scopes),
+				|key, _, process| match key {
+					"pid" => process.pid.to_string(),
+					_ => process.name.clone(),
+				},
+			)
+		})
+	}
+}
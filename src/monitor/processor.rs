@@ -1,5 +1,15 @@
+use crate::api::check_filters;
+use crate::metrics::{round2, MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::serialize_timestamp;
+use serde::Serialize;
+use serde_json::json;
 use std::time::Duration;
+use sysinfo::{CpuRefreshKind, RefreshKind, System};
 
+#[derive(Clone, Serialize)]
 pub struct Thread {
 	pub name: String,
 	pub brand: String,
@@ -7,6 +17,7 @@ pub struct Thread {
 	pub frequency: u64,
 }
 
+#[derive(Clone, Serialize)]
 pub struct Processor {
 	pub min1: f64,
 	pub min5: f64,
@@ -15,6 +26,7 @@ pub struct Processor {
 	pub thread_count: u64,
 	pub arch: String,
 	pub threads: Vec<Thread>,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -38,3 +50,165 @@ impl Default for Processor {
 		Self::new()
 	}
 }
+
+/// Collects the load average, overall and per thread CPU usage and frequencies.
+pub struct CpuCollector {
+	system: Option<System>,
+	processor: Processor,
+}
+
+impl CpuCollector {
+	pub fn new() -> Self {
+		CpuCollector {
+			system: None,
+			processor: Processor::new(),
+		}
+	}
+}
+
+impl Default for CpuCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for CpuCollector {
+	fn name(&self) -> &'static str {
+		"cpu"
+	}
+
+	fn key(&self) -> &'static str {
+		"processor"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name"]
+	}
+
+	fn refresh(&mut self, _settings: &Settings, now: Duration) -> Result<(), String> {
+		let system = self.system.get_or_insert_with(|| {
+			System::new_with_specifics(RefreshKind::nothing().with_cpu(CpuRefreshKind::everything()))
+		});
+
+		let load_average = System::load_average();
+		self.processor.min1 = load_average.one;
+		self.processor.min5 = load_average.five;
+		self.processor.min15 = load_average.fifteen;
+
+		system.refresh_cpu_specifics(CpuRefreshKind::nothing().with_cpu_usage().with_frequency());
+
+		self.processor.arch = System::cpu_arch();
+		self.processor.thread_count = system.cpus().len() as u64;
+		self.processor.percent = system.global_cpu_usage();
+
+		self.processor.threads = system
+			.cpus()
+			.iter()
+			.map(|cpu| Thread {
+				name: cpu.name().into(),
+				brand: cpu.brand().into(),
+				cpu_usage: cpu.cpu_usage(),
+				frequency: cpu.frequency(),
+			})
+			.collect();
+
+		self.processor.refreshed = now;
+
+		Ok(())
+	}
+
+	fn metrics(&self, settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		let processor = &self.processor;
+
+		metrics.info(
+			"cpu_info",
+			"Static CPU information",
+			&[
+				("arch", &processor.arch),
+				("threads", &processor.thread_count.to_string()),
+			],
+		);
+
+		if settings.cpu_details || settings.all_metrics {
+			metrics.gauge(
+				"cpu_load_1min",
+				"CPU load recorded in last minute",
+				processor.min1,
+				None,
+				&[],
+				processor.refreshed,
+			);
+			metrics.gauge(
+				"cpu_load_5min",
+				"CPU load recorded in last 5 minutes",
+				processor.min5,
+				None,
+				&[],
+				processor.refreshed,
+			);
+			metrics.gauge(
+				"cpu_load_15min",
+				"CPU load recorded in last 15 minutes",
+				processor.min15,
+				None,
+				&[],
+				processor.refreshed,
+			);
+
+			metrics.header(
+				"cpu_thread_usage",
+				"CPU load per thread in percent",
+				MetricType::Gauge,
+				Some("percent"),
+			);
+			for thead in &processor.threads {
+				metrics.sample(
+					round2(thead.cpu_usage),
+					&[("name", &thead.name), ("brand", &thead.brand)],
+					processor.refreshed,
+				);
+			}
+
+			metrics.header(
+				"cpu_thread_frequency",
+				"CPU frequency per thread in hertz",
+				MetricType::Gauge,
+				Some("hertz"),
+			);
+			for thead in &processor.threads {
+				metrics.sample(
+					(thead.frequency * 1_000_000) as f64,
+					&[("name", &thead.name), ("brand", &thead.brand)],
+					processor.refreshed,
+				);
+			}
+		}
+
+		metrics.gauge(
+			"cpu_load",
+			"CPU load in percent",
+			round2(processor.percent),
+			Some("percent"),
+			&[],
+			processor.refreshed,
+		);
+	}
+
+	fn json(&self) -> JsonView {
+		let processor = self.processor.clone();
+		let filters = self.filters();
+		Box::new(move |query, _scopes| {
+			check_filters(query, filters)?;
+
+			let mut rendered = json!(processor);
+			if let Some(thread) = query.get("name") {
+				rendered["threads"] = json!(processor
+					.threads
+					.iter()
+					.filter(|t| &t.name == thread)
+					.collect::<Vec<_>>());
+			}
+			Ok(rendered)
+		})
+	}
+}
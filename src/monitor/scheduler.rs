@@ -0,0 +1,194 @@
+use chrono::Utc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::monitor::collector::{self, CollectorSlot};
+use crate::monitor::Monitor;
+
+/// Longest time the scheduler sleeps, so reloaded intervals and collectors that
+/// become enabled are picked up quickly.
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// Time a refresh gets after its timeout to return on its own, since the subprocesses
+/// it runs are killed at the timeout and it then reports that itself.
+const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Starts the thread that refreshes every active collector on its own interval. Each
+/// refresh runs on a thread of its own, so a slow `upsc` or `ipmitool` call does not
+/// delay the other collectors, and publishes a new snapshot when it is done. A refresh
+/// that takes longer than its timeout marks the collector stale until it finishes; the
+/// subprocesses it started are killed by then.
+pub fn start(monitor: Arc<Monitor>) {
+	let slots: Vec<Arc<CollectorSlot>> = monitor.collectors.clone();
+
+	thread::spawn(move || loop {
+		let settings = monitor.settings.load_full();
+		let mut wakeup = Instant::now() + MAX_SLEEP;
+		let mut timed_out = false;
+		let mut pruned = false;
+
+		for slot in &slots {
+			let schedule = settings.schedule(slot.name);
+			let mut status = slot.status.lock().unwrap();
+			let now = Instant::now();
+
+			if let Some(started) = status.running {
+				let deadline = started + schedule.timeout + GRACE_PERIOD;
+				if now < deadline {
+					wakeup = wakeup.min(deadline);
+					continue;
+				}
+
+				// Counted once, whatever the refresh returns when it finishes
+				if !status.timed_out {
+					let error = format!("timed out after {}s", schedule.timeout.as_secs());
+					if status.error.as_ref() != Some(&error) {
+						eprintln!(
+							"Refreshing {} {}, keeping its last values",
+							slot.name, error
+						);
+					}
+					status.error = Some(error);
+					status.errors += 1;
+					status.timed_out = true;
+					timed_out = true;
+				}
+				continue;
+			}
+
+			if now < status.next_run {
+				wakeup = wakeup.min(status.next_run);
+				continue;
+			}
+			drop(status);
+
+			// The collector is idle, so this does not wait for a refresh
+			let mut collector = slot.lock();
+			if !collector::is_active(collector.as_ref(), &settings) {
+				// It was disabled by a reload while it was refreshing, e.g. so docker
+				// stats is stopped
+				if std::mem::take(&mut slot.status.lock().unwrap().prune) {
+					collector.prune(&settings);
+					slot.publish(collector.as_ref(), &settings, &monitor.system_info);
+					pruned = true;
+				}
+				continue;
+			}
+			drop(collector);
+
+			let mut status = slot.status.lock().unwrap();
+			status.running = Some(now);
+			status.next_run = now + schedule.interval;
+			wakeup = wakeup.min(status.next_run);
+			drop(status);
+
+			let slot = slot.clone();
+			let settings = settings.clone();
+			let monitor = monitor.clone();
+			thread::spawn(move || {
+				let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+				slot.refresh(&settings, &monitor.system_info, now);
+				monitor.publish(&[slot.name]);
+			});
+		}
+
+		// The stale metric of a collector that timed out and collectors that were
+		// pruned have to be published as well
+		if timed_out || pruned {
+			monitor.publish(&[]);
+		}
+
+		thread::sleep(wakeup.saturating_duration_since(Instant::now()));
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::metrics::MetricsBuilder;
+	use crate::monitor::collector::{Collector, CollectorStatus, JsonView};
+	use crate::monitor::settings::Settings;
+	use crate::monitor::system_info::SystemInfo;
+	use serde_json::Value;
+
+	/// Takes `delay` to refresh and then returns `result`.
+	struct SlowCollector {
+		delay: Duration,
+		result: Result<(), String>,
+	}
+
+	impl Collector for SlowCollector {
+		fn name(&self) -> &'static str {
+			"slow"
+		}
+
+		fn key(&self) -> &'static str {
+			"slow"
+		}
+
+		fn refresh(&mut self, _settings: &Settings, _now: Duration) -> Result<(), String> {
+			thread::sleep(self.delay);
+			self.result.clone()
+		}
+
+		fn metrics(
+			&self,
+			_settings: &Settings,
+			_system_info: &SystemInfo,
+			_metrics: &mut MetricsBuilder,
+		) {
+		}
+
+		fn json(&self) -> JsonView {
+			Box::new(|_, _| Ok(Value::Null))
+		}
+	}
+
+	/// Starts the scheduler with a collector that times out after 1s, is given up on
+	/// after 2s and returns `result` after 4s. Returns its status after 3s and after 5s.
+	fn time_out(result: Result<(), String>) -> (CollectorStatus, CollectorStatus) {
+		let mut settings = Settings::new();
+		settings.cache = 60;
+		settings.collector_timeout = 1;
+		let collector = SlowCollector {
+			delay: Duration::from_secs(4),
+			result,
+		};
+		let monitor = Arc::new(Monitor::with_collectors(
+			settings,
+			vec![Box::new(collector)],
+		));
+		let slot = monitor.collectors[0].clone();
+
+		start(monitor);
+		thread::sleep(Duration::from_secs(3));
+		let timed_out = slot.status.lock().unwrap().clone();
+		thread::sleep(Duration::from_secs(2));
+		let finished = slot.status.lock().unwrap().clone();
+		(timed_out, finished)
+	}
+
+	#[test]
+	fn counts_a_timeout_that_later_succeeds() {
+		let (timed_out, finished) = time_out(Ok(()));
+
+		assert!(timed_out.running.is_some());
+		assert_eq!(timed_out.error.as_deref(), Some("timed out after 1s"));
+		assert_eq!(timed_out.errors, 1);
+
+		assert!(finished.running.is_none());
+		assert!(finished.error.is_none());
+		assert!(finished.last_success.is_some());
+		assert_eq!(finished.errors, 1);
+	}
+
+	#[test]
+	fn counts_a_timeout_that_later_fails_once() {
+		let (timed_out, finished) = time_out(Err(String::from("upsc failed")));
+
+		assert_eq!(timed_out.errors, 1);
+		assert_eq!(finished.error.as_deref(), Some("upsc failed"));
+		assert_eq!(finished.errors, 1);
+	}
+}
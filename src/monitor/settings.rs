@@ -1,10 +1,25 @@
+use crate::config::{CollectorConfig, Config};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Clone)]
 pub struct EnergySettings {
 	pub enabled: bool,
 	pub interval: Option<u64>,
 }
 
+/// How often a collector refreshes and how long a refresh may take.
+#[derive(Clone, Copy)]
+pub struct Schedule {
+	pub interval: Duration,
+	pub timeout: Duration,
+}
+
+#[derive(Clone)]
 pub struct Settings {
 	pub cache: u64,
+	pub collector_timeout: u64,
+	pub collectors: HashMap<String, CollectorConfig>,
 	pub energy: EnergySettings,
 	pub upses: Vec<String>,
 	pub interfaces: Vec<String>,
@@ -12,6 +27,10 @@ pub struct Settings {
 	pub components: Vec<String>,
 	pub processes: Vec<String>,
 	pub containers: Vec<String>,
+	pub batteries: Vec<String>,
+	pub docker: bool,
+	pub disabled: Vec<String>,
+	pub timestamps: bool,
 	pub all_metrics: bool,
 	pub cpu_details: bool,
 	pub memory_details: bool,
@@ -24,6 +43,8 @@ impl Settings {
 	pub fn new() -> Self {
 		Settings {
 			cache: 3,
+			collector_timeout: 10,
+			collectors: HashMap::new(),
 			energy: EnergySettings {
 				enabled: false,
 				interval: None,
@@ -34,6 +55,10 @@ impl Settings {
 			components: Vec::new(),
 			processes: Vec::new(),
 			containers: Vec::new(),
+			batteries: Vec::new(),
+			docker: false,
+			disabled: Vec::new(),
+			timestamps: true,
 			all_metrics: false,
 			cpu_details: false,
 			memory_details: false,
@@ -42,6 +67,40 @@ impl Settings {
 			network_details: false,
 		}
 	}
+
+	pub fn apply_config(&mut self, config: &Config) {
+		self.cache = config.cache;
+		self.collector_timeout = config.collector_timeout;
+		self.collectors = config.collectors.clone();
+		self.interfaces = config.interfaces.clone();
+		self.mounts = config.mounts.clone();
+		self.components = config.components.clone();
+		self.processes = config.processes.clone();
+		self.containers = config.containers.clone();
+		self.batteries = config.batteries.clone();
+		self.disabled = config.disabled_subsystems.clone();
+		self.timestamps = config.timestamps;
+		self.all_metrics = config.all_metrics;
+		self.cpu_details = config.cpu_details;
+		self.memory_details = config.memory_details;
+		self.swap_details = config.swap_details;
+		self.storage_details = config.storage_details;
+		self.network_details = config.network_details;
+	}
+
+	/// Interval and timeout of a collector, falling back to `cache` and `collector_timeout`.
+	pub fn schedule(&self, collector: &str) -> Schedule {
+		let config = self.collectors.get(collector);
+		let interval = config.and_then(|c| c.interval).unwrap_or(self.cache);
+		let timeout = config
+			.and_then(|c| c.timeout)
+			.unwrap_or(self.collector_timeout);
+
+		Schedule {
+			interval: Duration::from_secs(interval),
+			timeout: Duration::from_secs(timeout),
+		}
+	}
 }
 
 impl Default for Settings {
@@ -0,0 +1,207 @@
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::api::ApiError;
+use crate::auth::Scopes;
+use crate::exporter;
+use crate::metrics::{self, Format, MetricFamily, MetricType, MetricsBuilder};
+use crate::monitor::collector::{CollectorSlot, CollectorStatus, Published};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+
+/// Everything the collectors published, assembled after every refresh. A snapshot is
+/// never changed, requests keep using the one they loaded while the next is built.
+pub struct Snapshot {
+	pub system_info: SystemInfo,
+	/// Metric families of the exporter and of every active collector, in the order they
+	/// are rendered
+	pub families: Vec<MetricFamily>,
+	/// Whether samples are exposed with their timestamp
+	pub timestamps: bool,
+	/// `families` in OpenMetrics and Prometheus text format, without the `# EOF` line
+	openmetrics: String,
+	prometheus: String,
+	subsystems: Vec<(&'static str, Arc<Published>)>,
+	collectors: Vec<(&'static str, Value)>,
+}
+
+impl Snapshot {
+	/// Assembles what every collector published last and renders the exposition once,
+	/// so scrapes of tokens that may see everything only copy it. `started` is when
+	/// Rabbit Monitor started, in seconds since the Unix epoch.
+	pub fn new(
+		settings: &Settings,
+		system_info: &SystemInfo,
+		slots: &[Arc<CollectorSlot>],
+		started: u64,
+	) -> Self {
+		let mut metrics = MetricsBuilder::new();
+		metrics.info(
+			"version_info",
+			"Rabbit Monitor version",
+			&[("version", "v10.2.1")],
+		);
+		metrics.subsystem("system");
+		metrics.info(
+			"system_info",
+			"System information",
+			&[
+				("name", &system_info.name),
+				("kernel_version", &system_info.kernel_version),
+				("os_version", &system_info.os_version),
+				("long_os_version", &system_info.long_os_version),
+				("distribution_id", &system_info.distribution_id),
+				("host_name", &system_info.host_name),
+				("boot_time", &system_info.boot_time.to_string()),
+			],
+		);
+		let mut families = metrics.build();
+
+		let mut subsystems = Vec::new();
+		let mut collectors = Vec::new();
+		let mut states: Vec<(&str, CollectorStatus)> = Vec::new();
+
+		for slot in slots {
+			let published = slot.published.load_full();
+
+			if published.active {
+				families.extend(published.families.iter().cloned());
+
+				let schedule = settings.schedule(slot.name);
+				let status = slot.status.lock().unwrap();
+				states.push((slot.name, status.clone()));
+				collectors.push((
+					slot.name,
+					json!({
+						"interval": schedule.interval.as_secs(),
+						"timeout": schedule.timeout.as_secs(),
+						"duration": status.last_duration.map(|d| d.as_secs_f64()),
+						"last_success": status.last_success.map(|t| t.as_secs_f64()),
+						"stale": status.is_stale(),
+						"error": status.error,
+						"errors": status.errors,
+					}),
+				));
+			}
+
+			subsystems.push((slot.name, published));
+		}
+
+		let now = Duration::from_millis(Utc::now().timestamp_millis() as u64);
+		let mut metrics = MetricsBuilder::new();
+		metrics.subsystem("system");
+		metrics.header(
+			"exporter_collector_stale",
+			"Whether the last refresh of a collector failed or timed out",
+			MetricType::Gauge,
+			None,
+		);
+		for (name, status) in &states {
+			let stale = if status.is_stale() { 1.0 } else { 0.0 };
+			metrics.sample(stale, &[("collector", name)], now);
+		}
+		metrics.header(
+			"exporter_collector_duration",
+			"How long the last refresh of a collector took",
+			MetricType::Gauge,
+			Some("seconds"),
+		);
+		for (name, status) in &states {
+			if let Some(duration) = status.last_duration {
+				metrics.sample(duration.as_secs_f64(), &[("collector", name)], now);
+			}
+		}
+		metrics.header(
+			"exporter_collector_last_success_timestamp",
+			"When a collector last refreshed successfully, in seconds since the Unix epoch",
+			MetricType::Gauge,
+			Some("seconds"),
+		);
+		for (name, status) in &states {
+			if let Some(last_success) = status.last_success {
+				metrics.sample(last_success.as_secs_f64(), &[("collector", name)], now);
+			}
+		}
+		metrics.header(
+			"exporter_collector_errors",
+			"Number of refreshes of a collector that failed or timed out",
+			MetricType::Counter,
+			None,
+		);
+		for (name, status) in &states {
+			metrics.counter_sample(status.errors as f64, &[("collector", name)], now, started);
+		}
+		exporter::metrics(&mut metrics, now, started);
+		families.extend(metrics.build());
+
+		Snapshot {
+			system_info: system_info.clone(),
+			openmetrics: metrics::encode_families(&families, Format::OpenMetrics, settings.timestamps),
+			prometheus: metrics::encode_families(&families, Format::Prometheus, settings.timestamps),
+			families,
+			timestamps: settings.timestamps,
+			subsystems,
+			collectors,
+		}
+	}
+
+	/// The pre-rendered exposition of every family, without the `# EOF` line.
+	pub fn exposition(&self, format: Format) -> &str {
+		match format {
+			Format::OpenMetrics => &self.openmetrics,
+			Format::Prometheus => &self.prometheus,
+		}
+	}
+
+	/// The families a refresh of `refreshed` changed: those of these collectors and
+	/// those of Rabbit Monitor itself, which are read again for every snapshot. The
+	/// families of other collectors still hold samples that were already seen.
+	pub fn refreshed_families(&self, refreshed: &[&str]) -> Vec<MetricFamily> {
+		self
+			.families
+			.iter()
+			.filter(|family| match family.subsystem.as_deref() {
+				Some(subsystem) => {
+					refreshed.contains(&subsystem)
+						|| !self.subsystems.iter().any(|(name, _)| *name == subsystem)
+				}
+				None => true,
+			})
+			.cloned()
+			.collect()
+	}
+
+	/// Renders a subsystem as JSON, or null when its collector is not active.
+	pub fn render(
+		&self,
+		name: &str,
+		query: &HashMap<String, String>,
+		scopes: &Scopes,
+	) -> Result<Value, ApiError> {
+		match self
+			.subsystems
+			.iter()
+			.find(|(subsystem, _)| *subsystem == name)
+		{
+			Some((_, published)) => (published.json)(query, scopes),
+			None => Err(ApiError::UnknownSubsystem(name.to_string())),
+		}
+	}
+
+	/// Refresh interval, timeout and state of every active collector, e.g. for the
+	/// `collectors` key of the snapshot.
+	pub fn collector_status(&self, scopes: &Scopes) -> Value {
+		let mut statuses = Map::new();
+
+		for (name, status) in &self.collectors {
+			if scopes.allows(name) {
+				statuses.insert(name.to_string(), status.clone());
+			}
+		}
+
+		Value::Object(statuses)
+	}
+}
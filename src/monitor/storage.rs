@@ -1,5 +1,15 @@
-use std::time::Duration;
+use crate::api::select;
+use crate::metrics::{MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::serialize_timestamp;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use sysinfo::{DiskRefreshKind, Disks};
 
+#[derive(Clone, Serialize)]
 pub struct Storage {
 	pub name: String,
 	pub mount_point: String,
@@ -11,6 +21,7 @@ pub struct Storage {
 	pub total_written_bytes: u64,
 	pub read_speed: f64,
 	pub write_speed: f64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -37,3 +48,220 @@ impl Default for Storage {
 		Self::new()
 	}
 }
+
+/// Collects the usage and I/O speed of the mounted disks.
+pub struct StorageCollector {
+	disks: Option<Disks>,
+	storage_devices: HashMap<String, Storage>,
+	refreshed: Instant,
+}
+
+impl StorageCollector {
+	pub fn new() -> Self {
+		StorageCollector {
+			disks: None,
+			storage_devices: HashMap::new(),
+			refreshed: Instant::now(),
+		}
+	}
+}
+
+impl Default for StorageCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for StorageCollector {
+	fn name(&self) -> &'static str {
+		"storage"
+	}
+
+	fn key(&self) -> &'static str {
+		"storage_devices"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name", "mount"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["device", "mount"]
+	}
+
+	fn entry_fields(&self) -> &'static [Option<&'static str>] {
+		&[None, Some("mount_point")]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let disks = self.disks.get_or_insert_with(|| {
+			Disks::new_with_refreshed_list_specifics(
+				DiskRefreshKind::nothing().with_storage().with_io_usage(),
+			)
+		});
+
+		let monitoring_time: u64 = self.refreshed.elapsed().as_millis() as u64;
+		disks.refresh_specifics(
+			true,
+			DiskRefreshKind::nothing().with_storage().with_io_usage(),
+		);
+
+		let mut millis: f64 = monitoring_time as f64 / 1000.0;
+		if millis == 0.0 {
+			millis = 1.0;
+		}
+
+		for disk in disks.list() {
+			let mount = disk.mount_point().to_string_lossy().to_string();
+
+			if !settings.mounts.is_empty() && !settings.mounts.contains(&mount) {
+				continue; // Skip if not in the user-defined mount list
+			}
+
+			let name = disk.name().to_string_lossy().to_string();
+			let total = disk.total_space();
+			let free = disk.available_space();
+			let used = total - free;
+			let usage = disk.usage();
+
+			let mut percent: f64 = (used as f64 / total as f64) * 100.0;
+			percent = if !f64::is_nan(percent) { percent } else { 0.0 };
+
+			self.storage_devices.insert(
+				name.clone(),
+				Storage {
+					name,
+					mount_point: mount,
+					total,
+					used,
+					free,
+					percent,
+					read_speed: usage.read_bytes as f64 / millis,
+					write_speed: usage.written_bytes as f64 / millis,
+					total_read_bytes: usage.total_read_bytes,
+					total_written_bytes: usage.total_written_bytes,
+					refreshed: now,
+				},
+			);
+		}
+
+		self.refreshed = Instant::now();
+
+		Ok(())
+	}
+
+	fn prune(&mut self, settings: &Settings) {
+		self.storage_devices.retain(|_, storage| {
+			settings.mounts.is_empty() || settings.mounts.contains(&storage.mount_point)
+		});
+	}
+
+	fn metrics(&self, settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.storage_devices.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"storage",
+			"Used storage in percent",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (device, storage) in &self.storage_devices {
+			metrics.sample(
+				storage.percent,
+				&[("device", device), ("mount", &storage.mount_point)],
+				storage.refreshed,
+			);
+		}
+
+		metrics.header(
+			"storage_read_speed",
+			"Disk read speed in bytes/sec",
+			MetricType::Gauge,
+			Some("bytes_per_second"),
+		);
+		for (device, storage) in &self.storage_devices {
+			metrics.sample(
+				storage.read_speed,
+				&[("device", device), ("mount", &storage.mount_point)],
+				storage.refreshed,
+			);
+		}
+
+		metrics.header(
+			"storage_write_speed",
+			"Disk write speed in bytes/sec",
+			MetricType::Gauge,
+			Some("bytes_per_second"),
+		);
+		for (device, storage) in &self.storage_devices {
+			metrics.sample(
+				storage.write_speed,
+				&[("device", device), ("mount", &storage.mount_point)],
+				storage.refreshed,
+			);
+		}
+
+		if settings.storage_details || settings.all_metrics {
+			metrics.header(
+				"storage_used",
+				"Used storage in bytes",
+				MetricType::Gauge,
+				Some("bytes"),
+			);
+			for (device, storage) in &self.storage_devices {
+				metrics.sample(
+					storage.used as f64,
+					&[("device", device), ("mount", &storage.mount_point)],
+					storage.refreshed,
+				);
+			}
+
+			metrics.header(
+				"storage_free",
+				"Free storage in bytes",
+				MetricType::Gauge,
+				Some("bytes"),
+			);
+			for (device, storage) in &self.storage_devices {
+				metrics.sample(
+					storage.free as f64,
+					&[("device", device), ("mount", &storage.mount_point)],
+					storage.refreshed,
+				);
+			}
+
+			metrics.header(
+				"storage_total",
+				"Total storage in bytes",
+				MetricType::Gauge,
+				Some("bytes"),
+			);
+			for (device, storage) in &self.storage_devices {
+				metrics.sample(
+					storage.total as f64,
+					&[("device", device), ("mount", &storage.mount_point)],
+					storage.refreshed,
+				);
+			}
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let storage_devices = self.storage_devices.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(
+				&storage_devices,
+				query,
+				filters,
+				(subsystem, scopes),
+				|key, name, storage| match key {
+					"mount" => storage.mount_point.clone(),
+					_ => name.to_string(),
+				},
+			)
+		})
+	}
+}
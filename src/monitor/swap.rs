@@ -1,10 +1,21 @@
+use crate::api::check_filters;
+use crate::metrics::{round2, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::serialize_timestamp;
+use serde::Serialize;
+use serde_json::json;
 use std::time::Duration;
+use sysinfo::{MemoryRefreshKind, System};
 
+#[derive(Serialize)]
 pub struct Swap {
 	pub total: u64,
 	pub used: u64,
 	pub free: u64,
 	pub percent: f64,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -25,3 +36,104 @@ impl Default for Swap {
 		Self::new()
 	}
 }
+
+/// Collects the usage of the swap space.
+pub struct SwapCollector {
+	system: System,
+	swap: Swap,
+}
+
+impl SwapCollector {
+	pub fn new() -> Self {
+		SwapCollector {
+			system: System::new(),
+			swap: Swap::new(),
+		}
+	}
+}
+
+impl Default for SwapCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for SwapCollector {
+	fn name(&self) -> &'static str {
+		"swap"
+	}
+
+	fn key(&self) -> &'static str {
+		"swap"
+	}
+
+	fn refresh(&mut self, _settings: &Settings, now: Duration) -> Result<(), String> {
+		self
+			.system
+			.refresh_memory_specifics(MemoryRefreshKind::nothing().with_swap());
+		self.swap.total = self.system.total_swap();
+		self.swap.used = self.system.used_swap();
+		self.swap.free = self.system.free_swap();
+
+		let swap_percent: f64 =
+			(self.system.used_swap() as f64 / self.system.total_swap() as f64) * 100.0;
+		self.swap.percent = if !f64::is_nan(swap_percent) {
+			swap_percent
+		} else {
+			0.0
+		};
+
+		self.swap.refreshed = now;
+
+		Ok(())
+	}
+
+	fn metrics(&self, settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		let swap = &self.swap;
+
+		if settings.swap_details || settings.all_metrics {
+			metrics.gauge(
+				"swap_total",
+				"Total swap storage in bytes",
+				swap.total as f64,
+				Some("bytes"),
+				&[],
+				swap.refreshed,
+			);
+			metrics.gauge(
+				"swap_used",
+				"Used swap storage in bytes",
+				swap.used as f64,
+				Some("bytes"),
+				&[],
+				swap.refreshed,
+			);
+			metrics.gauge(
+				"swap_free",
+				"Free swap storage in bytes",
+				swap.free as f64,
+				Some("bytes"),
+				&[],
+				swap.refreshed,
+			);
+		}
+
+		metrics.gauge(
+			"swap",
+			"Used swap storage in percent",
+			round2(swap.percent),
+			Some("percent"),
+			&[],
+			swap.refreshed,
+		);
+	}
+
+	fn json(&self) -> JsonView {
+		let swap = json!(self.swap);
+		let filters = self.filters();
+		Box::new(move |query, _scopes| {
+			check_filters(query, filters)?;
+			Ok(swap.clone())
+		})
+	}
+}
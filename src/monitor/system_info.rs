@@ -1,3 +1,6 @@
+use serde::Serialize;
+
+#[derive(Serialize, Clone)]
 pub struct SystemInfo {
 	pub name: String,
 	pub kernel_version: String,
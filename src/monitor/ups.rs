@@ -1,5 +1,14 @@
+use crate::api::select;
+use crate::metrics::{round2, MetricType, MetricsBuilder};
+use crate::monitor::collector::{Collector, JsonView};
+use crate::monitor::settings::Settings;
+use crate::monitor::system_info::SystemInfo;
+use crate::utils::{command_output, serialize_timestamp};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::{process::Command, time::Duration};
 
+#[derive(Clone, Serialize)]
 pub struct UPS {
 	pub manufacturer: String,
 	pub model: String,
@@ -11,6 +20,7 @@ pub struct UPS {
 	pub output_voltage: f32,
 	pub real_power_nominal: f32,
 	pub power_usage: f32,
+	#[serde(serialize_with = "serialize_timestamp")]
 	pub refreshed: Duration,
 }
 
@@ -54,11 +64,17 @@ impl UPS {
 		}
 	}
 
-	pub fn get_ups_data(ups_name: &str, refreshed: Duration) -> Option<UPS> {
-		let output = Command::new("upsc").arg(ups_name).output().ok()?;
+	pub fn get_ups_data(
+		ups_name: &str,
+		refreshed: Duration,
+		timeout: Duration,
+	) -> Result<UPS, String> {
+		let output = command_output(Command::new("upsc").arg(ups_name), timeout)
+			.map_err(|e| format!("upsc {}: {}", ups_name, e))?;
 
 		if !output.status.success() {
-			return None;
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			return Err(format!("upsc {}: {}", ups_name, stderr.trim()));
 		}
 
 		let stdout = String::from_utf8_lossy(&output.stdout);
@@ -107,7 +123,7 @@ impl UPS {
 			info.power_usage = (info.load_percent / 100.0) * info.real_power_nominal;
 		}
 
-		Some(info)
+		Ok(info)
 	}
 }
 
@@ -116,3 +132,208 @@ impl Default for UPS {
 		Self::new()
 	}
 }
+
+/// Collects the state of the UPS devices managed by NUT.
+pub struct UpsCollector {
+	upses: HashMap<String, UPS>,
+}
+
+impl UpsCollector {
+	pub fn new() -> Self {
+		UpsCollector {
+			upses: HashMap::new(),
+		}
+	}
+}
+
+impl Default for UpsCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Collector for UpsCollector {
+	fn name(&self) -> &'static str {
+		"ups"
+	}
+
+	fn key(&self) -> &'static str {
+		"upses"
+	}
+
+	fn filters(&self) -> &'static [&'static str] {
+		&["name"]
+	}
+
+	fn entry_labels(&self) -> &'static [&'static str] {
+		&["ups"]
+	}
+
+	fn refresh(&mut self, settings: &Settings, now: Duration) -> Result<(), String> {
+		let timeout = settings.schedule(self.name()).timeout;
+		let mut errors = Vec::new();
+
+		for ups_name in &settings.upses {
+			// A UPS that does not answer keeps its last reading
+			match UPS::get_ups_data(ups_name, now, timeout) {
+				Ok(ups_data) => {
+					self.upses.insert(ups_name.to_string(), ups_data);
+				}
+				Err(e) => errors.push(e),
+			}
+		}
+
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(errors.join(", "))
+		}
+	}
+
+	fn metrics(&self, _settings: &Settings, _system_info: &SystemInfo, metrics: &mut MetricsBuilder) {
+		if self.upses.is_empty() {
+			return;
+		}
+
+		metrics.header(
+			"ups_charge",
+			"UPS battery charge level",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (ups_name, ups) in &self.upses {
+			metrics.sample(
+				round2(ups.charge_percent),
+				&[
+					("ups", ups_name),
+					("manufacturer", &ups.manufacturer),
+					("model", &ups.model),
+				],
+				ups.refreshed,
+			);
+		}
+
+		metrics.header(
+			"ups_load",
+			"UPS load level",
+			MetricType::Gauge,
+			Some("percent"),
+		);
+		for (ups_name, ups) in &self.upses {
+			metrics.sample(
+				round2(ups.load_percent),
+				&[
+					("ups", ups_name),
+					("manufacturer", &ups.manufacturer),
+					("model", &ups.model),
+				],
+				ups.refreshed,
+			);
+		}
+
+		metrics.header(
+			"ups_runtime",
+			"Estimated UPS runtime on battery",
+			MetricType::Gauge,
+			Some("seconds"),
+		);
+		for (ups_name, ups) in &self.upses {
+			metrics.sample(
+				ups.runtime_seconds as f64,
+				&[
+					("ups", ups_name),
+					("manufacturer", &ups.manufacturer),
+					("model", &ups.model),
+				],
+				ups.refreshed,
+			);
+		}
+
+		metrics.header(
+			"ups_input_voltage",
+			"UPS input voltage",
+			MetricType::Gauge,
+			Some("volts"),
+		);
+		for (ups_name, ups) in &self.upses {
+			metrics.sample(
+				round2(ups.input_voltage),
+				&[
+					("ups", ups_name),
+					("manufacturer", &ups.manufacturer),
+					("model", &ups.model),
+				],
+				ups.refreshed,
+			);
+		}
+
+		metrics.header(
+			"ups_output_voltage",
+			"UPS output voltage",
+			MetricType::Gauge,
+			Some("volts"),
+		);
+		for (ups_name, ups) in &self.upses {
+			metrics.sample(
+				round2(ups.output_voltage),
+				&[
+					("ups", ups_name),
+					("manufacturer", &ups.manufacturer),
+					("model", &ups.model),
+				],
+				ups.refreshed,
+			);
+		}
+
+		metrics.header(
+			"ups_status_info",
+			"UPS operational status",
+			MetricType::Info,
+			None,
+		);
+		for (ups_name, ups) in &self.upses {
+			metrics.sample(
+				1.0,
+				&[
+					("ups", ups_name),
+					("manufacturer", &ups.manufacturer),
+					("model", &ups.model),
+					("status", &ups.status),
+				],
+				ups.refreshed,
+			);
+		}
+
+		if self.upses.values().any(|ups| ups.real_power_nominal > 0.0) {
+			metrics.header(
+				"ups_power_usage",
+				"UPS power usage in watts",
+				MetricType::Gauge,
+				Some("watts"),
+			);
+			for (ups_name, ups) in &self.upses {
+				if ups.real_power_nominal > 0.0 {
+					metrics.sample(
+						round2(ups.power_usage),
+						&[
+							("ups", ups_name),
+							("manufacturer", &ups.manufacturer),
+							("model", &ups.model),
+						],
+						ups.refreshed,
+					);
+				}
+			}
+		}
+	}
+
+	fn json(&self) -> JsonView {
+		let upses = self.upses.clone();
+		let (subsystem, filters) = (self.name(), self.filters());
+		Box::new(move |query, scopes| {
+			select(&upses, query, filters, (subsystem, scopes), |_, name, _| {
+				name.to_string()
+			})
+		})
+	}
+}
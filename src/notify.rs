@@ -0,0 +1,494 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::alerts::{Alert, AlertState};
+use crate::config::{Config, NotifierConfig, NotifierKind};
+
+/// Timeout of a single delivery attempt.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry, doubled after every failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Delivers firing and resolved alerts to webhooks and chat services.
+pub struct Notifier {
+	targets: RwLock<Vec<NotifierConfig>>,
+	dead_letter_file: RwLock<Option<PathBuf>>,
+	agent: ureq::Agent,
+	/// Serializes writes to the dead-letter file
+	dead_letter: Mutex<()>,
+}
+
+impl Notifier {
+	pub fn new(config: &Config) -> Self {
+		let agent: ureq::Agent = ureq::Agent::config_builder()
+			.timeout_global(Some(REQUEST_TIMEOUT))
+			.http_status_as_error(true)
+			.build()
+			.into();
+
+		Notifier {
+			targets: RwLock::new(config.notifiers.clone()),
+			dead_letter_file: RwLock::new(config.dead_letter_file.clone()),
+			agent,
+			dead_letter: Mutex::new(()),
+		}
+	}
+
+	pub fn set_config(&self, config: &Config) {
+		*self.targets.write().unwrap() = config.notifiers.clone();
+		*self.dead_letter_file.write().unwrap() = config.dead_letter_file.clone();
+	}
+
+	/// Sends `alert` to every target that wants it. Every delivery runs on its own
+	/// thread, so a slow or failing target does not hold up the others.
+	pub fn notify(self: &Arc<Self>, alert: &Alert) {
+		let targets: Vec<NotifierConfig> = self
+			.targets
+			.read()
+			.unwrap()
+			.iter()
+			.filter(|target| target.rules.is_empty() || target.rules.contains(&alert.rule))
+			.filter(|target| target.send_resolved || alert.state != AlertState::Resolved)
+			.cloned()
+			.collect();
+
+		for target in targets {
+			let notifier = self.clone();
+			let alert = alert.clone();
+			std::thread::spawn(move || notifier.deliver(&target, &alert));
+		}
+	}
+
+	/// Sends one alert to one target, retrying with exponential backoff. Deliveries
+	/// that still fail after the last retry go to the dead-letter log.
+	fn deliver(&self, target: &NotifierConfig, alert: &Alert) {
+		let mut backoff = INITIAL_BACKOFF;
+		let mut attempts = 0;
+
+		loop {
+			attempts += 1;
+			let error = match self.send(target, alert) {
+				Ok(()) => return,
+				Err(e) => e,
+			};
+
+			if attempts > target.retries {
+				eprintln!(
+					"Failed to notify {} about {} after {} attempts: {}",
+					target.name, alert.rule, attempts, error
+				);
+				self.dead_letter(target, alert, attempts, &error);
+				return;
+			}
+
+			eprintln!(
+				"Failed to notify {} about {}, retrying in {}s: {}",
+				target.name,
+				alert.rule,
+				backoff.as_secs(),
+				error
+			);
+			sleep(backoff);
+			backoff = (backoff * 2).min(MAX_BACKOFF);
+		}
+	}
+
+	fn send(&self, target: &NotifierConfig, alert: &Alert) -> Result<(), ureq::Error> {
+		let (url, body, content_type) = payload(target, alert);
+
+		let mut request = self.agent.post(&url).header("Content-Type", content_type);
+
+		match (target.kind, &target.token) {
+			(NotifierKind::Gotify, Some(token)) => request = request.header("X-Gotify-Key", token),
+			(_, Some(token)) => request = request.header("Authorization", format!("Bearer {}", token)),
+			(_, None) => {}
+		}
+
+		if target.kind == NotifierKind::Ntfy {
+			request = request
+				.header("Title", format!("{} {}", alert.rule, alert.state))
+				.header("Priority", priority(alert).to_string())
+				.header(
+					"Tags",
+					match alert.state {
+						AlertState::Resolved => "white_check_mark",
+						_ => "rotating_light",
+					},
+				);
+		}
+
+		for (name, value) in &target.headers {
+			request = request.header(name, value);
+		}
+
+		request.send(body)?;
+		Ok(())
+	}
+
+	/// Appends a failed delivery to the dead-letter file as a line of JSON.
+	fn dead_letter(
+		&self,
+		target: &NotifierConfig,
+		alert: &Alert,
+		attempts: u32,
+		error: &ureq::Error,
+	) {
+		let Some(path) = self.dead_letter_file.read().unwrap().clone() else {
+			return;
+		};
+
+		let line = json!({
+			"time": Utc::now().to_rfc3339(),
+			"notifier": target.name,
+			"url": target.url,
+			"attempts": attempts,
+			"error": error.to_string(),
+			"alert": alert,
+		});
+
+		let _guard = self.dead_letter.lock().unwrap();
+		let result = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&path)
+			.and_then(|mut file| writeln!(file, "{}", line));
+		if let Err(e) = result {
+			eprintln!("Failed to write to {}: {}", path.display(), e);
+		}
+	}
+}
+
+/// Returns the URL, body and content type of the request for a target.
+fn payload(target: &NotifierConfig, alert: &Alert) -> (String, String, &'static str) {
+	let summary = summary(alert);
+
+	match target.kind {
+		NotifierKind::Webhook => {
+			let body = match &target.template {
+				Some(template) => render(template, alert, &summary),
+				None => {
+					let mut body = json!(alert);
+					body["summary"] = json!(summary);
+					body.to_string()
+				}
+			};
+			(target.url.clone(), body, "application/json")
+		}
+		NotifierKind::Slack => (
+			target.url.clone(),
+			json!({ "text": summary }).to_string(),
+			"application/json",
+		),
+		NotifierKind::Discord => (
+			target.url.clone(),
+			json!({ "content": summary }).to_string(),
+			"application/json",
+		),
+		NotifierKind::Ntfy => (target.url.clone(), summary, "text/plain"),
+		NotifierKind::Gotify => (
+			format!("{}/message", target.url.trim_end_matches('/')),
+			json!({
+				"title": format!("{} {}", alert.rule, alert.state),
+				"message": summary,
+				"priority": priority(alert) * 2,
+			})
+			.to_string(),
+			"application/json",
+		),
+	}
+}
+
+/// One line describing the alert, e.g. `[FIRING] RootDiskFull (/dev/sda1): Disk full (value: 93.4)`.
+pub fn summary(alert: &Alert) -> String {
+	let mut summary = format!(
+		"[{}] {}",
+		alert.state.to_string().to_uppercase(),
+		alert.rule
+	);
+	if let Some(entry) = &alert.entry {
+		summary.push_str(&format!(" ({})", entry));
+	}
+	if let Some(description) = &alert.description {
+		summary.push_str(&format!(": {}", description));
+	}
+	if !alert.value.is_null() {
+		summary.push_str(&format!(" (value: {})", alert.value));
+	}
+	summary
+}
+
+/// Priority on ntfy's scale of 1 to 5; Gotify uses twice that.
+fn priority(alert: &Alert) -> u8 {
+	if alert.state == AlertState::Resolved {
+		return 2;
+	}
+
+	match alert.severity.as_deref() {
+		Some("critical") => 5,
+		Some("warning") => 4,
+		_ => 3,
+	}
+}
+
+/// Replaces `{{placeholder}}` with the fields of the alert. Values are escaped as
+/// JSON string contents, so placeholders can be used inside quoted strings. The
+/// template is scanned once, so placeholders inside inserted values stay as they are.
+fn render(template: &str, alert: &Alert, summary: &str) -> String {
+	let text = |value: &Value| match value {
+		Value::String(s) => s.clone(),
+		Value::Null => String::new(),
+		value => value.to_string(),
+	};
+	let fields = json!(alert);
+	let value = |name: &str| match name {
+		"summary" => Some(summary.to_string()),
+		"rule" | "entry" | "state" | "severity" | "description" | "value" | "active_since"
+		| "fired_at" | "resolved_at" => Some(text(&fields[name])),
+		_ => None,
+	};
+
+	let mut body = String::with_capacity(template.len());
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		body.push_str(&rest[..start]);
+		let after = &rest[start + 2..];
+		let replacement = after
+			.find("}}")
+			.and_then(|end| value(&after[..end]).map(|value| (value, end)));
+		match replacement {
+			Some((value, end)) => {
+				body.push_str(&escape(&value));
+				rest = &after[end + 2..];
+			}
+			None => {
+				body.push_str("{{");
+				rest = after;
+			}
+		}
+	}
+	body.push_str(rest);
+	body
+}
+
+fn escape(value: &str) -> String {
+	let quoted = Value::from(value).to_string();
+	quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+	use std::io::{BufRead, BufReader, Read};
+	use std::net::TcpListener;
+	use std::sync::mpsc::{channel, Receiver};
+
+	struct Request {
+		path: String,
+		headers: HashMap<String, String>,
+		body: String,
+	}
+
+	/// Answers one request with each of `statuses` and passes the requests on.
+	fn serve(statuses: Vec<u16>) -> (String, Receiver<Request>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let (sender, receiver) = channel();
+
+		std::thread::spawn(move || {
+			for status in statuses {
+				let (stream, _) = listener.accept().unwrap();
+				let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+
+				let mut headers = HashMap::new();
+				loop {
+					let mut line = String::new();
+					reader.read_line(&mut line).unwrap();
+					let Some((name, value)) = line.trim_end().split_once(": ") else {
+						break;
+					};
+					headers.insert(name.to_lowercase(), value.to_string());
+				}
+
+				let length = headers
+					.get("content-length")
+					.map_or(0, |length| length.parse().unwrap());
+				let mut body = vec![0; length];
+				reader.read_exact(&mut body).unwrap();
+
+				let mut stream = stream;
+				write!(
+					stream,
+					"HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+					status
+				)
+				.unwrap();
+
+				let body = String::from_utf8(body).unwrap();
+				let _ = sender.send(Request {
+					path,
+					headers,
+					body,
+				});
+			}
+		});
+
+		(url, receiver)
+	}
+
+	fn target(kind: NotifierKind, url: &str) -> NotifierConfig {
+		NotifierConfig {
+			name: "test".to_string(),
+			kind,
+			url: url.to_string(),
+			token: None,
+			headers: HashMap::new(),
+			template: None,
+			rules: Vec::new(),
+			send_resolved: true,
+			retries: 0,
+		}
+	}
+
+	fn alert() -> Alert {
+		let mut alert = Alert::for_test("DiskFull", Some("/dev/sda1"), AlertState::Firing);
+		alert.severity = Some("critical".to_string());
+		alert.description = Some("Disk \"root\" is full".to_string());
+		alert.value = json!(93.4);
+		alert
+	}
+
+	fn body(request: &Request) -> Value {
+		serde_json::from_str(&request.body).unwrap()
+	}
+
+	#[test]
+	fn sends_chat_payloads_with_their_headers() {
+		let (url, requests) = serve(vec![200; 4]);
+		let notifier = Notifier::new(&Config::default());
+		let alert = alert();
+		let summary = summary(&alert);
+
+		notifier
+			.send(&target(NotifierKind::Slack, &url), &alert)
+			.unwrap();
+		let request = requests.recv().unwrap();
+		assert_eq!(request.headers["content-type"], "application/json");
+		assert_eq!(body(&request), json!({ "text": summary }));
+
+		notifier
+			.send(&target(NotifierKind::Discord, &url), &alert)
+			.unwrap();
+		let request = requests.recv().unwrap();
+		assert_eq!(body(&request), json!({ "content": summary }));
+
+		let mut ntfy = target(NotifierKind::Ntfy, &url);
+		ntfy.token = Some("secret".to_string());
+		notifier.send(&ntfy, &alert).unwrap();
+		let request = requests.recv().unwrap();
+		assert_eq!(request.headers["content-type"], "text/plain");
+		assert_eq!(request.headers["authorization"], "Bearer secret");
+		assert_eq!(request.headers["title"], "DiskFull firing");
+		assert_eq!(request.headers["priority"], "5");
+		assert_eq!(request.headers["tags"], "rotating_light");
+		assert_eq!(request.body, summary);
+
+		let mut gotify = target(NotifierKind::Gotify, &format!("{}/", url));
+		gotify.token = Some("secret".to_string());
+		notifier.send(&gotify, &alert).unwrap();
+		let request = requests.recv().unwrap();
+		assert_eq!(request.path, "/message");
+		assert_eq!(request.headers["x-gotify-key"], "secret");
+		assert!(!request.headers.contains_key("authorization"));
+		assert_eq!(
+			body(&request),
+			json!({ "title": "DiskFull firing", "message": summary, "priority": 10 })
+		);
+	}
+
+	#[test]
+	fn tags_resolved_ntfy_notifications() {
+		let (url, requests) = serve(vec![200]);
+		let notifier = Notifier::new(&Config::default());
+		let mut alert = alert();
+		alert.state = AlertState::Resolved;
+
+		notifier
+			.send(&target(NotifierKind::Ntfy, &url), &alert)
+			.unwrap();
+		let request = requests.recv().unwrap();
+		assert_eq!(request.headers["title"], "DiskFull resolved");
+		assert_eq!(request.headers["priority"], "2");
+		assert_eq!(request.headers["tags"], "white_check_mark");
+	}
+
+	#[test]
+	fn escapes_values_in_templated_webhook_bodies() {
+		let (url, requests) = serve(vec![200]);
+		let notifier = Notifier::new(&Config::default());
+		let mut alert = alert();
+		alert.description = Some("Disk \"root\" is {{value}}\nfull".to_string());
+
+		let mut webhook = target(NotifierKind::Webhook, &url);
+		webhook.template = Some(
+			r#"{"text": "{{summary}}", "note": "{{description}}", "value": {{value}}, "other": "{{unknown}}"}"#
+				.to_string(),
+		);
+		notifier.send(&webhook, &alert).unwrap();
+
+		let request = requests.recv().unwrap();
+		assert_eq!(
+			body(&request),
+			json!({
+				"text": summary(&alert),
+				"note": "Disk \"root\" is {{value}}\nfull",
+				"value": 93.4,
+				"other": "{{unknown}}",
+			})
+		);
+	}
+
+	#[test]
+	fn dead_letters_a_delivery_after_the_last_retry() {
+		let (url, requests) = serve(vec![500, 503]);
+		let path = std::env::temp_dir().join(format!("notify-test-{}.jsonl", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		let notifier = Notifier::new(&Config {
+			dead_letter_file: Some(path.clone()),
+			..Config::default()
+		});
+		let mut slack = target(NotifierKind::Slack, &url);
+		slack.retries = 1;
+
+		notifier.deliver(&slack, &alert());
+		for _ in 0..2 {
+			requests.recv_timeout(Duration::from_secs(1)).unwrap();
+		}
+
+		let contents = std::fs::read_to_string(&path).unwrap();
+		let _ = std::fs::remove_file(&path);
+		let lines: Vec<Value> = contents
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect();
+		assert_eq!(lines.len(), 1);
+		assert_eq!(lines[0]["notifier"], "test");
+		assert_eq!(lines[0]["url"], url);
+		assert_eq!(lines[0]["attempts"], 2);
+		assert_eq!(lines[0]["alert"]["rule"], "DiskFull");
+		assert!(lines[0]["error"].as_str().unwrap().contains("503"));
+	}
+}
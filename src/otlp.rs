@@ -0,0 +1,1026 @@
+use bytes::Bytes;
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{Config, OtlpConfig, OtlpProtocol};
+use crate::metrics::{MetricFamily, MetricType};
+use crate::monitor::system_info::SystemInfo;
+use crate::protobuf::{write_bytes, write_double, write_fixed64, write_uint};
+
+/// Timeout of a single export.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before the first retry, doubled after every failed export.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Refreshes kept in memory while the collector is down, the oldest are dropped first.
+const MAX_QUEUED: usize = 1000;
+
+/// Path of the OTLP/HTTP metrics endpoint below the configured base URL.
+const HTTP_PATH: &str = "/v1/metrics";
+
+/// Method of the OTLP/gRPC metrics service.
+const GRPC_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+/// gRPC status codes worth retrying, see the OTLP specification.
+const GRPC_RETRYABLE: [u32; 7] = [1, 4, 8, 10, 11, 14, 15];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+	Gauge,
+	/// A sum that can go up and down, such as memory in use
+	UpDownCounter,
+	/// A monotonic, cumulative sum
+	Counter,
+}
+
+/// A metric family exported under its OpenTelemetry semantic convention name.
+struct Semantic {
+	/// Name of the metric family, e.g. `memory_used`
+	family: &'static str,
+	name: &'static str,
+	description: &'static str,
+	unit: &'static str,
+	kind: Kind,
+	/// Factor the value is multiplied with, e.g. to turn percent into a ratio
+	scale: f64,
+	/// Attribute that tells the families mapped to the same metric apart
+	attribute: Option<(&'static str, &'static str)>,
+}
+
+const SEMANTIC: &[Semantic] = &[
+	Semantic {
+		family: "cpu_load",
+		name: "system.cpu.utilization",
+		description: "Utilization of all CPUs",
+		unit: "1",
+		kind: Kind::Gauge,
+		scale: 0.01,
+		attribute: None,
+	},
+	Semantic {
+		family: "memory_used",
+		name: "system.memory.usage",
+		description: "Memory in use by state",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: Some(("system.memory.state", "used")),
+	},
+	Semantic {
+		family: "memory_free",
+		name: "system.memory.usage",
+		description: "Memory in use by state",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: Some(("system.memory.state", "free")),
+	},
+	Semantic {
+		family: "memory_total",
+		name: "system.memory.limit",
+		description: "Total memory available in the system",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: None,
+	},
+	Semantic {
+		family: "memory",
+		name: "system.memory.utilization",
+		description: "Memory in use as a fraction of the total memory",
+		unit: "1",
+		kind: Kind::Gauge,
+		scale: 0.01,
+		attribute: Some(("system.memory.state", "used")),
+	},
+	Semantic {
+		family: "swap_used",
+		name: "system.paging.usage",
+		description: "Swap space in use by state",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: Some(("system.paging.state", "used")),
+	},
+	Semantic {
+		family: "swap_free",
+		name: "system.paging.usage",
+		description: "Swap space in use by state",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: Some(("system.paging.state", "free")),
+	},
+	Semantic {
+		family: "swap",
+		name: "system.paging.utilization",
+		description: "Swap space in use as a fraction of the total swap space",
+		unit: "1",
+		kind: Kind::Gauge,
+		scale: 0.01,
+		attribute: Some(("system.paging.state", "used")),
+	},
+	Semantic {
+		family: "storage_used",
+		name: "system.filesystem.usage",
+		description: "Filesystem space in use by state",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: Some(("system.filesystem.state", "used")),
+	},
+	Semantic {
+		family: "storage_free",
+		name: "system.filesystem.usage",
+		description: "Filesystem space in use by state",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: Some(("system.filesystem.state", "free")),
+	},
+	Semantic {
+		family: "storage_total",
+		name: "system.filesystem.limit",
+		description: "Total size of the filesystem",
+		unit: "By",
+		kind: Kind::UpDownCounter,
+		scale: 1.0,
+		attribute: None,
+	},
+	Semantic {
+		family: "storage",
+		name: "system.filesystem.utilization",
+		description: "Filesystem space in use as a fraction of its size",
+		unit: "1",
+		kind: Kind::Gauge,
+		scale: 0.01,
+		attribute: Some(("system.filesystem.state", "used")),
+	},
+	Semantic {
+		family: "network_received",
+		name: "system.network.io",
+		description: "Bytes received and transmitted",
+		unit: "By",
+		kind: Kind::Counter,
+		scale: 1.0,
+		attribute: Some(("network.io.direction", "receive")),
+	},
+	Semantic {
+		family: "network_transmitted",
+		name: "system.network.io",
+		description: "Bytes received and transmitted",
+		unit: "By",
+		kind: Kind::Counter,
+		scale: 1.0,
+		attribute: Some(("network.io.direction", "transmit")),
+	},
+	Semantic {
+		family: "network_packets_received",
+		name: "system.network.packets",
+		description: "Packets received and transmitted",
+		unit: "{packet}",
+		kind: Kind::Counter,
+		scale: 1.0,
+		attribute: Some(("network.io.direction", "receive")),
+	},
+	Semantic {
+		family: "network_packets_transmitted",
+		name: "system.network.packets",
+		description: "Packets received and transmitted",
+		unit: "{packet}",
+		kind: Kind::Counter,
+		scale: 1.0,
+		attribute: Some(("network.io.direction", "transmit")),
+	},
+	Semantic {
+		family: "network_errors_received",
+		name: "system.network.errors",
+		description: "Errors while receiving and transmitting",
+		unit: "{error}",
+		kind: Kind::Counter,
+		scale: 1.0,
+		attribute: Some(("network.io.direction", "receive")),
+	},
+	Semantic {
+		family: "network_errors_transmitted",
+		name: "system.network.errors",
+		description: "Errors while receiving and transmitting",
+		unit: "{error}",
+		kind: Kind::Counter,
+		scale: 1.0,
+		attribute: Some(("network.io.direction", "transmit")),
+	},
+];
+
+/// Attribute name of a label of a metric exported under a semantic convention name.
+fn semantic_attribute(label: &str) -> &str {
+	match label {
+		"device" => "system.device",
+		"mount" => "system.filesystem.mountpoint",
+		"interface" => "network.interface.name",
+		label => label,
+	}
+}
+
+/// UCUM unit of a metric family exported under its own name.
+fn unit(unit: Option<&str>) -> &str {
+	match unit {
+		Some("bytes") => "By",
+		Some("bytes_per_second") => "By/s",
+		Some("percent") => "%",
+		Some("hertz") => "Hz",
+		Some("seconds") => "s",
+		Some("celsius") => "Cel",
+		Some("volts") => "V",
+		Some("watts") => "W",
+		Some("watt_hours") => "W.h",
+		Some(unit) => unit,
+		None => "",
+	}
+}
+
+struct DataPoint {
+	attributes: Vec<(String, String)>,
+	start: Option<Duration>,
+	time: Duration,
+	value: f64,
+}
+
+struct Metric {
+	name: String,
+	description: String,
+	unit: String,
+	kind: Kind,
+	points: Vec<DataPoint>,
+}
+
+/// Resource attributes describing this host, followed by the configured ones.
+pub fn resource(
+	system_info: &SystemInfo,
+	extra: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+	let os_type = match std::env::consts::OS {
+		"macos" => "darwin",
+		os => os,
+	};
+
+	let mut attributes: Vec<(String, String)> = vec![
+		(String::from("service.name"), String::from("rabbitmonitor")),
+		(
+			String::from("service.version"),
+			String::from(env!("CARGO_PKG_VERSION")),
+		),
+		(String::from("host.name"), system_info.host_name.clone()),
+		(
+			String::from("host.arch"),
+			String::from(std::env::consts::ARCH),
+		),
+		(String::from("os.type"), String::from(os_type)),
+		(String::from("os.name"), system_info.name.clone()),
+		(String::from("os.version"), system_info.os_version.clone()),
+		(
+			String::from("os.description"),
+			system_info.long_os_version.clone(),
+		),
+		(
+			String::from("os.kernel.version"),
+			system_info.kernel_version.clone(),
+		),
+	];
+	attributes.retain(|(_, value)| !value.is_empty());
+
+	for (key, value) in extra {
+		attributes.retain(|(k, _)| k != key);
+		attributes.push((key.clone(), value.clone()));
+	}
+	attributes
+}
+
+/// Encodes metric families as an OTLP `ExportMetricsServiceRequest`. Families with
+/// an OpenTelemetry semantic convention counterpart are exported under its name, the
+/// others under their Prometheus name. Info metrics become gauges with the value 1.
+pub fn encode(families: &[MetricFamily], resource: &[(String, String)], now: Duration) -> Vec<u8> {
+	let mut metrics: Vec<Metric> = Vec::new();
+	let mut index: HashMap<String, usize> = HashMap::new();
+
+	for family in families {
+		let semantic = SEMANTIC.iter().find(|s| s.family == family.name);
+
+		let (name, description, unit, kind, scale) = match semantic {
+			Some(s) => (
+				s.name.to_string(),
+				s.description.to_string(),
+				s.unit.to_string(),
+				s.kind,
+				s.scale,
+			),
+			None => (
+				family.full_name(),
+				family.description.clone(),
+				unit(family.unit.as_deref()).to_string(),
+				match family.metric_type {
+					MetricType::Counter => Kind::Counter,
+					_ => Kind::Gauge,
+				},
+				1.0,
+			),
+		};
+
+		let position = *index.entry(name.clone()).or_insert_with(|| {
+			metrics.push(Metric {
+				name,
+				description,
+				unit,
+				kind,
+				points: Vec::new(),
+			});
+			metrics.len() - 1
+		});
+
+		for sample in &family.samples {
+			let mut attributes: Vec<(String, String)> = match semantic {
+				Some(_) => sample
+					.labels
+					.iter()
+					.map(|(k, v)| (semantic_attribute(k).to_string(), v.clone()))
+					.collect(),
+				None => sample.labels.clone(),
+			};
+			if let Some((key, value)) = semantic.and_then(|s| s.attribute) {
+				attributes.push((key.to_string(), value.to_string()));
+			}
+
+			metrics[position].points.push(DataPoint {
+				attributes,
+				start: sample.created.map(Duration::from_secs),
+				time: sample.timestamp.unwrap_or(now),
+				value: sample.value * scale,
+			});
+		}
+	}
+
+	// message ScopeMetrics { InstrumentationScope scope = 1; repeated Metric metrics = 2; }
+	let mut scope_metrics = Vec::new();
+	let mut scope = Vec::new();
+	write_bytes(&mut scope, 1, b"rabbitmonitor");
+	write_bytes(&mut scope, 2, env!("CARGO_PKG_VERSION").as_bytes());
+	write_bytes(&mut scope_metrics, 1, &scope);
+	for metric in &metrics {
+		write_bytes(&mut scope_metrics, 2, &encode_metric(metric));
+	}
+
+	// message Resource { repeated KeyValue attributes = 1; }
+	let mut encoded_resource = Vec::new();
+	for (key, value) in resource {
+		write_bytes(&mut encoded_resource, 1, &encode_attribute(key, value));
+	}
+
+	// message ResourceMetrics { Resource resource = 1; repeated ScopeMetrics scope_metrics = 2; }
+	let mut resource_metrics = Vec::new();
+	write_bytes(&mut resource_metrics, 1, &encoded_resource);
+	write_bytes(&mut resource_metrics, 2, &scope_metrics);
+
+	// message ExportMetricsServiceRequest { repeated ResourceMetrics resource_metrics = 1; }
+	let mut request = Vec::new();
+	write_bytes(&mut request, 1, &resource_metrics);
+	request
+}
+
+/// Encodes a `Metric` with its `Gauge` or `Sum`.
+fn encode_metric(metric: &Metric) -> Vec<u8> {
+	// message NumberDataPoint { fixed64 start_time_unix_nano = 2; fixed64 time_unix_nano = 3;
+	//   double as_double = 4; repeated KeyValue attributes = 7; }
+	let mut data = Vec::new();
+	for point in &metric.points {
+		let mut encoded = Vec::new();
+		if let Some(start) = point.start {
+			write_fixed64(&mut encoded, 2, start.as_nanos() as u64);
+		}
+		write_fixed64(&mut encoded, 3, point.time.as_nanos() as u64);
+		write_double(&mut encoded, 4, point.value);
+		for (key, value) in &point.attributes {
+			write_bytes(&mut encoded, 7, &encode_attribute(key, value));
+		}
+		write_bytes(&mut data, 1, &encoded);
+	}
+
+	// message Metric { string name = 1; string description = 2; string unit = 3;
+	//   Gauge gauge = 5; Sum sum = 7; }
+	let mut encoded = Vec::new();
+	write_bytes(&mut encoded, 1, metric.name.as_bytes());
+	write_bytes(&mut encoded, 2, metric.description.as_bytes());
+	write_bytes(&mut encoded, 3, metric.unit.as_bytes());
+	match metric.kind {
+		// message Gauge { repeated NumberDataPoint data_points = 1; }
+		Kind::Gauge => write_bytes(&mut encoded, 5, &data),
+		// message Sum { repeated NumberDataPoint data_points = 1;
+		//   AggregationTemporality aggregation_temporality = 2; bool is_monotonic = 3; }
+		Kind::UpDownCounter | Kind::Counter => {
+			// AGGREGATION_TEMPORALITY_CUMULATIVE
+			write_uint(&mut data, 2, 2);
+			if metric.kind == Kind::Counter {
+				write_uint(&mut data, 3, 1);
+			}
+			write_bytes(&mut encoded, 7, &data);
+		}
+	}
+	encoded
+}
+
+/// Encodes a `KeyValue` with a string value.
+fn encode_attribute(key: &str, value: &str) -> Vec<u8> {
+	// message AnyValue { string string_value = 1; }
+	let mut any = Vec::new();
+	write_bytes(&mut any, 1, value.as_bytes());
+
+	// message KeyValue { string key = 1; AnyValue value = 2; }
+	let mut encoded = Vec::new();
+	write_bytes(&mut encoded, 1, key.as_bytes());
+	write_bytes(&mut encoded, 2, &any);
+	encoded
+}
+
+enum Outcome {
+	Sent,
+	/// The collector refused the request, sending it again would not help
+	Rejected(String),
+	/// The collector is unreachable or overloaded, the request should be retried
+	Failed(String),
+}
+
+/// HTTP/2 connection to a gRPC collector, reused between exports.
+struct Channel {
+	endpoint: String,
+	sender: h2::client::SendRequest<Bytes>,
+}
+
+/// Exports every refresh to an OpenTelemetry collector over OTLP/HTTP or OTLP/gRPC.
+pub struct OtlpExporter {
+	settings: RwLock<Option<OtlpConfig>>,
+	queue: Mutex<VecDeque<Vec<u8>>>,
+	wakeup: Condvar,
+	agent: ureq::Agent,
+}
+
+impl OtlpExporter {
+	/// Creates the exporter and starts the thread that sends the queued refreshes.
+	pub fn start(config: &Config) -> Arc<Self> {
+		let agent: ureq::Agent = ureq::Agent::config_builder()
+			.timeout_global(Some(REQUEST_TIMEOUT))
+			.http_status_as_error(false)
+			.build()
+			.into();
+
+		let exporter = Arc::new(OtlpExporter {
+			settings: RwLock::new(config.otlp.clone()),
+			queue: Mutex::new(VecDeque::new()),
+			wakeup: Condvar::new(),
+			agent,
+		});
+
+		let sender = exporter.clone();
+		std::thread::spawn(move || sender.run());
+
+		exporter
+	}
+
+	pub fn set_config(&self, config: &Config) {
+		if config.otlp.is_none() {
+			self.queue.lock().unwrap().clear();
+		}
+		*self.settings.write().unwrap() = config.otlp.clone();
+	}
+
+	/// Queues the samples of a refresh.
+	pub fn push(&self, families: &[MetricFamily], system_info: &SystemInfo, now: Duration) {
+		let Some(settings) = self.settings.read().unwrap().clone() else {
+			return;
+		};
+
+		let resource = resource(system_info, &settings.resource_attributes);
+		let request = encode(families, &resource, now);
+
+		let mut queue = self.queue.lock().unwrap();
+		queue.push_back(request);
+		if queue.len() > MAX_QUEUED {
+			queue.pop_front();
+			eprintln!("OTLP export queue is full, dropped the oldest refresh");
+		}
+		self.wakeup.notify_one();
+	}
+
+	fn run(&self) {
+		let runtime = match tokio::runtime::Builder::new_multi_thread()
+			.worker_threads(1)
+			.enable_all()
+			.build()
+		{
+			Ok(runtime) => runtime,
+			Err(e) => {
+				eprintln!("Failed to start the OTLP exporter: {}", e);
+				return;
+			}
+		};
+		let mut channel: Option<Channel> = None;
+		let mut backoff = INITIAL_BACKOFF;
+
+		loop {
+			let request = {
+				let mut queue = self.queue.lock().unwrap();
+				if queue.is_empty() {
+					let _ = self.wakeup.wait_timeout(queue, Duration::from_secs(1));
+					continue;
+				}
+				queue.pop_front().unwrap()
+			};
+			let Some(settings) = self.settings.read().unwrap().clone() else {
+				continue;
+			};
+
+			let outcome = match settings.protocol {
+				OtlpProtocol::HttpProtobuf => self.send_http(&settings, &request),
+				OtlpProtocol::Grpc => {
+					let result = runtime.block_on(async {
+						tokio::time::timeout(
+							REQUEST_TIMEOUT,
+							send_grpc(&mut channel, &settings, &request),
+						)
+						.await
+					});
+					match result {
+						Ok(outcome) => outcome,
+						Err(_) => Outcome::Failed(String::from("timed out")),
+					}
+				}
+			};
+
+			match outcome {
+				Outcome::Sent => backoff = INITIAL_BACKOFF,
+				Outcome::Rejected(reason) => {
+					eprintln!(
+						"OpenTelemetry collector rejected an export, dropping it: {}",
+						reason
+					);
+					backoff = INITIAL_BACKOFF;
+				}
+				Outcome::Failed(reason) => {
+					// The connection might be broken, open a new one for the retry
+					channel = None;
+
+					// Put the refresh back in front of the newer ones
+					let mut queue = self.queue.lock().unwrap();
+					if queue.len() < MAX_QUEUED {
+						queue.push_front(request);
+					}
+					drop(queue);
+
+					eprintln!(
+						"Failed to export metrics to {}, retrying in {}s: {}",
+						settings.endpoint,
+						backoff.as_secs(),
+						reason
+					);
+					std::thread::sleep(backoff);
+					backoff = (backoff * 2).min(MAX_BACKOFF);
+				}
+			}
+		}
+	}
+
+	fn send_http(&self, settings: &OtlpConfig, request: &[u8]) -> Outcome {
+		let endpoint = settings.endpoint.trim_end_matches('/');
+		let url = match endpoint.ends_with(HTTP_PATH) {
+			true => endpoint.to_string(),
+			false => format!("{}{}", endpoint, HTTP_PATH),
+		};
+
+		let mut http_request = self
+			.agent
+			.post(&url)
+			.header("Content-Type", "application/x-protobuf")
+			.header(
+				"User-Agent",
+				format!("rabbitmonitor/{}", env!("CARGO_PKG_VERSION")),
+			);
+		for (name, value) in &settings.headers {
+			http_request = http_request.header(name, value);
+		}
+
+		match http_request.send(request) {
+			Ok(mut response) => match response.status().as_u16() {
+				200..=299 => Outcome::Sent,
+				status @ (429 | 500..=599) => Outcome::Failed(format!("HTTP status {}", status)),
+				status => {
+					let message = response.body_mut().read_to_string().unwrap_or_default();
+					Outcome::Rejected(format!("HTTP status {}: {}", status, message.trim()))
+				}
+			},
+			Err(e) => Outcome::Failed(e.to_string()),
+		}
+	}
+}
+
+/// Sends one export over gRPC, opening a connection first if there is none yet.
+async fn send_grpc(
+	channel: &mut Option<Channel>,
+	settings: &OtlpConfig,
+	request: &[u8],
+) -> Outcome {
+	if channel
+		.as_ref()
+		.is_some_and(|channel| channel.endpoint != settings.endpoint)
+	{
+		*channel = None;
+	}
+	if channel.is_none() {
+		match connect(&settings.endpoint).await {
+			Ok(sender) => {
+				*channel = Some(Channel {
+					endpoint: settings.endpoint.clone(),
+					sender,
+				})
+			}
+			Err(e) => return Outcome::Failed(e),
+		}
+	}
+	let sender = channel.as_ref().unwrap().sender.clone();
+
+	let uri: http::Uri = match settings.endpoint.parse() {
+		Ok(uri) => uri,
+		Err(e) => return Outcome::Rejected(e.to_string()),
+	};
+	let mut builder = http::Request::builder()
+		.method("POST")
+		.uri(format!(
+			"{}://{}{}",
+			uri.scheme_str().unwrap_or("http"),
+			uri.authority().map(|a| a.as_str()).unwrap_or_default(),
+			GRPC_PATH
+		))
+		.header("content-type", "application/grpc")
+		.header("te", "trailers")
+		.header(
+			"user-agent",
+			format!("rabbitmonitor/{}", env!("CARGO_PKG_VERSION")),
+		);
+	for (name, value) in &settings.headers {
+		builder = builder.header(name.as_str(), value.as_str());
+	}
+	let http_request = match builder.body(()) {
+		Ok(http_request) => http_request,
+		Err(e) => return Outcome::Rejected(e.to_string()),
+	};
+
+	// Length-prefixed message: an uncompressed flag followed by the length in big endian
+	let mut message = Vec::with_capacity(request.len() + 5);
+	message.push(0);
+	message.extend_from_slice(&(request.len() as u32).to_be_bytes());
+	message.extend_from_slice(request);
+
+	let response = async {
+		let mut sender = sender.ready().await?;
+		let (response, mut stream) = sender.send_request(http_request, false)?;
+		stream.send_data(message.into(), true)?;
+
+		let (parts, mut body) = response.await?.into_parts();
+		while let Some(chunk) = body.data().await {
+			let chunk = chunk?;
+			let _ = body.flow_control().release_capacity(chunk.len());
+		}
+		let trailers = body.trailers().await?;
+		Ok::<_, h2::Error>((parts, trailers))
+	};
+
+	let (parts, trailers) = match response.await {
+		Ok(response) => response,
+		Err(e) => return Outcome::Failed(e.to_string()),
+	};
+
+	let status = parts.status.as_u16();
+	if status != 200 {
+		return match status {
+			429 | 500..=599 => Outcome::Failed(format!("HTTP status {}", status)),
+			_ => Outcome::Rejected(format!("HTTP status {}", status)),
+		};
+	}
+
+	// A response without a body carries the status in its headers
+	let header = |name: &str| {
+		trailers
+			.as_ref()
+			.and_then(|trailers| trailers.get(name))
+			.or_else(|| parts.headers.get(name))
+			.and_then(|value| value.to_str().ok())
+			.map(|value| value.to_string())
+	};
+	let code: u32 = header("grpc-status")
+		.and_then(|code| code.parse().ok())
+		.unwrap_or(2);
+	let reason = format!(
+		"gRPC status {}: {}",
+		code,
+		header("grpc-message").unwrap_or_default()
+	);
+
+	match code {
+		0 => Outcome::Sent,
+		code if GRPC_RETRYABLE.contains(&code) => Outcome::Failed(reason),
+		_ => Outcome::Rejected(reason),
+	}
+}
+
+/// Opens an HTTP/2 connection to a collector, with TLS for `https://` endpoints.
+async fn connect(endpoint: &str) -> Result<h2::client::SendRequest<Bytes>, String> {
+	let uri: http::Uri = endpoint
+		.parse()
+		.map_err(|e: http::uri::InvalidUri| e.to_string())?;
+	let tls = uri.scheme_str() == Some("https");
+	let host = uri
+		.host()
+		.ok_or_else(|| String::from("missing host"))?
+		.trim_start_matches('[')
+		.trim_end_matches(']')
+		.to_string();
+	let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+
+	let stream = TcpStream::connect((host.as_str(), port))
+		.await
+		.map_err(|e| e.to_string())?;
+	let _ = stream.set_nodelay(true);
+
+	if !tls {
+		let (sender, connection) = h2::client::handshake(stream)
+			.await
+			.map_err(|e| e.to_string())?;
+		tokio::spawn(async move {
+			let _ = connection.await;
+		});
+		return Ok(sender);
+	}
+
+	let mut roots = rustls::RootCertStore::empty();
+	roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+	let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+		.with_safe_default_protocol_versions()
+		.map_err(|e| e.to_string())?
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	config.alpn_protocols = vec![b"h2".to_vec()];
+
+	let server_name = ServerName::try_from(host).map_err(|e| e.to_string())?;
+	let stream = TlsConnector::from(Arc::new(config))
+		.connect(server_name, stream)
+		.await
+		.map_err(|e| e.to_string())?;
+
+	let (sender, connection) = h2::client::handshake(stream)
+		.await
+		.map_err(|e| e.to_string())?;
+	tokio::spawn(async move {
+		let _ = connection.await;
+	});
+	Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::metrics::Sample;
+
+	enum Field {
+		Varint(u64),
+		Fixed64(u64),
+		Bytes(Vec<u8>),
+	}
+
+	/// Splits an encoded message into its fields.
+	fn decode(mut bytes: &[u8]) -> Vec<(u64, Field)> {
+		fn varint(bytes: &mut &[u8]) -> u64 {
+			let mut value = 0;
+			let mut shift = 0;
+			loop {
+				let byte = bytes[0];
+				*bytes = &bytes[1..];
+				value |= u64::from(byte & 0x7f) << shift;
+				if byte < 0x80 {
+					return value;
+				}
+				shift += 7;
+			}
+		}
+
+		let mut fields = Vec::new();
+		while !bytes.is_empty() {
+			let tag = varint(&mut bytes);
+			let field = match tag & 7 {
+				0 => Field::Varint(varint(&mut bytes)),
+				1 => {
+					let (value, rest) = bytes.split_at(8);
+					bytes = rest;
+					Field::Fixed64(u64::from_le_bytes(value.try_into().unwrap()))
+				}
+				2 => {
+					let length = varint(&mut bytes) as usize;
+					let (value, rest) = bytes.split_at(length);
+					bytes = rest;
+					Field::Bytes(value.to_vec())
+				}
+				wire_type => panic!("unexpected wire type {}", wire_type),
+			};
+			fields.push((tag >> 3, field));
+		}
+		fields
+	}
+
+	fn messages(bytes: &[u8], number: u64) -> Vec<Vec<u8>> {
+		decode(bytes)
+			.into_iter()
+			.filter_map(|(n, field)| match field {
+				Field::Bytes(bytes) if n == number => Some(bytes),
+				_ => None,
+			})
+			.collect()
+	}
+
+	fn string(bytes: &[u8], number: u64) -> String {
+		String::from_utf8(messages(bytes, number).remove(0)).unwrap()
+	}
+
+	fn varints(bytes: &[u8], number: u64) -> Vec<u64> {
+		decode(bytes)
+			.into_iter()
+			.filter_map(|(n, field)| match field {
+				Field::Varint(value) if n == number => Some(value),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// A decoded `Metric`, with its data points as attributes and value.
+	struct Decoded {
+		name: String,
+		unit: String,
+		/// Whether it is a `Sum` rather than a `Gauge`
+		sum: bool,
+		temporality: Vec<u64>,
+		monotonic: Vec<u64>,
+		points: Vec<(Vec<(String, String)>, f64)>,
+	}
+
+	fn decode_request(request: &[u8]) -> Vec<Decoded> {
+		let resource_metrics = messages(request, 1).remove(0);
+		let scope_metrics = messages(&resource_metrics, 2).remove(0);
+
+		messages(&scope_metrics, 2)
+			.into_iter()
+			.map(|metric| {
+				let sum = messages(&metric, 7);
+				let data = match sum.first() {
+					Some(sum) => sum.clone(),
+					None => messages(&metric, 5).remove(0),
+				};
+
+				let points = messages(&data, 1)
+					.into_iter()
+					.map(|point| {
+						let attributes = messages(&point, 7)
+							.into_iter()
+							.map(|attribute| {
+								let value = messages(&attribute, 2).remove(0);
+								(string(&attribute, 1), string(&value, 1))
+							})
+							.collect();
+						let value = decode(&point)
+							.into_iter()
+							.find_map(|(n, field)| match field {
+								Field::Fixed64(bits) if n == 4 => Some(f64::from_bits(bits)),
+								_ => None,
+							})
+							.unwrap();
+						(attributes, value)
+					})
+					.collect();
+
+				Decoded {
+					name: string(&metric, 1),
+					unit: string(&metric, 3),
+					sum: !sum.is_empty(),
+					temporality: varints(&data, 2),
+					monotonic: varints(&data, 3),
+					points,
+				}
+			})
+			.collect()
+	}
+
+	fn family(
+		name: &str,
+		metric_type: MetricType,
+		samples: &[(&[(&str, &str)], f64)],
+	) -> MetricFamily {
+		MetricFamily {
+			name: name.to_string(),
+			description: String::new(),
+			metric_type,
+			unit: None,
+			subsystem: None,
+			samples: samples
+				.iter()
+				.map(|(labels, value)| Sample {
+					labels: labels
+						.iter()
+						.map(|(k, v)| (k.to_string(), v.to_string()))
+						.collect(),
+					value: *value,
+					timestamp: None,
+					created: None,
+				})
+				.collect(),
+		}
+	}
+
+	fn attributes(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+		pairs
+			.iter()
+			.map(|(k, v)| (k.to_string(), v.to_string()))
+			.collect()
+	}
+
+	fn export(families: &[MetricFamily]) -> Vec<Decoded> {
+		decode_request(&encode(families, &[], Duration::from_secs(1_700_000_000)))
+	}
+
+	#[test]
+	fn merges_memory_states_into_one_sum() {
+		let metrics = export(&[
+			family("memory_used", MetricType::Gauge, &[(&[], 6e9)]),
+			family("memory_free", MetricType::Gauge, &[(&[], 2e9)]),
+		]);
+
+		assert_eq!(metrics.len(), 1);
+		let usage = &metrics[0];
+		assert_eq!(usage.name, "system.memory.usage");
+		assert_eq!(usage.unit, "By");
+		assert!(usage.sum);
+		assert_eq!(usage.temporality, vec![2]);
+		assert!(usage.monotonic.is_empty());
+		assert_eq!(
+			usage.points,
+			vec![
+				(attributes(&[("system.memory.state", "used")]), 6e9),
+				(attributes(&[("system.memory.state", "free")]), 2e9),
+			]
+		);
+	}
+
+	#[test]
+	fn scales_percentages_to_ratios() {
+		let metrics = export(&[
+			family("cpu_load", MetricType::Gauge, &[(&[], 25.0)]),
+			family("storage", MetricType::Gauge, &[(&[("mount", "/")], 50.0)]),
+		]);
+
+		assert_eq!(metrics.len(), 2);
+		assert_eq!(metrics[0].name, "system.cpu.utilization");
+		assert_eq!(metrics[0].unit, "1");
+		assert!(!metrics[0].sum);
+		assert_eq!(metrics[0].points, vec![(Vec::new(), 0.25)]);
+
+		assert_eq!(metrics[1].unit, "1");
+		assert_eq!(metrics[1].points[0].1, 0.5);
+		assert!(metrics[1].points[0].0.contains(&(
+			String::from("system.filesystem.mountpoint"),
+			String::from("/")
+		)));
+	}
+
+	#[test]
+	fn writes_counters_as_cumulative_monotonic_sums() {
+		let metrics = export(&[
+			family(
+				"network_received",
+				MetricType::Counter,
+				&[(&[("interface", "eth0")], 1024.0)],
+			),
+			family("process_restarts", MetricType::Counter, &[(&[], 3.0)]),
+			family("process_count", MetricType::Gauge, &[(&[], 42.0)]),
+		]);
+
+		assert_eq!(metrics.len(), 3);
+		for counter in &metrics[..2] {
+			assert!(counter.sum);
+			assert_eq!(counter.temporality, vec![2]);
+			assert_eq!(counter.monotonic, vec![1]);
+		}
+		assert_eq!(metrics[1].name, "rabbit_process_restarts");
+		assert!(!metrics[2].sum);
+		assert!(metrics[2].temporality.is_empty());
+	}
+}
@@ -0,0 +1,33 @@
+/// Writes a base 128 varint, the encoding of protobuf integers and field tags.
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+/// Writes a varint field such as `int64`, `uint64`, `bool` or an enum.
+pub fn write_uint(out: &mut Vec<u8>, field: u64, value: u64) {
+	write_varint(out, field << 3);
+	write_varint(out, value);
+}
+
+/// Writes a `double` field.
+pub fn write_double(out: &mut Vec<u8>, field: u64, value: f64) {
+	write_varint(out, (field << 3) | 1);
+	out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Writes a `fixed64` field.
+pub fn write_fixed64(out: &mut Vec<u8>, field: u64, value: u64) {
+	write_varint(out, (field << 3) | 1);
+	out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Writes a length-delimited field: a string, bytes or an embedded message.
+pub fn write_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+	write_varint(out, (field << 3) | 2);
+	write_varint(out, bytes.len() as u64);
+	out.extend_from_slice(bytes);
+}
@@ -0,0 +1,338 @@
+use base64::Engine;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::config::{Config, RemoteWriteConfig};
+use crate::metrics::MetricFamily;
+use crate::protobuf::{write_bytes, write_double, write_uint};
+
+/// Timeout of a single push.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before the first retry, doubled after every failed push.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Extension of spooled requests.
+const SPOOL_EXTENSION: &str = "snappy";
+
+struct QueuedSample {
+	/// Labels including `__name__`, sorted by name as remote_write requires
+	labels: Vec<(String, String)>,
+	value: f64,
+	/// Milliseconds since the Unix epoch
+	timestamp: i64,
+}
+
+/// Labels of a series with its values and timestamps.
+type Series<'a> = (&'a [(String, String)], Vec<(f64, i64)>);
+
+enum Outcome {
+	Sent,
+	/// The receiver refused the request, sending it again would not help
+	Rejected(String),
+	/// The receiver is unreachable or overloaded, the request should be retried
+	Failed(String),
+}
+
+/// Pushes every refresh to a Prometheus remote_write endpoint. Samples wait in a
+/// bounded in-memory queue while the receiver is down; what does not fit is spooled
+/// to disk if a spool directory is configured and dropped otherwise.
+pub struct RemoteWriter {
+	settings: RwLock<Option<RemoteWriteConfig>>,
+	queue: Mutex<VecDeque<QueuedSample>>,
+	wakeup: Condvar,
+	agent: ureq::Agent,
+	/// Keeps the names of spool files unique within a millisecond
+	spool_sequence: AtomicU64,
+}
+
+impl RemoteWriter {
+	/// Creates the writer and starts the thread that sends the queued samples.
+	pub fn start(config: &Config) -> Arc<Self> {
+		let agent: ureq::Agent = ureq::Agent::config_builder()
+			.timeout_global(Some(REQUEST_TIMEOUT))
+			.http_status_as_error(false)
+			.build()
+			.into();
+
+		let writer = Arc::new(RemoteWriter {
+			settings: RwLock::new(config.remote_write.clone()),
+			queue: Mutex::new(VecDeque::new()),
+			wakeup: Condvar::new(),
+			agent,
+			spool_sequence: AtomicU64::new(0),
+		});
+
+		let sender = writer.clone();
+		std::thread::spawn(move || sender.run());
+
+		writer
+	}
+
+	pub fn set_config(&self, config: &Config) {
+		if config.remote_write.is_none() {
+			self.queue.lock().unwrap().clear();
+		}
+		*self.settings.write().unwrap() = config.remote_write.clone();
+	}
+
+	/// Queues the samples of a refresh. Info metrics are pushed like in the
+	/// Prometheus text format, as gauges with the value 1.
+	pub fn push(&self, families: &[MetricFamily], now: Duration) {
+		let Some(settings) = self.settings.read().unwrap().clone() else {
+			return;
+		};
+
+		let mut queue = self.queue.lock().unwrap();
+		for family in families {
+			let name = family.sample_name();
+			for sample in &family.samples {
+				let mut labels: Vec<(String, String)> = sample.labels.clone();
+				for (key, value) in &settings.labels {
+					if !labels.iter().any(|(k, _)| k == key) {
+						labels.push((key.clone(), value.clone()));
+					}
+				}
+				labels.push((String::from("__name__"), name.clone()));
+				labels.sort();
+
+				queue.push_back(QueuedSample {
+					labels,
+					value: sample.value,
+					timestamp: sample.timestamp.unwrap_or(now).as_millis() as i64,
+				});
+			}
+		}
+
+		// Move the oldest samples out of memory until the queue fits again
+		while queue.len() > settings.queue_capacity {
+			let count = settings.max_samples_per_send.min(queue.len());
+			let overflow: Vec<QueuedSample> = queue.drain(..count).collect();
+
+			match &settings.spool_dir {
+				Some(dir) => {
+					if let Err(e) = self.spool(dir, settings.spool_max_size, &overflow) {
+						eprintln!(
+							"Failed to spool {} samples to {}: {}",
+							overflow.len(),
+							dir.display(),
+							e
+						);
+					}
+				}
+				None => eprintln!(
+					"Remote write queue is full, dropped the {} oldest samples",
+					overflow.len()
+				),
+			}
+		}
+
+		self.wakeup.notify_one();
+	}
+
+	/// Sends spooled requests first, oldest first, and then the queue, so every
+	/// series reaches the receiver in order.
+	fn run(&self) {
+		let mut backoff = INITIAL_BACKOFF;
+
+		loop {
+			let Some(settings) = self.settings.read().unwrap().clone() else {
+				let queue = self.queue.lock().unwrap();
+				let _ = self.wakeup.wait_timeout(queue, Duration::from_secs(1));
+				continue;
+			};
+
+			let outcome = match spooled_files(settings.spool_dir.as_deref()).first() {
+				Some(path) => self.send_spooled(&settings, path),
+				None => {
+					let batch: Vec<QueuedSample> = {
+						let mut queue = self.queue.lock().unwrap();
+						if queue.is_empty() {
+							let _ = self.wakeup.wait_timeout(queue, Duration::from_secs(1));
+							continue;
+						}
+						let count = settings.max_samples_per_send.min(queue.len());
+						queue.drain(..count).collect()
+					};
+
+					let outcome = self.send(&settings, &encode(&batch));
+					if let Outcome::Failed(_) = outcome {
+						// Put the batch back in front of the newer samples
+						let mut queue = self.queue.lock().unwrap();
+						for sample in batch.into_iter().rev() {
+							queue.push_front(sample);
+						}
+					}
+					outcome
+				}
+			};
+
+			match outcome {
+				Outcome::Sent => backoff = INITIAL_BACKOFF,
+				Outcome::Rejected(reason) => {
+					eprintln!(
+						"Remote write receiver rejected samples, dropping them: {}",
+						reason
+					);
+					backoff = INITIAL_BACKOFF;
+				}
+				Outcome::Failed(reason) => {
+					eprintln!(
+						"Failed to push samples to {}, retrying in {}s: {}",
+						settings.url,
+						backoff.as_secs(),
+						reason
+					);
+					std::thread::sleep(backoff);
+					backoff = (backoff * 2).min(MAX_BACKOFF);
+				}
+			}
+		}
+	}
+
+	fn send_spooled(&self, settings: &RemoteWriteConfig, path: &Path) -> Outcome {
+		let body = match fs::read(path) {
+			Ok(body) => body,
+			Err(e) => {
+				let _ = fs::remove_file(path);
+				return Outcome::Rejected(format!("failed to read {}: {}", path.display(), e));
+			}
+		};
+
+		let outcome = self.send(settings, &body);
+		if !matches!(outcome, Outcome::Failed(_)) {
+			let _ = fs::remove_file(path);
+		}
+		outcome
+	}
+
+	fn send(&self, settings: &RemoteWriteConfig, body: &[u8]) -> Outcome {
+		let mut request = self
+			.agent
+			.post(&settings.url)
+			.header("Content-Type", "application/x-protobuf")
+			.header("Content-Encoding", "snappy")
+			.header("X-Prometheus-Remote-Write-Version", "0.1.0")
+			.header(
+				"User-Agent",
+				format!("rabbitmonitor/{}", env!("CARGO_PKG_VERSION")),
+			);
+
+		if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+			let credentials =
+				base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+			request = request.header("Authorization", format!("Basic {}", credentials));
+		}
+		if let Some(token) = &settings.bearer_token {
+			request = request.header("Authorization", format!("Bearer {}", token));
+		}
+
+		match request.send(body) {
+			Ok(response) => {
+				let status = response.status().as_u16();
+				match status {
+					200..=299 => Outcome::Sent,
+					429 | 500..=599 => Outcome::Failed(format!("HTTP status {}", status)),
+					_ => Outcome::Rejected(format!("HTTP status {}", status)),
+				}
+			}
+			Err(e) => Outcome::Failed(e.to_string()),
+		}
+	}
+
+	/// Writes samples as a ready-to-send request to the spool directory, and removes
+	/// the oldest spooled requests beyond `max_size` MiB.
+	fn spool(&self, dir: &Path, max_size: u64, samples: &[QueuedSample]) -> std::io::Result<()> {
+		fs::create_dir_all(dir)?;
+
+		let millis = chrono::Utc::now().timestamp_millis();
+		let sequence = self.spool_sequence.fetch_add(1, Ordering::Relaxed) % 1_000_000;
+		let path = dir.join(format!(
+			"{:013}-{:06}.{}",
+			millis, sequence, SPOOL_EXTENSION
+		));
+		fs::write(&path, encode(samples))?;
+
+		let files = spooled_files(Some(dir));
+		let mut total: u64 = files
+			.iter()
+			.filter_map(|file| fs::metadata(file).ok())
+			.map(|metadata| metadata.len())
+			.sum();
+		for file in &files {
+			if total <= max_size * 1024 * 1024 {
+				break;
+			}
+			let size = fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+			if fs::remove_file(file).is_ok() {
+				println!(
+					"Removed spooled samples {} to stay below the size limit",
+					file.display()
+				);
+				total = total.saturating_sub(size);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Spooled requests, oldest first.
+fn spooled_files(dir: Option<&Path>) -> Vec<PathBuf> {
+	let Some(entries) = dir.and_then(|dir| fs::read_dir(dir).ok()) else {
+		return Vec::new();
+	};
+
+	let mut files: Vec<PathBuf> = entries
+		.filter_map(|entry| entry.ok())
+		.map(|entry| entry.path())
+		.filter(|path| path.extension().is_some_and(|ext| ext == SPOOL_EXTENSION))
+		.collect();
+	files.sort();
+	files
+}
+
+/// Encodes samples as a snappy-compressed remote_write `WriteRequest`. Samples of
+/// the same series are grouped into one `TimeSeries`.
+fn encode(samples: &[QueuedSample]) -> Vec<u8> {
+	let mut series: Vec<Series> = Vec::new();
+	let mut index: HashMap<&[(String, String)], usize> = HashMap::new();
+	for sample in samples {
+		let position = *index.entry(&sample.labels).or_insert_with(|| {
+			series.push((&sample.labels, Vec::new()));
+			series.len() - 1
+		});
+		series[position].1.push((sample.value, sample.timestamp));
+	}
+
+	// message WriteRequest { repeated TimeSeries timeseries = 1; }
+	let mut request = Vec::new();
+	for (labels, points) in series {
+		// message TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }
+		let mut timeseries = Vec::new();
+		for (name, value) in labels {
+			// message Label { string name = 1; string value = 2; }
+			let mut label = Vec::new();
+			write_bytes(&mut label, 1, name.as_bytes());
+			write_bytes(&mut label, 2, value.as_bytes());
+			write_bytes(&mut timeseries, 1, &label);
+		}
+		for (value, timestamp) in points {
+			// message Sample { double value = 1; int64 timestamp = 2; }
+			let mut sample = Vec::new();
+			write_double(&mut sample, 1, value);
+			write_uint(&mut sample, 2, timestamp as u64);
+			write_bytes(&mut timeseries, 2, &sample);
+		}
+		write_bytes(&mut request, 1, &timeseries);
+	}
+
+	snap::raw::Encoder::new()
+		.compress_vec(&request)
+		.unwrap_or_default()
+}
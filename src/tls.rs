@@ -0,0 +1,143 @@
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+
+/// How often certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TlsFiles {
+	pub cert: PathBuf,
+	pub key: PathBuf,
+	pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct TlsError {
+	path: PathBuf,
+	message: String,
+}
+
+impl fmt::Display for TlsError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.path.display(), self.message)
+	}
+}
+
+impl std::error::Error for TlsError {}
+
+impl TlsFiles {
+	pub fn from_config(config: &Config) -> Option<Self> {
+		match (&config.tls_cert, &config.tls_key) {
+			(Some(cert), Some(key)) => Some(TlsFiles {
+				cert: cert.clone(),
+				key: key.clone(),
+				client_ca: config.tls_client_ca.clone(),
+			}),
+			_ => None,
+		}
+	}
+
+	/// Builds a rustls server configuration. When a client CA is set, clients
+	/// have to present a certificate signed by it.
+	pub fn server_config(&self) -> Result<Arc<ServerConfig>, TlsError> {
+		let provider = Arc::new(ring::default_provider());
+
+		let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(&self.cert)
+			.and_then(|certs| certs.collect())
+			.map_err(|e| tls_error(&self.cert, e))?;
+
+		if certs.is_empty() {
+			return Err(tls_error(&self.cert, "no certificates found"));
+		}
+
+		let key = PrivateKeyDer::from_pem_file(&self.key).map_err(|e| tls_error(&self.key, e))?;
+
+		let builder = ServerConfig::builder_with_provider(provider.clone())
+			.with_safe_default_protocol_versions()
+			.map_err(|e| tls_error(&self.cert, e))?;
+
+		let builder = match &self.client_ca {
+			Some(client_ca) => {
+				let mut roots = RootCertStore::empty();
+				for cert in CertificateDer::pem_file_iter(client_ca).map_err(|e| tls_error(client_ca, e))? {
+					let cert = cert.map_err(|e| tls_error(client_ca, e))?;
+					roots.add(cert).map_err(|e| tls_error(client_ca, e))?;
+				}
+
+				let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+					.build()
+					.map_err(|e| tls_error(client_ca, e))?;
+				builder.with_client_cert_verifier(verifier)
+			}
+			None => builder.with_no_client_auth(),
+		};
+
+		let mut server_config = builder
+			.with_single_cert(certs, key)
+			.map_err(|e| tls_error(&self.key, e))?;
+		server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+		Ok(Arc::new(server_config))
+	}
+
+	fn modified(&self) -> Vec<Option<SystemTime>> {
+		[Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+			.iter()
+			.map(|path| path.and_then(|p| fs::metadata(p).and_then(|m| m.modified()).ok()))
+			.collect()
+	}
+}
+
+/// Reloads the certificates whenever one of the files changes on disk, or the
+/// configured paths change after a configuration reload. A broken certificate
+/// is reported and the previous one stays in use.
+pub async fn watch(rustls_config: RustlsConfig, config: Arc<RwLock<Config>>) {
+	let mut files = TlsFiles::from_config(&config.read().unwrap());
+	let mut modified = files.as_ref().map(|f| f.modified());
+
+	loop {
+		tokio::time::sleep(RELOAD_INTERVAL).await;
+
+		let current = TlsFiles::from_config(&config.read().unwrap());
+		let current_modified = current.as_ref().map(|f| f.modified());
+
+		if current == files && current_modified == modified {
+			continue;
+		}
+
+		files = current;
+		modified = current_modified;
+
+		let Some(tls) = files.clone() else {
+			eprintln!("TLS can not be disabled without a restart, keeping the current certificate");
+			continue;
+		};
+
+		match tokio::task::spawn_blocking(move || tls.server_config()).await {
+			Ok(Ok(server_config)) => {
+				rustls_config.reload_from_config(server_config);
+				println!("TLS certificate reloaded");
+			}
+			Ok(Err(e)) => eprintln!("Failed to reload TLS certificate: {}", e),
+			Err(e) => eprintln!("Failed to reload TLS certificate: {}", e),
+		}
+	}
+}
+
+fn tls_error(path: &Path, error: impl fmt::Display) -> TlsError {
+	TlsError {
+		path: path.to_path_buf(),
+		message: error.to_string(),
+	}
+}
@@ -1,1037 +1,93 @@
-use crate::monitor::Monitor;
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::Duration;
-
-pub fn mega_bits<T: Into<f64>>(bytes: T) -> f64 {
-	(bytes.into() / 1048576.0) * 8.0
-}
-
-pub fn format_bytes_per_sec(bytes: f64) -> String {
-	format!("{:.2}", bytes / (1024.0 * 1024.0))
-}
-
-fn format_bytes_human(bytes: u64) -> String {
-	const KIB: f64 = 1024.0;
-	const MIB: f64 = 1024.0 * 1024.0;
-	const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
-
-	let b = bytes as f64;
-	if b >= GIB {
-		format!("{:.2} GiB", b / GIB)
-	} else if b >= MIB {
-		format!("{:.2} MiB", b / MIB)
-	} else if b >= KIB {
-		format!("{:.2} KiB", b / KIB)
-	} else {
-		format!("{} B", bytes)
-	}
-}
-
-fn parse_labels(labels: &[(&str, &str)]) -> String {
-	if !labels.is_empty() {
-		let label_pairs = labels
-			.iter()
-			.map(|(k, v)| format!("{k}=\"{v}\""))
-			.collect::<Vec<_>>()
-			.join(",");
-		format!("{{{}}}", label_pairs)
-	} else {
-		String::new()
-	}
+use crate::exporter;
+use serde::Serializer;
+use std::io::{self, Read};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Serializes a refresh timestamp as seconds since the Unix epoch.
+pub fn serialize_timestamp<S: Serializer>(
+	timestamp: &Duration,
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	serializer.serialize_f64(timestamp.as_secs_f64())
 }
 
-fn metric_full_name(name: &str, unit: Option<&str>) -> String {
-	if let Some(unit) = unit {
-		format!("{}_{}", name, unit)
-	} else {
-		name.to_string()
+/// Serializes an optional timestamp as seconds since the Unix epoch, or null.
+pub fn serialize_optional_timestamp<S: Serializer>(
+	timestamp: &Option<Duration>,
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	match timestamp {
+		Some(timestamp) => serializer.serialize_f64(timestamp.as_secs_f64()),
+		None => serializer.serialize_none(),
 	}
 }
 
-fn create_metric_header(
-	name: &str,
-	description: &str,
-	metric_type: &str,
-	unit: Option<&str>,
-) -> String {
-	let full_name = format!("rabbit_{}", metric_full_name(name, unit).as_str());
-	let unit_line = if let Some(unit) = unit {
-		format!("# UNIT {} {}\n", full_name, unit)
-	} else {
-		String::new()
-	};
-	format!("# HELP {full_name} {description}\n# TYPE {full_name} {metric_type}\n{unit_line}")
-}
-
-fn create_info_metric(name: &str, description: &str, labels: &[(&str, &str)]) -> String {
-	let header = create_metric_header(name, description, "info", None);
-	let labels_str = parse_labels(labels);
-	let full_name = format!("rabbit_{}", name);
-
-	format!("{header}{full_name}{labels_str} 1\n")
-}
-
-fn create_gauge_metric(
-	name: &str,
-	description: &str,
-	value: &str,
-	unit: Option<&str>,
-	labels: &[(&str, &str)],
-	timestamp: Duration,
-) -> String {
-	let ts = timestamp.as_secs_f64();
-	let header = create_metric_header(name, description, "gauge", unit);
-	let labels_str = parse_labels(labels);
-	let full_name = format!("rabbit_{}", metric_full_name(name, unit));
-
-	format!("{header}{full_name}{labels_str} {value} {ts:.3}\n")
-}
-
-fn create_gauge_metric_line(
-	name: &str,
-	value: &str,
-	unit: Option<&str>,
-	labels: &[(&str, &str)],
-	timestamp: Duration,
-) -> String {
-	let ts = timestamp.as_secs_f64();
-	let label_str = parse_labels(labels);
-	let full_name = format!("rabbit_{}", metric_full_name(name, unit));
-
-	format!("{full_name}{label_str} {value} {ts:.3}\n")
-}
-
-/*
-fn create_counter_metric(name: &str, description: &str, value: &str, unit: Option<&str>, labels: &[(&str, &str)], timestamp: Duration, created: Duration) -> String {
-	let ts = timestamp.as_secs_f64();
-	let cr = created.as_secs_f64();
-	let header = create_metric_header(name, description, "counter", unit);
-	let labels_str = parse_labels(labels);
-
-	format!("{header}rabbit_{name}_total{labels_str} {value} {ts:.3}\nrabbit_{name}_created{labels_str} {cr:.3} {ts:.3}\n")
-}
-*/
-
-fn create_counter_metric_line(
-	name: &str,
-	value: &str,
-	unit: Option<&str>,
-	labels: &[(&str, &str)],
-	timestamp: Duration,
-	created: u64,
-) -> String {
-	let ts = timestamp.as_secs_f64();
-	let cr = created as f64;
-	let labels_str = parse_labels(labels);
-	let full_name = format!("rabbit_{}", metric_full_name(name, unit));
-
-	format!(
-		"{full_name}_total{labels_str} {value} {ts:.3}\n\
-		 {full_name}_created{labels_str} {cr:.3} {ts:.3}\n"
-	)
+pub fn mega_bits<T: Into<f64>>(bytes: T) -> f64 {
+	(bytes.into() / 1048576.0) * 8.0
 }
 
-pub fn create_metrics(monitor: Arc<Mutex<Monitor>>) -> String {
-	let mut metrics: String = String::from("");
+/// Runs a command like `Command::output`, but kills it when it does not exit within
+/// `timeout`, e.g. when `upsc` or `ipmitool` hang on an unresponsive device. Commands
+/// that fail are counted in `rabbit_exporter_subprocess_failures_total`, except for
+/// ones that are not installed, which is how optional tools are detected.
+pub fn command_output(command: &mut Command, timeout: Duration) -> io::Result<Output> {
+	let program = command.get_program().to_string_lossy().to_string();
+	let mut child = match command
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
 	{
-		let temp: MutexGuard<Monitor> = monitor.lock().unwrap();
-
-		metrics += &create_info_metric(
-			"version_info",
-			"Rabbit Monitor version",
-			&[("version", "v10.2.1")],
-		);
-		metrics += &create_info_metric(
-			"system_info",
-			"System information",
-			&[
-				("name", &temp.system_info.name),
-				("kernel_version", &temp.system_info.kernel_version),
-				("os_version", &temp.system_info.os_version),
-				("long_os_version", &temp.system_info.long_os_version),
-				("distribution_id", &temp.system_info.distribution_id),
-				("host_name", &temp.system_info.host_name),
-				("boot_time", &temp.system_info.boot_time.to_string()),
-			],
-		);
-		metrics += &create_info_metric(
-			"cpu_info",
-			"Static CPU information",
-			&[
-				("arch", &temp.processor.arch),
-				("threads", &temp.processor.thread_count.to_string()),
-			],
-		);
-
-		if temp.settings.cpu_details || temp.settings.all_metrics {
-			metrics += &create_gauge_metric(
-				"cpu_load_1min",
-				"CPU load recorded in last minute",
-				&temp.processor.min1.to_string(),
-				None,
-				&[],
-				temp.processor.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"cpu_load_5min",
-				"CPU load recorded in last 5 minutes",
-				&temp.processor.min5.to_string(),
-				None,
-				&[],
-				temp.processor.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"cpu_load_15min",
-				"CPU load recorded in last 15 minutes",
-				&temp.processor.min15.to_string(),
-				None,
-				&[],
-				temp.processor.refreshed,
-			);
-
-			metrics += &create_metric_header(
-				"cpu_thread_usage",
-				"CPU load per thread in percent",
-				"gauge",
-				Some("percent"),
-			);
-			for thead in &temp.processor.threads {
-				metrics += &create_gauge_metric_line(
-					"cpu_thread_usage",
-					&format!("{:.2}", thead.cpu_usage),
-					Some("percent"),
-					&[("name", &thead.name), ("brand", &thead.brand)],
-					temp.processor.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"cpu_thread_frequency",
-				"CPU frequency per thread in hertz",
-				"gauge",
-				Some("hertz"),
-			);
-			for thead in &temp.processor.threads {
-				metrics += &create_gauge_metric_line(
-					"cpu_thread_frequency",
-					&(thead.frequency * 1_000_000).to_string(),
-					Some("hertz"),
-					&[("name", &thead.name), ("brand", &thead.brand)],
-					temp.processor.refreshed,
-				);
-			}
-		}
-
-		if temp.settings.memory_details || temp.settings.all_metrics {
-			metrics += &create_gauge_metric(
-				"memory_total",
-				"Total memory in bytes",
-				&temp.memory.total.to_string(),
-				Some("bytes"),
-				&[],
-				temp.memory.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"memory_available",
-				"Available memory in bytes",
-				&temp.memory.available.to_string(),
-				Some("bytes"),
-				&[],
-				temp.memory.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"memory_used",
-				"Used memory in bytes",
-				&temp.memory.used.to_string(),
-				Some("bytes"),
-				&[],
-				temp.memory.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"memory_free",
-				"Free memory in bytes",
-				&temp.memory.free.to_string(),
-				Some("bytes"),
-				&[],
-				temp.memory.refreshed,
-			);
-		}
-
-		if temp.settings.swap_details || temp.settings.all_metrics {
-			metrics += &create_gauge_metric(
-				"swap_total",
-				"Total swap storage in bytes",
-				&temp.swap.total.to_string(),
-				Some("bytes"),
-				&[],
-				temp.swap.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"swap_used",
-				"Used swap storage in bytes",
-				&temp.swap.used.to_string(),
-				Some("bytes"),
-				&[],
-				temp.swap.refreshed,
-			);
-			metrics += &create_gauge_metric(
-				"swap_free",
-				"Free swap storage in bytes",
-				&temp.swap.free.to_string(),
-				Some("bytes"),
-				&[],
-				temp.swap.refreshed,
-			);
-		}
-
-		metrics += &create_gauge_metric(
-			"cpu_load",
-			"CPU load in percent",
-			&format!("{:.2}", temp.processor.percent),
-			Some("percent"),
-			&[],
-			temp.processor.refreshed,
-		);
-		metrics += &create_gauge_metric(
-			"memory",
-			"Used memory in percent",
-			&format!("{:.2}", temp.memory.percent),
-			Some("percent"),
-			&[],
-			temp.memory.refreshed,
-		);
-		metrics += &create_gauge_metric(
-			"swap",
-			"Used swap storage in percent",
-			&format!("{:.2}", temp.swap.percent),
-			Some("percent"),
-			&[],
-			temp.swap.refreshed,
-		);
-
-		if temp.settings.energy.enabled {
-			let energy = temp.energy.lock().unwrap();
-			metrics += &create_gauge_metric(
-				"power_consumption",
-				"Power consumption in watts",
-				&format!("{:.2}", energy.power_consumption),
-				Some("watts"),
-				&[],
-				energy.refreshed,
-			);
-		}
-
-		if !temp.storage_devices.is_empty() {
-			metrics += &create_metric_header(
-				"storage",
-				"Used storage in percent",
-				"gauge",
-				Some("percent"),
-			);
-			for (device, storage) in &temp.storage_devices {
-				metrics += &create_gauge_metric_line(
-					"storage",
-					&storage.percent.to_string(),
-					Some("percent"),
-					&[("device", device), ("mount", &storage.mount_point)],
-					storage.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"storage_read_speed",
-				"Disk read speed in bytes/sec",
-				"gauge",
-				Some("bytes_per_second"),
-			);
-			for (device, storage) in &temp.storage_devices {
-				metrics += &create_gauge_metric_line(
-					"storage_read_speed",
-					&storage.read_speed.to_string(),
-					Some("bytes_per_second"),
-					&[("device", device), ("mount", &storage.mount_point)],
-					storage.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"storage_write_speed",
-				"Disk write speed in bytes/sec",
-				"gauge",
-				Some("bytes_per_second"),
-			);
-			for (device, storage) in &temp.storage_devices {
-				metrics += &create_gauge_metric_line(
-					"storage_write_speed",
-					&storage.write_speed.to_string(),
-					Some("bytes_per_second"),
-					&[("device", device), ("mount", &storage.mount_point)],
-					storage.refreshed,
-				);
-			}
-
-			if temp.settings.storage_details || temp.settings.all_metrics {
-				metrics += &create_metric_header(
-					"storage_used",
-					"Used storage in bytes",
-					"gauge",
-					Some("bytes"),
-				);
-				for (device, storage) in &temp.storage_devices {
-					metrics += &create_gauge_metric_line(
-						"storage_used",
-						&storage.used.to_string(),
-						Some("bytes"),
-						&[("device", device), ("mount", &storage.mount_point)],
-						storage.refreshed,
-					);
-				}
-
-				metrics += &create_metric_header(
-					"storage_free",
-					"Free storage in bytes",
-					"gauge",
-					Some("bytes"),
-				);
-				for (device, storage) in &temp.storage_devices {
-					metrics += &create_gauge_metric_line(
-						"storage_free",
-						&storage.free.to_string(),
-						Some("bytes"),
-						&[("device", device), ("mount", &storage.mount_point)],
-						storage.refreshed,
-					);
-				}
-
-				metrics += &create_metric_header(
-					"storage_total",
-					"Total storage in bytes",
-					"gauge",
-					Some("bytes"),
-				);
-				for (device, storage) in &temp.storage_devices {
-					metrics += &create_gauge_metric_line(
-						"storage_total",
-						&storage.total.to_string(),
-						Some("bytes"),
-						&[("device", device), ("mount", &storage.mount_point)],
-						storage.refreshed,
-					);
-				}
-			}
-		}
-
-		if !temp.network_interfaces.is_empty() {
-			metrics += &create_metric_header(
-				"network_download_speed",
-				"Download speed in bytes/sec",
-				"gauge",
-				Some("bytes_per_second"),
-			);
-			for (iface, network) in &temp.network_interfaces {
-				metrics += &create_gauge_metric_line(
-					"network_download_speed",
-					&network.download.to_string(),
-					Some("bytes_per_second"),
-					&[("interface", iface)],
-					network.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"network_upload_speed",
-				"Upload speed in bytes/sec",
-				"gauge",
-				Some("bytes_per_second"),
-			);
-			for (iface, network) in &temp.network_interfaces {
-				metrics += &create_gauge_metric_line(
-					"network_upload_speed",
-					&network.upload.to_string(),
-					Some("bytes_per_second"),
-					&[("interface", iface)],
-					network.refreshed,
-				);
-			}
-
-			if temp.settings.network_details || temp.settings.all_metrics {
-				metrics += &create_metric_header(
-					"network_packets_received",
-					"Total number of incoming packets",
-					"counter",
-					None,
-				);
-				for (iface, network) in &temp.network_interfaces {
-					metrics += &create_counter_metric_line(
-						"network_packets_received",
-						&network.total_packets_received.to_string(),
-						None,
-						&[("interface", iface)],
-						network.refreshed,
-						temp.system_info.boot_time,
-					);
-				}
-
-				metrics += &create_metric_header(
-					"network_packets_transmitted",
-					"Total number of outcoming packets",
-					"counter",
-					None,
-				);
-				for (iface, network) in &temp.network_interfaces {
-					metrics += &create_counter_metric_line(
-						"network_packets_transmitted",
-						&network.total_packets_transmitted.to_string(),
-						None,
-						&[("interface", iface)],
-						network.refreshed,
-						temp.system_info.boot_time,
-					);
-				}
-
-				metrics += &create_metric_header(
-					"network_errors_received",
-					"Total number of incoming errors",
-					"counter",
-					None,
-				);
-				for (iface, network) in &temp.network_interfaces {
-					metrics += &create_counter_metric_line(
-						"network_errors_received",
-						&network.total_errors_on_received.to_string(),
-						None,
-						&[("interface", iface)],
-						network.refreshed,
-						temp.system_info.boot_time,
-					);
-				}
-
-				metrics += &create_metric_header(
-					"network_errors_transmitted",
-					"Total number of outcoming errors",
-					"counter",
-					None,
-				);
-				for (iface, network) in &temp.network_interfaces {
-					metrics += &create_counter_metric_line(
-						"network_errors_transmitted",
-						&network.total_errors_on_transmitted.to_string(),
-						None,
-						&[("interface", iface)],
-						network.refreshed,
-						temp.system_info.boot_time,
-					);
-				}
+		Ok(child) => child,
+		Err(e) => {
+			if e.kind() != io::ErrorKind::NotFound {
+				exporter::record_subprocess_failure(&program, "spawn");
 			}
+			return Err(e);
 		}
+	};
 
-		if !temp.component_list.is_empty() {
-			metrics += &create_metric_header(
-				"hardware_component_temperature",
-				"Temperature of hardware components in celsius",
-				"gauge",
-				Some("celsius"),
-			);
-			for (label, component) in &temp.component_list {
-				metrics += &create_gauge_metric_line(
-					"hardware_component_temperature",
-					&component.temperature.unwrap_or(0.0).to_string(),
-					Some("celsius"),
-					&[("component", label)],
-					component.refreshed,
-				);
-			}
-		}
-
-		if !temp.upses.is_empty() {
-			metrics += &create_metric_header(
-				"ups_charge",
-				"UPS battery charge level",
-				"gauge",
-				Some("percent"),
-			);
-			for (ups_name, ups) in &temp.upses {
-				metrics += &create_gauge_metric_line(
-					"ups_charge",
-					&format!("{:.2}", ups.charge_percent),
-					Some("percent"),
-					&[
-						("ups", ups_name),
-						("manufacturer", &ups.manufacturer),
-						("model", &ups.model),
-					],
-					ups.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header("ups_load", "UPS load level", "gauge", Some("percent"));
-			for (ups_name, ups) in &temp.upses {
-				metrics += &create_gauge_metric_line(
-					"ups_load",
-					&format!("{:.2}", ups.load_percent),
-					Some("percent"),
-					&[
-						("ups", ups_name),
-						("manufacturer", &ups.manufacturer),
-						("model", &ups.model),
-					],
-					ups.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"ups_runtime",
-				"Estimated UPS runtime on battery",
-				"gauge",
-				Some("seconds"),
-			);
-			for (ups_name, ups) in &temp.upses {
-				metrics += &create_gauge_metric_line(
-					"ups_runtime",
-					&ups.runtime_seconds.to_string(),
-					Some("seconds"),
-					&[
-						("ups", ups_name),
-						("manufacturer", &ups.manufacturer),
-						("model", &ups.model),
-					],
-					ups.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"ups_input_voltage",
-				"UPS input voltage",
-				"gauge",
-				Some("volts"),
-			);
-			for (ups_name, ups) in &temp.upses {
-				metrics += &create_gauge_metric_line(
-					"ups_input_voltage",
-					&format!("{:.2}", ups.input_voltage),
-					Some("volts"),
-					&[
-						("ups", ups_name),
-						("manufacturer", &ups.manufacturer),
-						("model", &ups.model),
-					],
-					ups.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"ups_output_voltage",
-				"UPS output voltage",
-				"gauge",
-				Some("volts"),
-			);
-			for (ups_name, ups) in &temp.upses {
-				metrics += &create_gauge_metric_line(
-					"ups_output_voltage",
-					&format!("{:.2}", ups.output_voltage),
-					Some("volts"),
-					&[
-						("ups", ups_name),
-						("manufacturer", &ups.manufacturer),
-						("model", &ups.model),
-					],
-					ups.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header("ups_status_info", "UPS operational status", "info", None);
-			for (ups_name, ups) in &temp.upses {
-				metrics += &format!("rabbit_ups_status_info{{ups=\"{}\",manufacturer=\"{}\",model=\"{}\",status=\"{}\"}} 1 {:.3}\n", ups_name, ups.manufacturer, ups.model, ups.status, ups.refreshed.as_secs_f64());
-			}
-
-			if temp.upses.values().any(|ups| ups.real_power_nominal > 0.0) {
-				metrics += &create_metric_header(
-					"ups_power_usage",
-					"UPS power usage in watts",
-					"gauge",
-					Some("watts"),
-				);
-				for (ups_name, ups) in &temp.upses {
-					if ups.real_power_nominal > 0.0 {
-						metrics += &create_gauge_metric_line(
-							"ups_power_usage",
-							&format!("{:.2}", ups.power_usage),
-							Some("watts"),
-							&[
-								("ups", ups_name),
-								("manufacturer", &ups.manufacturer),
-								("model", &ups.model),
-							],
-							ups.refreshed,
-						);
-					}
-				}
-			}
-		}
-
-		if !temp.docker_containers.is_empty() {
-			metrics += &create_metric_header(
-				"docker_cpu_usage",
-				"Docker container CPU usage in percent",
-				"gauge",
-				Some("percent"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_cpu_usage",
-					&format!("{:.2}", container.cpu_percent),
-					Some("percent"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_memory",
-				"Docker container memory usage in percent",
-				"gauge",
-				Some("percent"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_memory",
-					&format!("{:.2}", container.memory_percent),
-					Some("percent"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_memory_usage",
-				"Docker container memory usage in bytes",
-				"gauge",
-				Some("bytes"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_memory_usage",
-					&container.memory_usage.to_string(),
-					Some("bytes"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_memory_limit",
-				"Docker container memory limit in bytes",
-				"gauge",
-				Some("bytes"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_memory_limit",
-					&container.memory_limit.to_string(),
-					Some("bytes"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_network_download_speed",
-				"Docker container download speed in bytes/sec",
-				"gauge",
-				Some("bytes_per_second"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_network_download_speed",
-					&container.download.to_string(),
-					Some("bytes_per_second"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_network_upload_speed",
-				"Docker container upload speed in bytes/sec",
-				"gauge",
-				Some("bytes_per_second"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_network_upload_speed",
-					&container.upload.to_string(),
-					Some("bytes_per_second"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_network_packets_received",
-				"Docker container total number of incoming packets",
-				"counter",
-				None,
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_counter_metric_line(
-					"docker_network_packets_received",
-					&container.total_packets_received.to_string(),
-					None,
-					&[("container", name)],
-					container.refreshed,
-					temp.system_info.boot_time,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_network_packets_transmitted",
-				"Docker container total number of outgoing packets",
-				"counter",
-				None,
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_counter_metric_line(
-					"docker_network_packets_transmitted",
-					&container.total_packets_transmitted.to_string(),
-					None,
-					&[("container", name)],
-					container.refreshed,
-					temp.system_info.boot_time,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_network_errors_received",
-				"Docker container total number of incoming errors",
-				"counter",
-				None,
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_counter_metric_line(
-					"docker_network_errors_received",
-					&container.total_errors_on_received.to_string(),
-					None,
-					&[("container", name)],
-					container.refreshed,
-					temp.system_info.boot_time,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_network_errors_transmitted",
-				"Docker container total number of outgoing errors",
-				"counter",
-				None,
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_counter_metric_line(
-					"docker_network_errors_transmitted",
-					&container.total_errors_on_transmitted.to_string(),
-					None,
-					&[("container", name)],
-					container.refreshed,
-					temp.system_info.boot_time,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_block_read",
-				"Docker container block read in bytes",
-				"gauge",
-				Some("bytes"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_block_read",
-					&container.block_read_bytes.to_string(),
-					Some("bytes"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_block_write",
-				"Docker container block write in bytes",
-				"gauge",
-				Some("bytes"),
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_block_write",
-					&container.block_write_bytes.to_string(),
-					Some("bytes"),
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"docker_pids",
-				"Docker container number of PIDs",
-				"gauge",
-				None,
-			);
-			for (name, container) in &temp.docker_containers {
-				metrics += &create_gauge_metric_line(
-					"docker_pids",
-					&container.pids.to_string(),
-					None,
-					&[("container", name)],
-					container.refreshed,
-				);
-			}
-		}
-
-		if !temp.process_list.is_empty() {
-			metrics += &create_metric_header(
-				"process_cpu_usage",
-				"CPU usage of the monitored process",
-				"gauge",
-				None,
-			);
-			for process in temp.process_list.values() {
-				metrics += &create_gauge_metric_line(
-					"process_cpu_usage",
-					&format!("{:.2}", process.cpu),
-					None,
-					&[("pid", &process.pid.to_string()), ("name", &process.name)],
-					process.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"process_memory_usage",
-				"Memory usage of the monitored process",
-				"gauge",
-				Some("bytes"),
-			);
-			for process in temp.process_list.values() {
-				metrics += &create_gauge_metric_line(
-					"process_memory_usage",
-					&process.memory.to_string(),
-					Some("bytes"),
-					&[("pid", &process.pid.to_string()), ("name", &process.name)],
-					process.refreshed,
-				);
-			}
-
-			metrics += &create_metric_header(
-				"process_virtual_memory_usage",
-				"Virtual memory usage of the monitored process",
-				"gauge",
-				Some("bytes"),
-			);
-			for process in temp.process_list.values() {
-				metrics += &create_gauge_metric_line(
-					"process_virtual_memory_usage",
-					&process.virtual_memory.to_string(),
-					Some("bytes"),
-					&[("pid", &process.pid.to_string()), ("name", &process.name)],
-					process.refreshed,
-				);
-			}
+	// Read both pipes while waiting, a child blocked on a full pipe would never exit
+	let mut stdout = child.stdout.take().unwrap();
+	let mut stderr = child.stderr.take().unwrap();
+	let stdout_reader = thread::spawn(move || {
+		let mut buffer = Vec::new();
+		let _ = stdout.read_to_end(&mut buffer);
+		buffer
+	});
+	let stderr_reader = thread::spawn(move || {
+		let mut buffer = Vec::new();
+		let _ = stderr.read_to_end(&mut buffer);
+		buffer
+	});
+
+	let deadline = Instant::now() + timeout;
+	let status = loop {
+		if let Some(status) = child.try_wait()? {
+			break status;
 		}
-	}
-	metrics += "# EOF\n";
-	metrics
-}
-
-pub fn main_page(monitor: Arc<Mutex<Monitor>>) -> String {
-	let temp: MutexGuard<Monitor> = monitor.lock().unwrap();
-
-	let mut html = format!(
-		r#"<!DOCTYPE html>
-<html>
-<head>
-	<title>Rabbit Monitor</title>
-	<meta http-equiv='refresh' content='{}'>
-</head>
-<body>
-	<style>
-		td, th {{
-			border-bottom: 1px solid #000;
-			border-right: 1px solid #000;
-			text-align: center;
-			padding: 8px;
-		}}
-	</style>
-	<h1>Rabbit Monitor</h1>
-	<b>Version:</b> v10.2.1</br>
-	<b>Fetch every:</b> {} seconds</br></br>
-	<table>
-	<tr><th>CPU Load</th><td>{:.2}%</td></tr>
-	<tr><th>RAM Usage</th><td>{:.2}%</td></tr>
-	<tr><th>Swap Usage</th><td>{:.2}%</td></tr>"#,
-		temp.settings.cache,
-		temp.settings.cache,
-		temp.processor.percent,
-		temp.memory.percent,
-		temp.swap.percent
-	);
-
-	// Add all disks
-	html += r#"<tr><th colspan="2">Storage Devices</th></tr>"#;
-	for (name, disk) in &temp.storage_devices {
-		html += &format!(
-			r#"<tr><th>{} ({})</th><td>{:.2}% used — ↓ {} MB/s / ↑ {} MB/s</td></tr>"#,
-			name,
-			disk.mount_point,
-			disk.percent,
-			format_bytes_per_sec(disk.read_speed),
-			format_bytes_per_sec(disk.write_speed),
-		);
-	}
 
-	// Add all network interfaces
-	html += r#"<tr><th colspan="2">Network Interfaces</th></tr>"#;
-	for (name, iface) in &temp.network_interfaces {
-		html += &format!(
-			r#"<tr><th>{}</th><td>↓ {:.2} Mbps / ↑ {:.2} Mbps</td></tr>"#,
-			name, iface.download, iface.upload
-		);
-	}
-
-	// Add all components
-	html += r#"<tr><th colspan="2">Components</th></tr>"#;
-	for (name, component) in &temp.component_list {
-		html += &format!(
-			r#"<tr><th>{}</th><td>{:.2} °C</td></tr>"#,
-			name,
-			component.temperature.unwrap_or(0.0),
-		);
-	}
-
-	// Add Docker containers
-	if !temp.docker_containers.is_empty() {
-		html += r#"<tr><th colspan="2">Docker Containers</th></tr>"#;
-		for (name, container) in &temp.docker_containers {
-			html += &format!(
-				r#"<tr><th>{}</th><td>CPU: {:.2}% — RAM: {:.2}% ({} / {}) — ↓ {:.2} Mbps / ↑ {:.2} Mbps — PIDs: {}</td></tr>"#,
-				name,
-				container.cpu_percent,
-				container.memory_percent,
-				format_bytes_human(container.memory_usage),
-				format_bytes_human(container.memory_limit),
-				container.download,
-				container.upload,
-				container.pids,
-			);
+		if Instant::now() >= deadline {
+			let _ = child.kill();
+			let _ = child.wait();
+			exporter::record_subprocess_failure(&program, "timeout");
+			return Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				format!("killed after {}s", timeout.as_secs()),
+			));
 		}
-	}
+		thread::sleep(Duration::from_millis(20));
+	};
 
-	html += r#"<tr><th colspan="2">Processes</th></tr>"#;
-	for process in temp.process_list.values() {
-		html += &format!(
-			r#"<tr><th>{}</th><td>{:.0}</td></tr>"#,
-			process.name, process.cpu,
-		);
+	if !status.success() {
+		exporter::record_subprocess_failure(&program, "exit");
 	}
 
-	html += r#"
-	</table>
-	</body>
-</html>"#;
-
-	html
+	Ok(Output {
+		status,
+		stdout: stdout_reader.join().unwrap_or_default(),
+		stderr: stderr_reader.join().unwrap_or_default(),
+	})
 }